@@ -0,0 +1,87 @@
+use crate::ray::Ray;
+use crate::vec3::{Point, Vec3};
+
+/// Axis aligned bounding box
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    min: Point,
+    max: Point,
+}
+
+impl Aabb {
+    pub fn new(a: Point, b: Point) -> Self {
+        Self {
+            min: a.min(b),
+            max: a.max(b),
+        }
+    }
+
+    /// A box containing nothing, which any union replaces
+    pub fn empty() -> Self {
+        Self {
+            min: Point::new_singleton(f32::INFINITY),
+            max: Point::new_singleton(f32::NEG_INFINITY),
+        }
+    }
+
+    /// Get the box's min corner.
+    pub fn min(&self) -> Point {
+        self.min
+    }
+
+    /// Get the box's max corner.
+    pub fn max(&self) -> Point {
+        self.max
+    }
+
+    pub fn union(self, other: Aabb) -> Aabb {
+        Self {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    pub fn grow(self, p: Point) -> Aabb {
+        Self {
+            min: self.min.min(p),
+            max: self.max.max(p),
+        }
+    }
+
+    pub fn centroid(&self) -> Point {
+        0.5 * (self.min + self.max)
+    }
+
+    pub fn extent(&self) -> Vec3 {
+        self.max - self.min
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        let d = self.extent();
+        match d.x() < 0.0 {
+            true => 0.0,
+            false => 2.0 * (d.x() * d.y() + d.y() * d.z() + d.z() * d.x()),
+        }
+    }
+
+    /// Slab test. `inv_dir` is the reciprocal of the ray's direction, computed once per
+    /// ray since every node of a hierarchy needs it.
+    pub fn hit(&self, r: Ray, inv_dir: Vec3, tmin: f32, tmax: f32) -> bool {
+        let (mut tmin, mut tmax) = (tmin, tmax);
+        for axis in 0..3 {
+            let t0 = (self.min[axis] - r.orig()[axis]) * inv_dir[axis];
+            let t1 = (self.max[axis] - r.orig()[axis]) * inv_dir[axis];
+            let (near, far) = match inv_dir[axis] < 0.0 {
+                true => (t1, t0),
+                false => (t0, t1),
+            };
+            // Written so a NaN from 0 * infinity leaves the interval alone
+            tmin = if near > tmin { near } else { tmin };
+            tmax = if far < tmax { far } else { tmax };
+            if tmax < tmin {
+                return false;
+            }
+        }
+        true
+    }
+}
@@ -0,0 +1,457 @@
+//! Bounding volume hierarchy over a scene's primitives.
+//!
+//! Nodes live in one flat array with each node's first child directly after it, and are
+//! split where the binned surface area heuristic predicts the cheapest traversal.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::aabb::Aabb;
+use crate::hittable::{HitRecord, Hittable, HittableObject};
+use crate::instance::Instance;
+use crate::ray::Ray;
+use crate::vec3::Vec3;
+
+/// Relative costs of stepping through a node and of testing a primitive
+const TRAVERSAL_COST: f32 = 1.0;
+const INTERSECTION_COST: f32 = 1.0;
+const BINS: usize = 16;
+/// Leaves are allowed to grow this large when splitting does not pay off
+const MAX_LEAF_SIZE: usize = 8;
+/// Nodes this deep are always leaves, so traversal's stack has a fixed size
+const MAX_DEPTH: usize = 63;
+
+#[derive(Clone, Debug, PartialEq)]
+enum NodeKind {
+    Leaf {
+        first: usize,
+        count: usize,
+    },
+    /// The first child follows the node, `second` is the other's index
+    Interior {
+        second: usize,
+        axis: usize,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct BvhNode {
+    bbox: Aabb,
+    kind: NodeKind,
+}
+
+/// What building the hierarchy produced and how long it took
+#[derive(Clone, Debug, PartialEq)]
+pub struct BvhStats {
+    pub primitives: usize,
+    pub nodes: usize,
+    pub leaves: usize,
+    pub max_depth: usize,
+    pub max_leaf_size: usize,
+    /// Expected cost of a ray through the tree, in primitive intersections
+    pub sah_cost: f32,
+    pub build_time: Duration,
+}
+
+impl fmt::Display for BvhStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "bvh: {} primitives in {} nodes ({} leaves, at most {} primitives each), \
+             depth {}, SAH cost {:.2}, built in {:.2?}",
+            self.primitives,
+            self.nodes,
+            self.leaves,
+            self.max_leaf_size,
+            self.max_depth,
+            self.sah_cost,
+            self.build_time
+        )
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Bvh {
+    nodes: Vec<BvhNode>,
+    /// Primitives in leaf order
+    objects: Vec<HittableObject>,
+    /// Anything without a bounding box, tested against every ray
+    unbounded: Vec<HittableObject>,
+    stats: BvhStats,
+}
+
+/// A primitive waiting to be placed, with its box and centroid cached
+struct BuildItem {
+    object: HittableObject,
+    bbox: Aabb,
+    centroid: Vec3,
+}
+
+/// Objects shared by instances, by address, each with the hierarchy built over it.
+/// Holding the original keeps its address from being reused while building.
+type Shared = HashMap<*const HittableObject, (Arc<HittableObject>, Arc<HittableObject>)>;
+
+fn flatten(
+    object: HittableObject,
+    bounded: &mut Vec<BuildItem>,
+    unbounded: &mut Vec<HittableObject>,
+    shared: &mut Shared,
+) {
+    match object {
+        HittableObject::HittableList(objects) => objects
+            .into_iter()
+            .for_each(|object| flatten(object, bounded, unbounded, shared)),
+        HittableObject::Bvh(bvh) => bvh
+            .objects
+            .into_iter()
+            .chain(bvh.unbounded)
+            .for_each(|object| flatten(object, bounded, unbounded, shared)),
+        HittableObject::Mesh(mesh) => (0..mesh.triangles().len()).for_each(|i| {
+            flatten(
+                HittableObject::MeshTriangle(mesh.clone(), i),
+                bounded,
+                unbounded,
+                shared,
+            )
+        }),
+        // An instance stays one primitive, over a hierarchy of its own that is built
+        // once however many instances share the object
+        HittableObject::Instance(instance)
+            if !matches!(**instance.object(), HittableObject::Bvh(_)) =>
+        {
+            let original = instance.object().clone();
+            let key = Arc::as_ptr(&original);
+            let object = match shared.get(&key) {
+                Some((_, built)) => built.clone(),
+                None => {
+                    let bvh = Bvh::with_shared((*original).clone(), shared);
+                    let built = Arc::new(HittableObject::Bvh(Box::new(bvh)));
+                    shared.insert(key, (original, built.clone()));
+                    built
+                }
+            };
+            let instance = Instance::new(object, *instance.transform());
+            flatten(
+                HittableObject::Instance(Box::new(instance)),
+                bounded,
+                unbounded,
+                shared,
+            )
+        }
+        object => match object.bounding_box() {
+            Some(bbox) => bounded.push(BuildItem {
+                object,
+                bbox,
+                centroid: bbox.centroid(),
+            }),
+            None => unbounded.push(object),
+        },
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Bin {
+    bbox: Aabb,
+    count: usize,
+}
+
+/// The cheapest split of `items` as (cost, axis, bin boundary), if any axis has extent
+fn best_split(items: &[BuildItem], centroids: Aabb) -> Option<(f32, usize, usize)> {
+    let mut best: Option<(f32, usize, usize)> = None;
+    for axis in 0..3 {
+        let (lo, extent) = (centroids.min()[axis], centroids.extent()[axis]);
+        if extent <= 0.0 {
+            continue;
+        }
+        let mut bins = [Bin {
+            bbox: Aabb::empty(),
+            count: 0,
+        }; BINS];
+        for item in items {
+            let b = bin_index(item.centroid[axis], lo, extent);
+            bins[b].bbox = bins[b].bbox.union(item.bbox);
+            bins[b].count += 1;
+        }
+        // Sweep from the right to get the area and count of everything past each boundary
+        let mut right = [(0.0, 0); BINS];
+        let (mut bbox, mut count) = (Aabb::empty(), 0);
+        for b in (1..BINS).rev() {
+            bbox = bbox.union(bins[b].bbox);
+            count += bins[b].count;
+            right[b] = (bbox.surface_area(), count);
+        }
+        let (mut bbox, mut count) = (Aabb::empty(), 0);
+        for b in 1..BINS {
+            bbox = bbox.union(bins[b - 1].bbox);
+            count += bins[b - 1].count;
+            let (right_area, right_count) = right[b];
+            if count == 0 || right_count == 0 {
+                continue;
+            }
+            let cost = bbox.surface_area() * count as f32 + right_area * right_count as f32;
+            let better = match best {
+                Some((c, _, _)) => cost < c,
+                None => true,
+            };
+            if better {
+                best = Some((cost, axis, b));
+            }
+        }
+    }
+    best
+}
+
+fn bin_index(c: f32, lo: f32, extent: f32) -> usize {
+    (((c - lo) / extent * BINS as f32) as usize).min(BINS - 1)
+}
+
+/// Move the items for which `left` holds to the front, returning how many there are
+fn partition<F: Fn(&BuildItem) -> bool>(items: &mut [BuildItem], left: F) -> usize {
+    let mut boundary = 0;
+    for i in 0..items.len() {
+        if left(&items[i]) {
+            items.swap(i, boundary);
+            boundary += 1;
+        }
+    }
+    boundary
+}
+
+impl Bvh {
+    /// Build a hierarchy over every primitive in `world`, flattening any lists
+    pub fn new(world: HittableObject) -> Self {
+        Self::with_shared(world, &mut HashMap::new())
+    }
+
+    fn with_shared(world: HittableObject, shared: &mut Shared) -> Self {
+        let start = Instant::now();
+        let mut items = Vec::new();
+        let mut unbounded = Vec::new();
+        flatten(world, &mut items, &mut unbounded, shared);
+        let mut bvh = Self {
+            nodes: Vec::with_capacity(2 * items.len()),
+            objects: Vec::with_capacity(items.len()),
+            unbounded,
+            stats: BvhStats {
+                primitives: items.len(),
+                nodes: 0,
+                leaves: 0,
+                max_depth: 0,
+                max_leaf_size: 0,
+                sah_cost: 0.0,
+                build_time: Duration::default(),
+            },
+        };
+        if !items.is_empty() {
+            bvh.build(&mut items, 0, 0);
+            let root_area = bvh.nodes[0].bbox.surface_area();
+            bvh.stats.sah_cost = bvh
+                .nodes
+                .iter()
+                .map(|node| {
+                    let area = match root_area > 0.0 {
+                        true => node.bbox.surface_area() / root_area,
+                        false => 1.0,
+                    };
+                    match node.kind {
+                        NodeKind::Leaf { count, .. } => area * count as f32 * INTERSECTION_COST,
+                        NodeKind::Interior { .. } => area * TRAVERSAL_COST,
+                    }
+                })
+                .sum();
+        }
+        // Leaves index into `objects` in the order the build placed them
+        bvh.objects = items.into_iter().map(|item| item.object).collect();
+        bvh.stats.nodes = bvh.nodes.len();
+        bvh.stats.build_time = start.elapsed();
+        bvh
+    }
+
+    /// Add the node for `items`, which start at `offset` in the final object order,
+    /// and everything below it
+    fn build(&mut self, items: &mut [BuildItem], offset: usize, depth: usize) {
+        let bbox = items
+            .iter()
+            .fold(Aabb::empty(), |b, item| b.union(item.bbox));
+        let centroids = items
+            .iter()
+            .fold(Aabb::empty(), |b, item| b.grow(item.centroid));
+        self.stats.max_depth = self.stats.max_depth.max(depth);
+        let index = self.nodes.len();
+        let leaf_cost = items.len() as f32 * INTERSECTION_COST;
+        let area = bbox.surface_area().max(f32::MIN_POSITIVE);
+        let split = match items.len() {
+            1 => None,
+            _ => best_split(items, centroids),
+        };
+        let split = match split {
+            _ if depth == MAX_DEPTH => None,
+            Some((cost, axis, boundary))
+                if TRAVERSAL_COST + INTERSECTION_COST * cost / area < leaf_cost
+                    || items.len() > MAX_LEAF_SIZE =>
+            {
+                let (lo, extent) = (centroids.min()[axis], centroids.extent()[axis]);
+                let mid = partition(items, |item| {
+                    bin_index(item.centroid[axis], lo, extent) < boundary
+                });
+                Some((axis, mid))
+            }
+            // Identical centroids cannot be told apart, so just halve the list
+            None if items.len() > MAX_LEAF_SIZE => Some((0, items.len() / 2)),
+            _ => None,
+        };
+        match split {
+            None => {
+                self.stats.leaves += 1;
+                self.stats.max_leaf_size = self.stats.max_leaf_size.max(items.len());
+                self.nodes.push(BvhNode {
+                    bbox,
+                    kind: NodeKind::Leaf {
+                        first: offset,
+                        count: items.len(),
+                    },
+                });
+            }
+            Some((axis, mid)) => {
+                self.nodes.push(BvhNode {
+                    bbox,
+                    kind: NodeKind::Interior { second: 0, axis },
+                });
+                let (left, right) = items.split_at_mut(mid);
+                self.build(left, offset, depth + 1);
+                let second = self.nodes.len();
+                self.nodes[index].kind = NodeKind::Interior { second, axis };
+                self.build(right, offset + mid, depth + 1);
+            }
+        }
+    }
+
+    /// Get the bvh's build statistics.
+    pub fn stats(&self) -> &BvhStats {
+        &self.stats
+    }
+
+    /// Get the bvh's primitives, bounded ones first.
+    pub fn objects(&self) -> impl Iterator<Item = &HittableObject> {
+        self.objects.iter().chain(self.unbounded.iter())
+    }
+}
+
+impl Hittable for Bvh {
+    fn hit(&self, r: Ray, tmin: f32, tmax: f32) -> Option<HitRecord<'_>> {
+        let mut closest =
+            self.unbounded
+                .iter()
+                .fold(None, |best: Option<HitRecord<'_>>, object| {
+                    let tmax = best.as_ref().map_or(tmax, |hit| hit.t());
+                    object.hit(r, tmin, tmax).or(best)
+                });
+        if self.nodes.is_empty() {
+            return closest;
+        }
+        let dir = r.dir();
+        let inv_dir = Vec3::new(1.0 / dir.x(), 1.0 / dir.y(), 1.0 / dir.z());
+        // Each level leaves at most one sibling waiting, plus the node being visited
+        let (mut stack, mut len) = ([0; MAX_DEPTH + 1], 1);
+        while len > 0 {
+            len -= 1;
+            let index = stack[len];
+            let node = &self.nodes[index];
+            let tmax = closest.as_ref().map_or(tmax, |hit| hit.t());
+            if !node.bbox.hit(r, inv_dir, tmin, tmax) {
+                continue;
+            }
+            match node.kind {
+                NodeKind::Leaf { first, count } => {
+                    for object in &self.objects[first..first + count] {
+                        let tmax = closest.as_ref().map_or(tmax, |hit| hit.t());
+                        if let Some(hit) = object.hit(r, tmin, tmax) {
+                            closest = Some(hit);
+                        }
+                    }
+                }
+                // Visit the child nearer the ray's origin first, so its hits can cull
+                // the other one
+                NodeKind::Interior { second, axis } => {
+                    let (near, far) = match dir[axis] < 0.0 {
+                        true => (second, index + 1),
+                        false => (index + 1, second),
+                    };
+                    stack[len] = far;
+                    stack[len + 1] = near;
+                    len += 2;
+                }
+            }
+        }
+        closest
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        match (self.nodes.first(), self.unbounded.is_empty()) {
+            (Some(root), true) => Some(root.bbox),
+            _ => None,
+        }
+    }
+}
+
+#[test]
+fn test_bvh_matches_list() {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use crate::material::MaterialType;
+    use crate::utils::random_range;
+    use crate::vec3::Point;
+
+    let mut rng = StdRng::seed_from_u64(7);
+    let material = MaterialType::Lambertian(Vec3::new_singleton(0.5).into());
+    let spheres = (0..200)
+        .map(|_| {
+            let center = Vec3::random_vec3_range(-10.0, 10.0, &mut rng);
+            let radius = random_range(0.1, 1.0, &mut rng);
+            HittableObject::Sphere(center, radius, material.clone())
+        })
+        .collect::<Vec<_>>();
+    let list = HittableObject::HittableList(spheres);
+    let bvh = Bvh::new(list.clone());
+    assert_eq!(bvh.stats().primitives, 200);
+    assert_eq!(bvh.stats().nodes, 2 * bvh.stats().leaves - 1);
+    assert!(bvh.stats().sah_cost < 200.0);
+
+    for _ in 0..500 {
+        let orig = Point::random_vec3_range(-15.0, 15.0, &mut rng);
+        let dir = Vec3::random_unit_vector(&mut rng);
+        let r = Ray::new(orig, dir);
+        let expected = list.hit(r, 0.001, f32::INFINITY).map(|hit| hit.t());
+        assert_eq!(
+            bvh.hit(r, 0.001, f32::INFINITY).map(|hit| hit.t()),
+            expected
+        );
+    }
+}
+
+#[test]
+fn test_bvh_depth_limit() {
+    use crate::material::MaterialType;
+    use crate::vec3::Point;
+
+    // Nodes at the limit are leaves however many primitives they hold, so a build
+    // started just above it stops after one split
+    let material = MaterialType::Lambertian(Vec3::new_singleton(0.5).into());
+    let spheres = (0..100)
+        .map(|i| HittableObject::Sphere(Point::new(i as f32, 0.0, 0.0), 0.5, material.clone()))
+        .collect::<Vec<_>>();
+    let mut items = Vec::new();
+    flatten(
+        HittableObject::HittableList(spheres),
+        &mut items,
+        &mut Vec::new(),
+        &mut HashMap::new(),
+    );
+    let mut bvh = Bvh::new(HittableObject::HittableList(Vec::new()));
+    bvh.build(&mut items, 0, MAX_DEPTH - 1);
+    assert_eq!(bvh.stats().max_depth, MAX_DEPTH);
+    assert_eq!((bvh.nodes.len(), bvh.stats().leaves), (3, 2));
+}
@@ -6,6 +6,69 @@ use crate::{
     vec3::{Point, Vec3},
 };
 
+/// Everything needed to place a camera, independent of the image it renders to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CameraSettings {
+    pub lookfrom: Point,
+    pub lookat: Point,
+    pub vup: Vec3,
+    pub vfov: f32,
+    pub aperture: f32,
+    pub focus_dist: f32,
+}
+
+impl CameraSettings {
+    /// Pinhole camera at the origin looking down -z, as in the first images of the book
+    pub fn new_dfl() -> Self {
+        Self {
+            lookfrom: Point::new_dfl(),
+            lookat: Point::new(0., 0., -1.),
+            vup: Vec3::new(0., 1., 0.),
+            vfov: 90.,
+            aperture: 0.,
+            focus_dist: 1.,
+        }
+    }
+    pub fn new_debug() -> Self {
+        Self {
+            lookfrom: Point::new(0., 0., 2.),
+            lookat: Point::new(0., 0., 1.),
+            ..Self::new_dfl()
+        }
+    }
+    pub fn new_random() -> Self {
+        Self {
+            lookfrom: Point::new(13., 2., 3.),
+            lookat: Point::new_dfl(),
+            vup: Vec3::new(0., 1., 0.),
+            vfov: 20.,
+            aperture: 0.1,
+            focus_dist: 10.,
+        }
+    }
+    pub fn new_cornell() -> Self {
+        Self {
+            lookfrom: Point::new(278., 278., -800.),
+            lookat: Point::new(278., 278., 0.),
+            vup: Vec3::new(0., 1., 0.),
+            vfov: 40.,
+            aperture: 0.,
+            focus_dist: 10.,
+        }
+    }
+    pub fn build(&self, aspect_ratio: f32) -> Camera {
+        Camera::new(
+            self.lookfrom,
+            self.lookat,
+            self.vup,
+            self.vfov,
+            aspect_ratio,
+            self.aperture,
+            self.focus_dist,
+        )
+    }
+}
+
 pub struct Camera {
     origin: Point,
     horizontal: Vec3,
@@ -13,7 +76,6 @@ pub struct Camera {
     lower_left_corner: Point,
     u: Vec3,
     v: Vec3,
-    w: Vec3,
     lens_radius: f32,
 }
 
@@ -48,70 +110,9 @@ impl Camera {
             lower_left_corner,
             u,
             v,
-            w,
             lens_radius,
         }
     }
-    pub fn new_dfl(aspect_ratio: f32) -> Self {
-        let viewport_height = 2.;
-        let viewport_width = aspect_ratio * viewport_height;
-        let focal_length = 1.;
-        let origin = Point::new_dfl();
-        let horizontal = Vec3::new(viewport_width, 0., 0.);
-        let vertical = Vec3::new(0., viewport_height, 0.);
-        let lower_left_corner =
-            origin - horizontal / 2. - vertical / 2. - Vec3::new(0., 0., focal_length);
-        let w = Vec3::new(0., 0., -1.);
-        let u = Vec3::new(-1., 0., 0.);
-        let v = w.cross(u);
-
-        Self {
-            origin,
-            horizontal,
-            vertical,
-            lower_left_corner,
-            u,
-            v,
-            w,
-            lens_radius: (0.0),
-        }
-    }
-    pub fn new_debug(aspect_ratio: f32) -> Self {
-        let viewport_height = 2.;
-        let viewport_width = aspect_ratio * viewport_height;
-        let focal_length = 1.;
-        let origin = Point::new(0., 0., 2.);
-        let horizontal = Vec3::new(viewport_width, 0., 0.);
-        let vertical = Vec3::new(0., viewport_height, 0.);
-        let lower_left_corner =
-            origin - horizontal / 2. - vertical / 2. - Vec3::new(0., 0., focal_length);
-        let w = Vec3::new(0., 0., -1.);
-        let u = Vec3::new(-1., 0., 0.);
-        let v = w.cross(u);
-
-        Self {
-            origin,
-            horizontal,
-            vertical,
-            lower_left_corner,
-            u,
-            v,
-            w,
-            lens_radius: (0.0),
-        }
-    }
-
-    pub fn new_random(aspect_ratio: f32) -> Self {
-        Self::new(
-            Point::new(13., 2., 3.),
-            Point::new_dfl(),
-            Vec3::new(0., 1., 0.),
-            20.,
-            aspect_ratio,
-            0.1,
-            10.,
-        )
-    }
 
     /// Get a reference to the camera's origin.
     pub fn origin(&self) -> &Point {
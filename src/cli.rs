@@ -0,0 +1,654 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use crate::camera::CameraSettings;
+use crate::compare::DEFAULT_PIXELS_PER_DEGREE;
+use crate::image::OutputOptions;
+use crate::render::{Integrator, RenderSettings};
+use crate::scene::Scene;
+
+pub const USAGE: &str = "\
+Usage: raytracer_weekend [OPTIONS]
+       raytracer_weekend convert [OUTPUT OPTIONS] <INPUT> <OUTPUT>
+       raytracer_weekend compare [COMPARE OPTIONS] <REFERENCE> <TEST>
+
+Render options:
+  -w, --width <PIXELS>       image width (default 240)
+      --height <PIXELS>      image height (default: width / aspect)
+  -a, --aspect <RATIO>       aspect ratio as a number or W:H / W/H (default 16:9)
+  -s, --spp <N>              samples per pixel (default 25)
+  -d, --max-depth <N>        maximum bounces per ray (default 20)
+      --seed <N>             seed for scene generation (default 0)
+      --scene <NAME>         random | img11 | debug | cornell (default random)
+  -f, --scene-file <PATH>    load the scene from a scene file instead
+      --camera <NAME>        random | default | debug | cornell (default: from
+                             the scene)
+  -o, --output <PATH>        write the image to PATH instead of stdout, in the format
+                             given by its extension: .ppm, .png, .hdr or .pfm
+      --export-scene <PATH>  save the scene as rendered to a scene file
+  -j, --threads <N>          number of render threads (default: all cores)
+      --accel <NAME>         bvh | none, to test every object (default bvh)
+      --stats                print how the BVH was built to stderr
+      --integrator <NAME>    rgb | spectral, to follow a few wavelengths of light per
+                             path (default rgb)
+  -h, --help                 print this message
+
+Options given on the command line take precedence over the scene file.
+
+Output options:
+      --ppm <FORMAT>         ascii (P3) | binary (P6) (default ascii)
+      --png <FORMAT>         rgb8 | rgb16 | rgba8 | rgba16 (default rgb8)
+      --tonemap <NAME>       clamp | reinhard | reinhard-extended | aces | hable
+                             (default clamp)
+      --exposure <STOPS>     brighten or darken before tone mapping (default 0)
+      --white-point <L>      luminance that becomes white for reinhard-extended
+                             (default 4)
+      --gamma <CURVE>        srgb, or a number for a plain power curve (default srgb)
+      --color-size <N>       levels per channel in PPM files, 2 to 65536 (default 256)
+      --dither <NAME>        none | ordered | blue-noise (default none)
+
+The convert command reads a .hdr or .pfm image and writes it out in another format.
+
+Compare options:
+      --diff <PATH>          write a heatmap of the perceptual difference to PATH
+      --ppd <N>              pixels per degree of viewing angle for the heatmap
+                             (default 67)
+
+The compare command reports per channel RMSE, PSNR and SSIM between two .ppm or .png
+images, and the mean of a FLIP style perceptual error.
+";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ScenePreset {
+    Random,
+    Img11,
+    Debug,
+    Cornell,
+}
+
+impl FromStr for ScenePreset {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "random" => Ok(ScenePreset::Random),
+            "img11" => Ok(ScenePreset::Img11),
+            "debug" => Ok(ScenePreset::Debug),
+            "cornell" => Ok(ScenePreset::Cornell),
+            _ => Err("expected one of random, img11, debug, cornell".to_string()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CameraPreset {
+    Random,
+    Default,
+    Debug,
+    Cornell,
+}
+
+impl FromStr for CameraPreset {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "random" => Ok(CameraPreset::Random),
+            "default" => Ok(CameraPreset::Default),
+            "debug" => Ok(CameraPreset::Debug),
+            "cornell" => Ok(CameraPreset::Cornell),
+            _ => Err("expected one of random, default, debug, cornell".to_string()),
+        }
+    }
+}
+
+impl CameraPreset {
+    pub fn settings(self) -> CameraSettings {
+        match self {
+            CameraPreset::Random => CameraSettings::new_random(),
+            CameraPreset::Default => CameraSettings::new_dfl(),
+            CameraPreset::Debug => CameraSettings::new_debug(),
+            CameraPreset::Cornell => CameraSettings::new_cornell(),
+        }
+    }
+}
+
+/// How rays find the objects they hit
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Accelerator {
+    /// Test every object
+    None,
+    Bvh,
+}
+
+impl FromStr for Accelerator {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Accelerator::None),
+            "bvh" => Ok(Accelerator::Bvh),
+            _ => Err("expected none or bvh".to_string()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SceneSource {
+    Preset(ScenePreset),
+    File(PathBuf),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    pub width: Option<usize>,
+    pub height: Option<usize>,
+    pub aspect: Option<f32>,
+    pub samples_per_pixel: Option<usize>,
+    pub max_depth: Option<usize>,
+    pub seed: u64,
+    pub scene: SceneSource,
+    pub camera: Option<CameraPreset>,
+    pub output: Option<PathBuf>,
+    pub output_options: OutputOptions,
+    pub export_scene: Option<PathBuf>,
+    pub threads: Option<usize>,
+    pub accelerator: Accelerator,
+    pub integrator: Integrator,
+    /// Report how the scene's acceleration structure was built
+    pub stats: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConvertConfig {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    pub output_options: OutputOptions,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CompareConfig {
+    pub reference: PathBuf,
+    pub test: PathBuf,
+    pub diff: Option<PathBuf>,
+    pub pixels_per_degree: f32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    Render(Config),
+    Convert(ConvertConfig),
+    Compare(CompareConfig),
+}
+
+impl Config {
+    /// Combine the command line with the scene's own settings, falling back to defaults.
+    pub fn render_settings(&self, scene: &Scene) -> Result<RenderSettings, CliError> {
+        const DEFAULT_WIDTH: usize = 240;
+        const DEFAULT_ASPECT: f32 = 16. / 9.;
+        let width = self.width.or(scene.width).unwrap_or(DEFAULT_WIDTH);
+        let height = match (self.height, self.aspect, scene.width, scene.height) {
+            (Some(_), Some(_), _, _) => {
+                return Err(CliError::Conflict(
+                    "--height and --aspect cannot both be given".to_string(),
+                ))
+            }
+            (Some(height), None, _, _) => height,
+            (None, Some(aspect), _, _) => (width as f32 / aspect) as usize,
+            // Keep the scene's aspect ratio when only the width is overridden
+            (None, None, Some(w), Some(h)) => (width as f32 * h as f32 / w as f32) as usize,
+            (None, None, None, Some(h)) => h,
+            (None, None, _, None) => (width as f32 / DEFAULT_ASPECT) as usize,
+        };
+        if height < 2 {
+            return Err(CliError::Conflict(format!(
+                "a width of {} gives an image less than 2 pixels tall",
+                width
+            )));
+        }
+        Ok(RenderSettings {
+            width,
+            height,
+            samples_per_pixel: self
+                .samples_per_pixel
+                .or(scene.samples_per_pixel)
+                .unwrap_or(25),
+            max_depth: self.max_depth.or(scene.max_depth).unwrap_or(20),
+            integrator: self.integrator,
+        })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CliError {
+    HelpRequested,
+    UnknownFlag(String),
+    UnexpectedArgument(String),
+    MissingArgument(String),
+    MissingValue(String),
+    InvalidValue {
+        flag: String,
+        value: String,
+        reason: String,
+    },
+    Conflict(String),
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::HelpRequested => write!(f, "help requested"),
+            CliError::UnknownFlag(flag) => write!(f, "unknown option '{}'", flag),
+            CliError::UnexpectedArgument(arg) => write!(f, "unexpected argument '{}'", arg),
+            CliError::MissingArgument(name) => write!(f, "missing {} argument", name),
+            CliError::MissingValue(flag) => write!(f, "option '{}' needs a value", flag),
+            CliError::InvalidValue {
+                flag,
+                value,
+                reason,
+            } => write!(f, "invalid value '{}' for '{}': {}", value, flag, reason),
+            CliError::Conflict(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+fn parse_value<T: FromStr>(flag: &str, value: &str) -> Result<T, CliError>
+where
+    T::Err: fmt::Display,
+{
+    value.parse::<T>().map_err(|e| CliError::InvalidValue {
+        flag: flag.to_string(),
+        value: value.to_string(),
+        reason: e.to_string(),
+    })
+}
+
+fn invalid_value(flag: &str, value: &str, reason: &str) -> CliError {
+    CliError::InvalidValue {
+        flag: flag.to_string(),
+        value: value.to_string(),
+        reason: reason.to_string(),
+    }
+}
+
+fn parse_positive(flag: &str, value: &str, min: usize) -> Result<usize, CliError> {
+    match parse_value::<usize>(flag, value)? {
+        n if n < min => Err(invalid_value(
+            flag,
+            value,
+            &format!("must be at least {}", min),
+        )),
+        n => Ok(n),
+    }
+}
+
+fn parse_finite(flag: &str, value: &str) -> Result<f32, CliError> {
+    match parse_value::<f32>(flag, value)? {
+        x if x.is_finite() => Ok(x),
+        _ => Err(invalid_value(flag, value, "must be a finite number")),
+    }
+}
+
+/// Accepts a plain number or a ratio written as `16:9` or `16/9`.
+fn parse_aspect(flag: &str, value: &str) -> Result<f32, CliError> {
+    let ratio = match value.split_once([':', '/']) {
+        Some((w, h)) => parse_value::<f32>(flag, w)? / parse_value::<f32>(flag, h)?,
+        None => parse_value::<f32>(flag, value)?,
+    };
+    match ratio.is_finite() && ratio > 0.0 {
+        true => Ok(ratio),
+        false => Err(invalid_value(
+            flag,
+            value,
+            "must be a positive, finite ratio",
+        )),
+    }
+}
+
+enum Arg {
+    Flag(String, String),
+    /// A flag that takes no value
+    Switch(String),
+    Positional(String),
+}
+
+/// Flags that are given alone, without a value
+const SWITCHES: [&str; 1] = ["--stats"];
+
+/// Take the next option and its value, given either as `--flag value` or `--flag=value`
+fn next_arg<I: Iterator<Item = String>>(args: &mut I) -> Option<Result<Arg, CliError>> {
+    let arg = args.next()?;
+    if !arg.starts_with('-') {
+        return Some(Ok(Arg::Positional(arg)));
+    }
+    let (flag, inline_value) = match arg.split_once('=') {
+        Some((flag, value)) if flag.starts_with("--") => {
+            (flag.to_string(), Some(value.to_string()))
+        }
+        _ => (arg, None),
+    };
+    if flag == "-h" || flag == "--help" {
+        return Some(Err(CliError::HelpRequested));
+    }
+    if SWITCHES.contains(&flag.as_str()) {
+        return Some(match inline_value {
+            Some(value) => Err(invalid_value(&flag, &value, "takes no value")),
+            None => Ok(Arg::Switch(flag)),
+        });
+    }
+    Some(match inline_value.or_else(|| args.next()) {
+        Some(value) => Ok(Arg::Flag(flag, value)),
+        None => Err(CliError::MissingValue(flag)),
+    })
+}
+
+/// Apply an option shared by every command that writes images, returning false if
+/// `flag` is not one of them
+fn parse_output_option(
+    options: &mut OutputOptions,
+    flag: &str,
+    value: &str,
+) -> Result<bool, CliError> {
+    match flag {
+        "--ppm" => options.ppm = parse_value(flag, value)?,
+        "--png" => options.png = parse_value(flag, value)?,
+        "--tonemap" => options.tone_mapping.operator = parse_value(flag, value)?,
+        "--exposure" => options.tone_mapping.exposure = parse_finite(flag, value)?,
+        "--white-point" => match parse_finite(flag, value)? {
+            w if w > 0.0 => options.tone_mapping.white_point = w,
+            _ => return Err(invalid_value(flag, value, "must be greater than 0")),
+        },
+        "--gamma" => options.tone_mapping.transfer = parse_value(flag, value)?,
+        "--color-size" => match parse_positive(flag, value, 2)? {
+            n if n <= 65536 => options.color_size = Some(n),
+            _ => return Err(invalid_value(flag, value, "must be at most 65536")),
+        },
+        "--dither" => options.dither = parse_value(flag, value)?,
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+fn parse_render<I: Iterator<Item = String>>(mut args: I) -> Result<Config, CliError> {
+    let mut config = Config {
+        width: None,
+        height: None,
+        aspect: None,
+        samples_per_pixel: None,
+        max_depth: None,
+        seed: 0,
+        scene: SceneSource::Preset(ScenePreset::Random),
+        camera: None,
+        output: None,
+        output_options: OutputOptions::default(),
+        export_scene: None,
+        threads: None,
+        accelerator: Accelerator::Bvh,
+        integrator: Integrator::Rgb,
+        stats: false,
+    };
+    let mut scene_flag: Option<String> = None;
+
+    while let Some(arg) = next_arg(&mut args) {
+        let (flag, value) = match arg? {
+            Arg::Flag(flag, value) => (flag, value),
+            Arg::Switch(flag) if flag == "--stats" => {
+                config.stats = true;
+                continue;
+            }
+            Arg::Switch(flag) => return Err(CliError::UnknownFlag(flag)),
+            Arg::Positional(arg) => return Err(CliError::UnexpectedArgument(arg)),
+        };
+        if parse_output_option(&mut config.output_options, &flag, &value)? {
+            continue;
+        }
+        match flag.as_str() {
+            "-w" | "--width" => config.width = Some(parse_positive(&flag, &value, 2)?),
+            "--height" => config.height = Some(parse_positive(&flag, &value, 2)?),
+            "-a" | "--aspect" => config.aspect = Some(parse_aspect(&flag, &value)?),
+            "-s" | "--spp" => config.samples_per_pixel = Some(parse_positive(&flag, &value, 1)?),
+            "-d" | "--max-depth" => config.max_depth = Some(parse_positive(&flag, &value, 1)?),
+            "--seed" => config.seed = parse_value(&flag, &value)?,
+            "--scene" | "-f" | "--scene-file" => {
+                if let Some(prev) = scene_flag.replace(flag.clone()) {
+                    return Err(CliError::Conflict(format!(
+                        "{} and {} cannot both be given",
+                        prev, flag
+                    )));
+                }
+                config.scene = match flag.as_str() {
+                    "--scene" => SceneSource::Preset(parse_value(&flag, &value)?),
+                    _ => SceneSource::File(PathBuf::from(value)),
+                }
+            }
+            "--camera" => config.camera = Some(parse_value(&flag, &value)?),
+            "-o" | "--output" => config.output = Some(PathBuf::from(value)),
+            "--export-scene" => config.export_scene = Some(PathBuf::from(value)),
+            "-j" | "--threads" => config.threads = Some(parse_positive(&flag, &value, 1)?),
+            "--accel" => config.accelerator = parse_value(&flag, &value)?,
+            "--integrator" => config.integrator = parse_value(&flag, &value)?,
+            _ => return Err(CliError::UnknownFlag(flag)),
+        }
+    }
+    Ok(config)
+}
+
+fn parse_convert<I: Iterator<Item = String>>(mut args: I) -> Result<ConvertConfig, CliError> {
+    let mut output_options = OutputOptions::default();
+    let mut paths = Vec::new();
+    while let Some(arg) = next_arg(&mut args) {
+        match arg? {
+            Arg::Positional(path) if paths.len() < 2 => paths.push(PathBuf::from(path)),
+            Arg::Positional(arg) => return Err(CliError::UnexpectedArgument(arg)),
+            Arg::Flag(flag, value) => {
+                if !parse_output_option(&mut output_options, &flag, &value)? {
+                    return Err(CliError::UnknownFlag(flag));
+                }
+            }
+            Arg::Switch(flag) => return Err(CliError::UnknownFlag(flag)),
+        }
+    }
+    let mut paths = paths.into_iter();
+    Ok(ConvertConfig {
+        input: paths
+            .next()
+            .ok_or_else(|| CliError::MissingArgument("INPUT".to_string()))?,
+        output: paths
+            .next()
+            .ok_or_else(|| CliError::MissingArgument("OUTPUT".to_string()))?,
+        output_options,
+    })
+}
+
+fn parse_compare<I: Iterator<Item = String>>(mut args: I) -> Result<CompareConfig, CliError> {
+    let mut diff = None;
+    let mut pixels_per_degree = DEFAULT_PIXELS_PER_DEGREE;
+    let mut paths = Vec::new();
+    while let Some(arg) = next_arg(&mut args) {
+        match arg? {
+            Arg::Positional(path) if paths.len() < 2 => paths.push(PathBuf::from(path)),
+            Arg::Positional(arg) => return Err(CliError::UnexpectedArgument(arg)),
+            Arg::Switch(flag) => return Err(CliError::UnknownFlag(flag)),
+            Arg::Flag(flag, value) => match flag.as_str() {
+                "--diff" => diff = Some(PathBuf::from(value)),
+                "--ppd" => match parse_finite(&flag, &value)? {
+                    ppd if ppd > 0.0 => pixels_per_degree = ppd,
+                    _ => return Err(invalid_value(&flag, &value, "must be greater than 0")),
+                },
+                _ => return Err(CliError::UnknownFlag(flag)),
+            },
+        }
+    }
+    let mut paths = paths.into_iter();
+    Ok(CompareConfig {
+        reference: paths
+            .next()
+            .ok_or_else(|| CliError::MissingArgument("REFERENCE".to_string()))?,
+        test: paths
+            .next()
+            .ok_or_else(|| CliError::MissingArgument("TEST".to_string()))?,
+        diff,
+        pixels_per_degree,
+    })
+}
+
+/// Parse the arguments following the program name.
+pub fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<Command, CliError> {
+    let mut args = args.into_iter().peekable();
+    match args.peek().map(String::as_str) {
+        Some("convert") => parse_convert(args.skip(1)).map(Command::Convert),
+        Some("compare") => parse_compare(args.skip(1)).map(Command::Compare),
+        _ => parse_render(args).map(Command::Render),
+    }
+}
+
+#[test]
+fn test_parse_args() {
+    let scene = empty_scene();
+    let config = render_config("").unwrap();
+    let settings = config.render_settings(&scene).unwrap();
+    assert_eq!((settings.width, settings.height), (240, 135));
+    assert_eq!(config.camera, None);
+
+    let config = render_config("-w 400 --aspect=16:10 --scene img11 -o out.ppm").unwrap();
+    let settings = config.render_settings(&scene).unwrap();
+    assert_eq!((settings.width, settings.height), (400, 250));
+    assert_eq!(config.scene, SceneSource::Preset(ScenePreset::Img11));
+    assert_eq!(config.output, Some(PathBuf::from("out.ppm")));
+
+    assert_eq!(
+        render_config("--spp"),
+        Err(CliError::MissingValue("--spp".to_string()))
+    );
+    assert!(matches!(
+        render_config("--spp 0"),
+        Err(CliError::InvalidValue { .. })
+    ));
+    assert!(matches!(
+        render_config("--height 100 --aspect 2")
+            .unwrap()
+            .render_settings(&scene),
+        Err(CliError::Conflict(_))
+    ));
+}
+
+#[test]
+fn test_scene_file_args() {
+    // The scene file's settings fill in whatever the command line leaves out
+    let mut scene = empty_scene();
+    scene.width = Some(400);
+    scene.height = Some(300);
+    scene.samples_per_pixel = Some(7);
+    let config = render_config("-w 200 -f a.scene").unwrap();
+    let settings = config.render_settings(&scene).unwrap();
+    assert_eq!((settings.width, settings.height), (200, 150));
+    assert_eq!(settings.samples_per_pixel, 7);
+
+    assert!(matches!(
+        render_config("--scene img11 -f a.scene"),
+        Err(CliError::Conflict(_))
+    ));
+}
+
+#[test]
+fn test_convert_args() {
+    assert!(matches!(
+        parse_args(args("convert --png rgb16 in.hdr out.png")),
+        Ok(Command::Convert(ConvertConfig { output_options, .. })) if output_options.png.sixteen_bit
+    ));
+
+    assert_eq!(
+        parse_args(args("convert in.hdr")),
+        Err(CliError::MissingArgument("OUTPUT".to_string()))
+    );
+}
+
+#[test]
+fn test_tone_mapping_args() {
+    use crate::tonemap::{ToneOperator, Transfer};
+
+    let options = render_config("--tonemap aces --exposure -1.5 --gamma 2.2")
+        .unwrap()
+        .output_options
+        .tone_mapping;
+    assert_eq!(options.operator, ToneOperator::Aces);
+    assert_eq!(options.exposure, -1.5);
+    assert_eq!(options.transfer, Transfer::Gamma(2.2));
+
+    assert!(matches!(
+        render_config("--white-point -1"),
+        Err(CliError::InvalidValue { .. })
+    ));
+}
+
+#[test]
+fn test_color_size_arg() {
+    assert!(matches!(
+        render_config("--color-size 65537"),
+        Err(CliError::InvalidValue { .. })
+    ));
+}
+
+#[test]
+fn test_compare_args() {
+    assert_eq!(
+        parse_args(args("compare dump1.ppm dump2.ppm --diff diff.png")),
+        Ok(Command::Compare(CompareConfig {
+            reference: PathBuf::from("dump1.ppm"),
+            test: PathBuf::from("dump2.ppm"),
+            diff: Some(PathBuf::from("diff.png")),
+            pixels_per_degree: DEFAULT_PIXELS_PER_DEGREE,
+        }))
+    );
+}
+
+#[test]
+fn test_integrator_arg() {
+    let scene = empty_scene();
+    let settings = render_config("").unwrap().render_settings(&scene).unwrap();
+    assert_eq!(settings.integrator, Integrator::Rgb);
+    let config = render_config("--integrator spectral").unwrap();
+    let settings = config.render_settings(&scene).unwrap();
+    assert_eq!(settings.integrator, Integrator::Spectral);
+    assert!(matches!(
+        render_config("--integrator photon"),
+        Err(CliError::InvalidValue { .. })
+    ));
+}
+
+#[test]
+fn test_stats_switch() {
+    // Stats stay quiet unless asked for, and the switch takes no value
+    assert!(!render_config("-w 100").unwrap().stats);
+    let config = render_config("--stats -w 100").unwrap();
+    assert!(config.stats && config.width == Some(100));
+    assert!(matches!(
+        render_config("--stats=yes"),
+        Err(CliError::InvalidValue { .. })
+    ));
+    assert_eq!(
+        parse_args(args("convert --stats in.hdr out.png")),
+        Err(CliError::UnknownFlag("--stats".to_string()))
+    );
+}
+
+#[cfg(test)]
+fn args(s: &str) -> Vec<String> {
+    s.split_whitespace().map(String::from).collect()
+}
+
+/// Parse `s`, which must be the arguments of a render
+#[cfg(test)]
+fn render_config(s: &str) -> Result<Config, CliError> {
+    match parse_args(args(s))? {
+        Command::Render(config) => Ok(config),
+        _ => panic!("expected a render command"),
+    }
+}
+
+/// A scene that leaves every render setting to the command line
+#[cfg(test)]
+fn empty_scene() -> Scene {
+    Scene::new(
+        crate::hittable::HittableObject::HittableList(Vec::new()),
+        CameraSettings::new_dfl(),
+    )
+}
@@ -0,0 +1,393 @@
+//! Measure how far an image is from a reference: per channel RMSE, PSNR and SSIM, plus a
+//! per pixel perceptual error in the spirit of NVIDIA's FLIP that can be drawn as a
+//! heatmap.
+//!
+//! The FLIP style error follows the paper's outline: both images are blurred by
+//! approximations of the eye's contrast sensitivity, compared with the HyAB colour
+//! distance, and the result is boosted where edges or points differ. Hunt adjustment and
+//! the error redistribution are included; the exact filters are simplified.
+
+use crate::framebuffer::Framebuffer;
+use crate::quantize::LdrImage;
+use crate::tonemap::Transfer;
+use crate::vec3::Color;
+
+/// Pixels per degree of visual angle: a 4K monitor 0.7m away, as FLIP assumes by default
+pub const DEFAULT_PIXELS_PER_DEGREE: f32 = 67.0;
+
+const RGB_TO_XYZ: [[f32; 3]; 3] = [
+    [0.4124, 0.3576, 0.1805],
+    [0.2126, 0.7152, 0.0722],
+    [0.0193, 0.1192, 0.9505],
+];
+const XYZ_TO_RGB: [[f32; 3]; 3] = [
+    [3.2406, -1.5372, -0.4986],
+    [-0.9689, 1.8758, 0.0415],
+    [0.0557, -0.2040, 1.0570],
+];
+
+/// Matplotlib's magma colour map at evenly spaced points, sRGB encoded
+const MAGMA: [[f32; 3]; 9] = [
+    [0.001, 0.000, 0.014],
+    [0.113, 0.065, 0.277],
+    [0.317, 0.072, 0.485],
+    [0.513, 0.126, 0.507],
+    [0.716, 0.215, 0.475],
+    [0.902, 0.320, 0.390],
+    [0.987, 0.536, 0.383],
+    [0.996, 0.764, 0.531],
+    [0.987, 0.991, 0.750],
+];
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Comparison {
+    pub width: usize,
+    pub height: usize,
+    pub rmse: [f32; 3],
+    /// Infinite for identical channels
+    pub psnr: [f32; 3],
+    pub ssim: [f32; 3],
+    /// Per pixel FLIP style error in [0, 1], rows top first
+    pub error_map: Vec<f32>,
+}
+
+impl Comparison {
+    pub fn identical(&self) -> bool {
+        self.rmse.iter().all(|&e| e == 0.0)
+    }
+
+    pub fn mean_error(&self) -> f32 {
+        self.error_map.iter().sum::<f32>() / self.error_map.len() as f32
+    }
+
+    /// The error map coloured with magma, stored linear so it survives the usual
+    /// sRGB encoding on the way out
+    pub fn heatmap(&self) -> Framebuffer {
+        let pixels = self
+            .error_map
+            .iter()
+            .map(|&e| {
+                let t = e.clamp(0.0, 1.0) * (MAGMA.len() - 1) as f32;
+                let i = (t as usize).min(MAGMA.len() - 2);
+                let f = t - i as f32;
+                let channel = |c: usize| {
+                    let v = (1.0 - f) * MAGMA[i][c] + f * MAGMA[i + 1][c];
+                    Transfer::Srgb.decode(v)
+                };
+                Color::new(channel(0), channel(1), channel(2))
+            })
+            .collect();
+        Framebuffer::from_pixels(self.width, self.height, pixels)
+    }
+}
+
+fn transform(m: &[[f32; 3]; 3], c: Color) -> Color {
+    let row = |r: &[f32; 3]| r[0] * c.x() + r[1] * c.y() + r[2] * c.z();
+    Color::new(row(&m[0]), row(&m[1]), row(&m[2]))
+}
+
+fn white_xyz() -> Color {
+    transform(&RGB_TO_XYZ, Color::new_singleton(1.0))
+}
+
+fn rgb_to_ycxcz(c: Color) -> Color {
+    let xyz = transform(&RGB_TO_XYZ, c);
+    let white = white_xyz();
+    let (x, y, z) = (
+        xyz.x() / white.x(),
+        xyz.y() / white.y(),
+        xyz.z() / white.z(),
+    );
+    Color::new(116.0 * y - 16.0, 500.0 * (x - y), 200.0 * (y - z))
+}
+
+fn ycxcz_to_rgb(c: Color) -> Color {
+    let y = (c.x() + 16.0) / 116.0;
+    let (x, z) = (y + c.y() / 500.0, y - c.z() / 200.0);
+    let white = white_xyz();
+    let xyz = Color::new(x * white.x(), y * white.y(), z * white.z());
+    transform(&XYZ_TO_RGB, xyz)
+}
+
+fn lab_f(t: f32) -> f32 {
+    const DELTA: f32 = 6.0 / 29.0;
+    match t > DELTA * DELTA * DELTA {
+        true => t.cbrt(),
+        false => t / (3.0 * DELTA * DELTA) + 4.0 / 29.0,
+    }
+}
+
+/// CIELAB from linear RGB, with FLIP's Hunt adjustment scaling chroma by lightness
+fn rgb_to_hunt_lab(c: Color) -> Color {
+    let xyz = transform(&RGB_TO_XYZ, c);
+    let white = white_xyz();
+    let (fx, fy, fz) = (
+        lab_f(xyz.x() / white.x()),
+        lab_f(xyz.y() / white.y()),
+        lab_f(xyz.z() / white.z()),
+    );
+    let l = 116.0 * fy - 16.0;
+    let hunt = 0.01 * l;
+    Color::new(l, hunt * 500.0 * (fx - fy), hunt * 200.0 * (fy - fz))
+}
+
+/// Lightness difference plus Euclidean chroma difference
+fn hyab(a: Color, b: Color) -> f32 {
+    let d = a - b;
+    d.x().abs() + (d.y() * d.y() + d.z() * d.z()).sqrt()
+}
+
+/// Filter with `kx` along rows then `ky` along columns, clamping reads to the edges.
+/// Both kernels have odd length and are centred.
+fn separable(plane: &[f32], width: usize, height: usize, kx: &[f32], ky: &[f32]) -> Vec<f32> {
+    let pass = |input: &[f32], kernel: &[f32], horizontal: bool| -> Vec<f32> {
+        let r = (kernel.len() / 2) as isize;
+        (0..width * height)
+            .map(|i| {
+                let (x, y) = ((i % width) as isize, (i / width) as isize);
+                kernel
+                    .iter()
+                    .enumerate()
+                    .map(|(k, w)| {
+                        let o = k as isize - r;
+                        let (sx, sy) = match horizontal {
+                            true => ((x + o).clamp(0, width as isize - 1), y),
+                            false => (x, (y + o).clamp(0, height as isize - 1)),
+                        };
+                        w * input[sy as usize * width + sx as usize]
+                    })
+                    .sum()
+            })
+            .collect()
+    };
+    pass(&pass(plane, kx, true), ky, false)
+}
+
+/// Samples of `f` at -r..=r, for a radius covering three standard deviations
+fn kernel<F: Fn(f32) -> f32>(sigma: f32, f: F) -> Vec<f32> {
+    let r = (3.0 * sigma).ceil().max(1.0) as isize;
+    (-r..=r).map(|x| f(x as f32)).collect()
+}
+
+fn gaussian(sigma: f32) -> Vec<f32> {
+    let k = kernel(sigma, |x| (-x * x / (2.0 * sigma * sigma)).exp());
+    let sum: f32 = k.iter().sum();
+    k.iter().map(|w| w / sum).collect()
+}
+
+/// Scale positive and negative weights separately so each side sums to one
+fn balance(k: Vec<f32>) -> Vec<f32> {
+    let positive: f32 = k.iter().filter(|&&w| w > 0.0).sum();
+    let negative: f32 = -k.iter().filter(|&&w| w < 0.0).sum::<f32>();
+    k.iter()
+        .map(|&w| match w > 0.0 {
+            true => w / positive,
+            false => w / negative,
+        })
+        .collect()
+}
+
+fn blur(plane: &[f32], width: usize, height: usize, sigma: f32) -> Vec<f32> {
+    let g = gaussian(sigma);
+    separable(plane, width, height, &g, &g)
+}
+
+/// Mean structural similarity over Gaussian windows, for one channel in [0, 1]
+fn ssim(a: &[f32], b: &[f32], width: usize, height: usize) -> f32 {
+    const C1: f32 = 0.01 * 0.01;
+    const C2: f32 = 0.03 * 0.03;
+    let local = |p: Vec<f32>| blur(&p, width, height, 1.5);
+    let mu_a = local(a.to_vec());
+    let mu_b = local(b.to_vec());
+    let aa = local(a.iter().map(|x| x * x).collect());
+    let bb = local(b.iter().map(|x| x * x).collect());
+    let ab = local(a.iter().zip(b).map(|(x, y)| x * y).collect());
+    let total: f32 = (0..a.len())
+        .map(|i| {
+            let (ma, mb) = (mu_a[i], mu_b[i]);
+            let var_a = aa[i] - ma * ma;
+            let var_b = bb[i] - mb * mb;
+            let cov = ab[i] - ma * mb;
+            ((2.0 * ma * mb + C1) * (2.0 * cov + C2))
+                / ((ma * ma + mb * mb + C1) * (var_a + var_b + C2))
+        })
+        .sum();
+    total / a.len() as f32
+}
+
+/// Split linear colours into three planes
+fn planes(pixels: &[Color]) -> [Vec<f32>; 3] {
+    [
+        pixels.iter().map(|c| c.x()).collect(),
+        pixels.iter().map(|c| c.y()).collect(),
+        pixels.iter().map(|c| c.z()).collect(),
+    ]
+}
+
+/// Edge and point strength of a normalized lightness plane
+fn features(lightness: &[f32], width: usize, height: usize, ppd: f32) -> (Vec<f32>, Vec<f32>) {
+    let sigma = 0.5 * 0.082 * ppd;
+    let g = gaussian(sigma);
+    let s2 = sigma * sigma;
+    let edge = balance(kernel(sigma, |x| -x * (-x * x / (2.0 * s2)).exp()));
+    let point = balance(kernel(sigma, |x| {
+        (x * x / s2 - 1.0) * (-x * x / (2.0 * s2)).exp()
+    }));
+    let magnitude = |k: &[f32]| -> Vec<f32> {
+        let dx = separable(lightness, width, height, k, &g);
+        let dy = separable(lightness, width, height, &g, k);
+        dx.iter().zip(&dy).map(|(x, y)| x.hypot(*y)).collect()
+    };
+    (magnitude(&edge), magnitude(&point))
+}
+
+/// Per pixel FLIP style error between two linear RGB images
+fn flip_error(
+    reference: &[Color],
+    test: &[Color],
+    width: usize,
+    height: usize,
+    ppd: f32,
+) -> Vec<f32> {
+    const QC: f32 = 0.7;
+    const QF: f32 = 0.5;
+    const PC: f32 = 0.4;
+    const PT: f32 = 0.95;
+    // Spatial filters standing in for the contrast sensitivity of each opponent channel,
+    // from FLIP's sum of Gaussians parameters b (in squared degrees)
+    let sigma = |b: f32| (b / (2.0 * std::f32::consts::PI.powi(2))).sqrt() * ppd;
+    let filtered = |pixels: &[Color]| -> Vec<Color> {
+        let [y, cx, cz] = planes(&pixels.iter().map(|&c| rgb_to_ycxcz(c)).collect::<Vec<_>>());
+        let y = blur(&y, width, height, sigma(0.0047));
+        let cx = blur(&cx, width, height, sigma(0.0053));
+        // Blue-yellow is a weighted pair of Gaussians
+        let wide = blur(&cz, width, height, sigma(0.04));
+        let narrow = blur(&cz, width, height, sigma(0.025));
+        let (w1, w2) = (
+            34.1 * (1.0 / 0.04_f32).sqrt(),
+            13.5 * (1.0 / 0.025_f32).sqrt(),
+        );
+        (0..pixels.len())
+            .map(|i| {
+                let cz = (w1 * wide[i] + w2 * narrow[i]) / (w1 + w2);
+                let rgb = ycxcz_to_rgb(Color::new(y[i], cx[i], cz));
+                let clamp = |v: f32| v.clamp(0.0, 1.0);
+                rgb_to_hunt_lab(Color::new(clamp(rgb.x()), clamp(rgb.y()), clamp(rgb.z())))
+            })
+            .collect()
+    };
+    let (lab_ref, lab_test) = (filtered(reference), filtered(test));
+    let green = rgb_to_hunt_lab(Color::new(0.0, 1.0, 0.0));
+    let blue = rgb_to_hunt_lab(Color::new(0.0, 0.0, 1.0));
+    let cmax = hyab(green, blue).powf(QC);
+
+    let lightness = |pixels: &[Color]| -> Vec<f32> {
+        pixels
+            .iter()
+            .map(|c| (rgb_to_hunt_lab(*c).x() + 16.0) / 116.0)
+            .collect()
+    };
+    let (edge_ref, point_ref) = features(&lightness(reference), width, height, ppd);
+    let (edge_test, point_test) = features(&lightness(test), width, height, ppd);
+
+    (0..reference.len())
+        .map(|i| {
+            let d = hyab(lab_ref[i], lab_test[i]).powf(QC);
+            // Give small differences most of the range, as FLIP does
+            let color = match d < PC * cmax {
+                true => PT * d / (PC * cmax),
+                false => PT + (1.0 - PT) * (d - PC * cmax) / (cmax - PC * cmax),
+            };
+            let feature = (edge_ref[i] - edge_test[i])
+                .abs()
+                .max((point_ref[i] - point_test[i]).abs());
+            let feature = (feature / 2_f32.sqrt()).powf(QF);
+            color.clamp(0.0, 1.0).powf(1.0 - feature.clamp(0.0, 1.0))
+        })
+        .collect()
+}
+
+/// Compare `test` against `reference`, which must be the same size. Images with
+/// different maxvals are compared by value relative to their maxval.
+pub fn compare(reference: &LdrImage, test: &LdrImage, ppd: f32) -> Result<Comparison, String> {
+    if (reference.width, reference.height) != (test.width, test.height) {
+        return Err(format!(
+            "image sizes differ: {}x{} and {}x{}",
+            reference.width, reference.height, test.width, test.height
+        ));
+    }
+    let (width, height) = (reference.width, reference.height);
+    let display = |image: &LdrImage| -> Vec<Color> {
+        let scale = image.maxval as f32;
+        image
+            .data
+            .iter()
+            .map(|p| {
+                Color::new(
+                    p[0] as f32 / scale,
+                    p[1] as f32 / scale,
+                    p[2] as f32 / scale,
+                )
+            })
+            .collect()
+    };
+    let (a, b) = (display(reference), display(test));
+    let (pa, pb) = (planes(&a), planes(&b));
+    let mut rmse = [0.0; 3];
+    let mut psnr = [0.0; 3];
+    let mut ssim_values = [0.0; 3];
+    for c in 0..3 {
+        let mse = pa[c]
+            .iter()
+            .zip(&pb[c])
+            .map(|(x, y)| (x - y) * (x - y))
+            .sum::<f32>()
+            / pa[c].len() as f32;
+        rmse[c] = mse.sqrt();
+        psnr[c] = -10.0 * mse.log10();
+        ssim_values[c] = ssim(&pa[c], &pb[c], width, height);
+    }
+    let linear = |pixels: &[Color]| -> Vec<Color> {
+        let decode = |v: f32| Transfer::Srgb.decode(v);
+        pixels
+            .iter()
+            .map(|c| Color::new(decode(c.x()), decode(c.y()), decode(c.z())))
+            .collect()
+    };
+    Ok(Comparison {
+        width,
+        height,
+        rmse,
+        psnr,
+        ssim: ssim_values,
+        error_map: flip_error(&linear(&a), &linear(&b), width, height, ppd),
+    })
+}
+
+#[test]
+fn test_compare() {
+    let gradient = |shift: u16| LdrImage {
+        width: 16,
+        height: 16,
+        maxval: 255,
+        data: (0..256_u16)
+            .map(|i| [i, (i * 7 + shift) % 256, 255 - i])
+            .collect(),
+    };
+    let same = compare(&gradient(0), &gradient(0), DEFAULT_PIXELS_PER_DEGREE).unwrap();
+    assert!(same.identical());
+    assert!(same.psnr.iter().all(|p| p.is_infinite()));
+    assert!(same.ssim.iter().all(|&s| (s - 1.0).abs() < 1e-4));
+    assert!(same.mean_error() < 1e-4);
+
+    let shifted = compare(&gradient(0), &gradient(40), DEFAULT_PIXELS_PER_DEGREE).unwrap();
+    assert!(!shifted.identical());
+    assert_eq!((shifted.rmse[0], shifted.rmse[2]), (0.0, 0.0));
+    assert!(shifted.rmse[1] > 0.0 && shifted.ssim[1] < 0.99);
+    assert!(shifted.mean_error() > same.mean_error());
+
+    let mut small = gradient(0);
+    small.width = 8;
+    small.height = 32;
+    assert!(compare(&gradient(0), &small, DEFAULT_PIXELS_PER_DEGREE).is_err());
+}
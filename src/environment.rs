@@ -0,0 +1,176 @@
+//! Lighting from a panorama of everything around the scene, stored as an
+//! equirectangular (latitude-longitude) image.
+//!
+//! The middle of the image looks down -z, its left and right edges meet behind at +z,
+//! and its top and bottom rows are straight up and down.
+
+use std::f32::consts::PI;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::framebuffer::Framebuffer;
+use crate::image::{image_source, load_hdr_image};
+use crate::sampling::Distribution2D;
+use crate::vec3::{luminance, Color, Transform, Vec3};
+
+/// A linear panorama, turned about the vertical axis and scaled in brightness, that can
+/// pick directions in proportion to how much light comes from them
+#[derive(Clone, Debug, PartialEq)]
+pub struct EnvironmentMap {
+    path: PathBuf,
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+    rotation: f32,
+    intensity: f32,
+    to_map: Transform,
+    distribution: Distribution2D,
+}
+
+impl EnvironmentMap {
+    /// `rotation` turns the panorama by that many degrees about +y
+    pub fn new(path: PathBuf, image: &Framebuffer, rotation: f32, intensity: f32) -> Self {
+        let (width, height) = (image.width(), image.height());
+        let pixels = image.pixels().collect::<Vec<_>>();
+        // Rows near the poles cover less of the sphere, so are picked less often
+        let weights = pixels
+            .iter()
+            .enumerate()
+            .map(|(i, c)| {
+                let theta = PI * ((i / width) as f32 + 0.5) / height as f32;
+                luminance(*c) * theta.sin()
+            })
+            .collect::<Vec<_>>();
+        Self {
+            path,
+            width,
+            height,
+            pixels,
+            rotation,
+            intensity,
+            to_map: Transform::rotate(1, -rotation),
+            distribution: Distribution2D::new(width, &weights),
+        }
+    }
+
+    /// Load a `.hdr` or `.pfm` panorama
+    pub fn load<P: AsRef<Path>>(path: P, rotation: f32, intensity: f32) -> io::Result<Self> {
+        let image = load_hdr_image(&path)?;
+        let absolute = image_source(path)?;
+        Ok(Self::new(absolute, &image, rotation, intensity))
+    }
+
+    /// Get the environment map's path.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Get the environment map's rotation in degrees about +y.
+    pub fn rotation(&self) -> f32 {
+        self.rotation
+    }
+
+    /// Get the environment map's intensity.
+    pub fn intensity(&self) -> f32 {
+        self.intensity
+    }
+
+    /// Where a world direction lands on the image, from the top left corner
+    fn image_coordinates(&self, dir: Vec3) -> (f32, f32) {
+        let d = self.to_map.vector(dir).unit_vector();
+        let s = 0.5 + d.x().atan2(-d.z()) / (2.0 * PI);
+        let t = d.y().clamp(-1.0, 1.0).acos() / PI;
+        (s.clamp(0.0, 1.0 - f32::EPSILON), t.min(1.0 - f32::EPSILON))
+    }
+
+    fn pixel(&self, s: f32, t: f32) -> Color {
+        let i = ((s * self.width as f32) as usize).min(self.width - 1);
+        let j = ((t * self.height as f32) as usize).min(self.height - 1);
+        self.intensity * self.pixels[j * self.width + i]
+    }
+
+    /// The light arriving from direction `dir`
+    pub fn color(&self, dir: Vec3) -> Color {
+        let (s, t) = self.image_coordinates(dir);
+        self.pixel(s, t)
+    }
+
+    /// A direction toward the scene's surroundings picked mostly where they are bright,
+    /// the light from it and the density of picking it per unit solid angle
+    pub fn sample(&self, u0: f32, u1: f32) -> Option<(Vec3, Color, f32)> {
+        let ((s, t), pdf) = self.distribution.sample(u0, u1);
+        let (phi, theta) = (2.0 * PI * (s - 0.5), PI * t);
+        let local = Vec3::new(
+            theta.sin() * phi.sin(),
+            theta.cos(),
+            -theta.sin() * phi.cos(),
+        );
+        let dir = self.to_map.inverse().vector(local);
+        // The image covers 2 pi by pi radians, squeezed by sin(theta) away from the equator
+        match pdf > 0.0 && theta.sin() > 0.0 {
+            true => Some((dir, self.pixel(s, t), pdf / (2.0 * PI * PI * theta.sin()))),
+            false => None,
+        }
+    }
+
+    /// The density of `sample` picking `dir`, per unit solid angle
+    pub fn pdf(&self, dir: Vec3) -> f32 {
+        let (s, t) = self.image_coordinates(dir);
+        let sin_theta = (PI * t).sin();
+        match sin_theta > 0.0 {
+            true => self.distribution.pdf(s, t) / (2.0 * PI * PI * sin_theta),
+            false => 0.0,
+        }
+    }
+}
+
+#[test]
+fn test_environment_map() {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    // Dim everywhere but one bright pixel left of the middle, just above the horizon
+    let (width, height) = (16, 8);
+    let mut pixels = vec![Color::new_singleton(0.1); width * height];
+    pixels[3 * width + 6] = Color::new(50.0, 40.0, 30.0);
+    let image = Framebuffer::from_pixels(width, height, pixels);
+    let map = EnvironmentMap::new(PathBuf::new(), &image, 0.0, 2.0);
+
+    let ahead = Vec3::new(0.0, 0.0, -1.0);
+    assert_eq!(map.color(ahead), Color::new_singleton(0.2));
+    let bright = Vec3::new(-0.5, 0.2, -0.9);
+    assert_eq!(map.color(bright), Color::new(100.0, 80.0, 60.0));
+    // Turning the map turns where its light comes from
+    let turned = EnvironmentMap::new(PathBuf::new(), &image, 90.0, 2.0);
+    let turned_bright = Transform::rotate(1, 90.0).vector(bright);
+    assert_eq!(turned.color(turned_bright), Color::new(100.0, 80.0, 60.0));
+
+    // Most samples head for the bright pixel, each with the density `pdf` gives it, and
+    // weighting by that density integrates the map. Rounding can move a sample on the
+    // edge of a pixel into the next one.
+    let mut rng = StdRng::seed_from_u64(2);
+    let (mut toward_bright, mut disagree) = (0, 0);
+    let mut total = 0.0;
+    let n = 20000;
+    for _ in 0..n {
+        let (dir, color, pdf) = turned.sample(rng.gen(), rng.gen()).unwrap();
+        if color != turned.color(dir) || (pdf - turned.pdf(dir)).abs() > 1e-3 * pdf {
+            disagree += 1;
+        }
+        if color.x() > 1.0 {
+            toward_bright += 1;
+        }
+        total += color.y() / pdf / n as f32;
+    }
+    assert!(toward_bright > n / 2);
+    assert!(disagree < 10);
+    let mut expected = 0.0;
+    for j in 0..height {
+        let theta = |j: f32| PI * j / height as f32;
+        let band = 2.0 * PI * (theta(j as f32).cos() - theta(j as f32 + 1.0).cos());
+        for i in 0..width {
+            expected += image.pixel(i, j).y() * 2.0 * band / width as f32;
+        }
+    }
+    assert!((total - expected).abs() < 0.02 * expected);
+}
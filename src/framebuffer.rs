@@ -0,0 +1,100 @@
+use crate::vec3::Color;
+
+/// Linear radiance straight out of the renderer, before any tone mapping or quantization.
+///
+/// Each pixel keeps the sum of its samples and how many there were, rather than a
+/// finished average. Rows are stored top first, as images are written.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Framebuffer {
+    width: usize,
+    height: usize,
+    sums: Vec<Color>,
+    samples: Vec<u32>,
+}
+
+impl Framebuffer {
+    /// Build from rows of per pixel sums, top row first, that all took `samples` samples
+    pub fn from_rows(width: usize, rows: Vec<Vec<Color>>, samples: u32) -> Self {
+        let height = rows.len();
+        let sums = rows.into_iter().flatten().collect::<Vec<_>>();
+        assert_eq!(sums.len(), width * height);
+        Self {
+            width,
+            height,
+            sums,
+            samples: vec![samples; width * height],
+        }
+    }
+
+    /// Build from already averaged pixels that count as one sample each
+    pub fn from_pixels(width: usize, height: usize, pixels: Vec<Color>) -> Self {
+        assert_eq!(pixels.len(), width * height);
+        Self {
+            width,
+            height,
+            sums: pixels,
+            samples: vec![1; width * height],
+        }
+    }
+
+    /// Get the framebuffer's width.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Get the framebuffer's height.
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Mean radiance of a pixel, black if it has no samples
+    pub fn pixel(&self, x: usize, y: usize) -> Color {
+        let i = y * self.width + x;
+        match self.samples[i] {
+            0 => Color::new_dfl(),
+            n => self.sums[i] / n as f32,
+        }
+    }
+
+    /// Mean radiance of every pixel, rows top first
+    pub fn pixels(&self) -> impl Iterator<Item = Color> + '_ {
+        (0..self.height).flat_map(move |y| (0..self.width).map(move |x| self.pixel(x, y)))
+    }
+}
+
+#[test]
+fn test_framebuffer() {
+    use crate::quantize::{quantize, Dither};
+    use crate::tonemap::{ToneMapping, Transfer};
+
+    // Sums of 4 samples each, from black past white
+    let values = [0.0, 0.01, 0.2, 0.5, 0.99, 1.0, 1.5, 40.0];
+    let rows = vec![values
+        .iter()
+        .map(|v| Color::new(*v, *v / 2.0, 4.0 * *v))
+        .collect()];
+    let sums = rows.clone();
+    let fb = Framebuffer::from_rows(values.len(), rows, 4);
+    assert_eq!((fb.width(), fb.height()), (values.len(), 1));
+    for (pixel, sum) in fb.pixels().zip(sums[0].iter()) {
+        assert_eq!(pixel, *sum / 4.0);
+    }
+
+    // Quantized with the old square root gamma it matches the old 8 bit output, to
+    // within the level lost to truncating rather than rounding
+    let gamma2 = ToneMapping {
+        transfer: Transfer::Gamma(2.0),
+        ..ToneMapping::default()
+    };
+    let old = |sum: f32| ((sum / 4.0).sqrt().clamp(0.0, 0.999) * 255.999) as u8;
+    let image = quantize(&fb, &gamma2, 255, Dither::None);
+    for (level, sum) in image.data.iter().zip(sums[0].iter()) {
+        for (new, sum) in level.iter().zip([sum.x(), sum.y(), sum.z()].iter()) {
+            let old = old(*sum) as i32;
+            assert!((*new as i32 - old).abs() <= 1, "{} against {}", new, old);
+            if *sum / 4.0 >= 1.0 {
+                assert_eq!(*new, 255);
+            }
+        }
+    }
+}
@@ -0,0 +1,260 @@
+//! Radiance `.hdr` images: one shared exponent byte per pixel (RGBE), with each scanline
+//! run length encoded one channel at a time.
+
+use std::io::{self, BufRead, Write};
+
+use crate::framebuffer::Framebuffer;
+use crate::vec3::Color;
+
+fn invalid<S: Into<String>>(message: S) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// The brightest channel an RGBE pixel holds, a full mantissa under the largest exponent
+const MAX_RGBE: f32 = 255.0 / 256.0 * 1.701_411_8e38;
+
+fn to_rgbe(c: Color) -> [u8; 4] {
+    // NaN has no sensible brightness so is stored as black, and anything too bright,
+    // infinity included, as the brightest value that fits
+    let channel = |x: f32| match x.is_nan() {
+        true => 0.0,
+        false => x.clamp(0.0, MAX_RGBE),
+    };
+    let c = Color::new(channel(c.x()), channel(c.y()), channel(c.z()));
+    let v = c.x().max(c.y()).max(c.z());
+    if v < 1e-32 {
+        return [0, 0, 0, 0];
+    }
+    // v = m * 2^e with m in [0.5, 1), like C's frexp
+    let e = v.log2().floor() as i32 + 1;
+    let scale = 256.0 / 2_f32.powi(e);
+    let byte = |x: f32| (x * scale).min(255.0) as u8;
+    [byte(c.x()), byte(c.y()), byte(c.z()), (e + 128) as u8]
+}
+
+fn from_rgbe([r, g, b, e]: [u8; 4]) -> Color {
+    match e {
+        0 => Color::new_dfl(),
+        e => {
+            let f = 2_f32.powi(e as i32 - (128 + 8));
+            Color::new(
+                (r as f32 + 0.5) * f,
+                (g as f32 + 0.5) * f,
+                (b as f32 + 0.5) * f,
+            )
+        }
+    }
+}
+
+/// Run length encode one channel of a scanline. A count byte over 128 repeats the next
+/// byte `count - 128` times, otherwise `count` literal bytes follow.
+fn write_rle_channel<W: Write>(out: &mut W, data: &[u8]) -> io::Result<()> {
+    const MIN_RUN: usize = 4;
+    let mut i = 0;
+    while i < data.len() {
+        // Find the next run long enough to be worth encoding
+        let mut run_start = i;
+        let mut run_len = 0;
+        while run_start < data.len() {
+            run_len = data[run_start..]
+                .iter()
+                .take(127)
+                .take_while(|&&b| b == data[run_start])
+                .count();
+            if run_len >= MIN_RUN {
+                break;
+            }
+            run_start += run_len;
+            run_len = 0;
+        }
+        // Everything before it goes out as literals
+        while i < run_start {
+            let n = (run_start - i).min(128);
+            out.write_all(&[n as u8])?;
+            out.write_all(&data[i..i + n])?;
+            i += n;
+        }
+        if run_len > 0 {
+            out.write_all(&[128 + run_len as u8, data[run_start]])?;
+            i += run_len;
+        }
+    }
+    Ok(())
+}
+
+pub fn write_hdr<W: Write>(out: &mut W, fb: &Framebuffer) -> io::Result<()> {
+    let (width, height) = (fb.width(), fb.height());
+    write!(
+        out,
+        "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n",
+        height, width
+    )?;
+    for y in 0..height {
+        let row = (0..width)
+            .map(|x| to_rgbe(fb.pixel(x, y)))
+            .collect::<Vec<_>>();
+        // Scanlines outside this range cannot be run length encoded
+        if !(8..=0x7FFF).contains(&width) {
+            out.write_all(&row.concat())?;
+            continue;
+        }
+        out.write_all(&[2, 2, (width >> 8) as u8, (width & 0xFF) as u8])?;
+        for channel in 0..4 {
+            let data = row.iter().map(|p| p[channel]).collect::<Vec<_>>();
+            write_rle_channel(out, &data)?;
+        }
+    }
+    Ok(())
+}
+
+fn read_byte<R: BufRead>(input: &mut R) -> io::Result<u8> {
+    let mut byte = [0];
+    input.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+fn read_scanline<R: BufRead>(input: &mut R, width: usize) -> io::Result<Vec<[u8; 4]>> {
+    let mut first = [0; 4];
+    input.read_exact(&mut first)?;
+    let encoded_width = (first[2] as usize) << 8 | first[3] as usize;
+    if first[0] != 2 || first[1] != 2 || first[2] & 0x80 != 0 || !(8..=0x7FFF).contains(&width) {
+        // Flat pixels, kept only as they are read since the width is unchecked
+        let mut row = vec![first];
+        for _ in 1..width {
+            let mut pixel = [0; 4];
+            input.read_exact(&mut pixel)?;
+            row.push(pixel);
+        }
+        return Ok(row);
+    }
+    if encoded_width != width {
+        return Err(invalid("scanline width does not match the image width"));
+    }
+    let mut row = vec![[0_u8; 4]; width];
+    for channel in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let count = read_byte(input)? as usize;
+            let (n, run) = match count {
+                c if c > 128 => (c - 128, true),
+                c => (c, false),
+            };
+            if n == 0 || x + n > width {
+                return Err(invalid("bad run length in scanline"));
+            }
+            match run {
+                true => {
+                    let value = read_byte(input)?;
+                    row[x..x + n].iter_mut().for_each(|p| p[channel] = value);
+                }
+                false => {
+                    for pixel in row[x..x + n].iter_mut() {
+                        pixel[channel] = read_byte(input)?;
+                    }
+                }
+            }
+            x += n;
+        }
+    }
+    Ok(row)
+}
+
+pub fn read_hdr<R: BufRead>(input: &mut R) -> io::Result<Framebuffer> {
+    let mut line = String::new();
+    input.read_line(&mut line)?;
+    if !line.starts_with("#?") {
+        return Err(invalid("not a Radiance HDR file"));
+    }
+    loop {
+        line.clear();
+        if input.read_line(&mut line)? == 0 {
+            return Err(invalid("header ends before the image size"));
+        }
+        match line.trim_end() {
+            "" => break,
+            format if format.starts_with("FORMAT=") && format != "FORMAT=32-bit_rle_rgbe" => {
+                return Err(invalid(format!("unsupported {}", format)))
+            }
+            _ => {}
+        }
+    }
+    line.clear();
+    input.read_line(&mut line)?;
+    let (height, width) = match line.split_whitespace().collect::<Vec<_>>()[..] {
+        ["-Y", h, "+X", w] => match (h.parse::<usize>(), w.parse::<usize>()) {
+            (Ok(h), Ok(w)) if h > 0 && w > 0 => (h, w),
+            _ => return Err(invalid("bad image size")),
+        },
+        _ => {
+            return Err(invalid(format!(
+                "unsupported image orientation '{}'",
+                line.trim_end()
+            )))
+        }
+    };
+    if width.checked_mul(height).is_none() {
+        return Err(invalid("image too large"));
+    }
+    let mut pixels = Vec::new();
+    for _ in 0..height {
+        pixels.extend(read_scanline(input, width)?.into_iter().map(from_rgbe));
+    }
+    Ok(Framebuffer::from_pixels(width, height, pixels))
+}
+
+#[test]
+fn test_hdr_round_trip() {
+    let width = 20;
+    let pixels = (0..width * 3)
+        .map(|i| match i % width {
+            // a flat stretch to exercise runs, then a ramp of literals
+            x if x < 10 => Color::new(0.5, 2.0, 100.0),
+            x => Color::new_singleton(x as f32 * 0.37),
+        })
+        .collect::<Vec<_>>();
+    let fb = Framebuffer::from_pixels(width, 3, pixels);
+    let mut bytes = Vec::new();
+    write_hdr(&mut bytes, &fb).unwrap();
+    let loaded = read_hdr(&mut &bytes[..]).unwrap();
+    assert_eq!((loaded.width(), loaded.height()), (width, 3));
+    for (a, b) in fb.pixels().zip(loaded.pixels()) {
+        // 8 bits of mantissa keep values within 1%
+        assert!((a - b).length() <= 0.01 * a.length());
+    }
+}
+
+#[test]
+fn test_hdr_extreme_values() {
+    let pixels = vec![
+        Color::new(f32::INFINITY, 1.0, 0.0),
+        Color::new(f32::NAN, 0.5, f32::NEG_INFINITY),
+        Color::new_singleton(f32::MAX),
+        Color::new(MAX_RGBE, 0.0, 1e-3),
+    ];
+    let fb = Framebuffer::from_pixels(4, 1, pixels);
+    let mut bytes = Vec::new();
+    write_hdr(&mut bytes, &fb).unwrap();
+    let loaded = read_hdr(&mut &bytes[..])
+        .unwrap()
+        .pixels()
+        .collect::<Vec<_>>();
+    let near = |a: f32, b: f32| (a - b).abs() <= 0.01 * b;
+    // Too bright is as bright as the format goes, not wrapped round to black
+    assert!(near(loaded[0].x(), MAX_RGBE));
+    assert!(near(loaded[2].x(), MAX_RGBE) && near(loaded[2].z(), MAX_RGBE));
+    assert!(near(loaded[3].x(), MAX_RGBE));
+    // NaN and negative infinity come back black, leaving the finite channel
+    assert!(loaded[1].x() < 0.01 && loaded[1].z() < 0.01);
+    assert!(near(loaded[1].y(), 0.5));
+}
+
+#[test]
+fn test_hdr_bad_size() {
+    // A size far beyond the data is an error rather than a huge allocation
+    let header = |size: &str| format!("#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n{}\n", size);
+    for size in ["-Y 3000000000 +X 3000000000", "-Y 5000000000 +X 5000000000"].iter() {
+        let mut data = header(size).into_bytes();
+        data.extend([1, 2, 3, 130].iter());
+        assert!(read_hdr(&mut &data[..]).is_err());
+    }
+}
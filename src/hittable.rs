@@ -1,4 +1,16 @@
-use crate::{material::MaterialType, ray::Ray, vec3::Vec3};
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+use crate::{
+    aabb::Aabb,
+    bvh::Bvh,
+    instance::Instance,
+    material::MaterialType,
+    mesh::{self, Mesh},
+    ray::Ray,
+    rect::Rect,
+    vec3::{Color, Point, Transform, Vec3},
+};
 
 pub struct HitRecord<'a> {
     p: Vec3,
@@ -6,9 +18,71 @@ pub struct HitRecord<'a> {
     normal: Vec3,
     mat_ptr: &'a MaterialType,
     front_face: bool,
+    u: f32,
+    v: f32,
+    tint: Color,
+    wavelength: Option<f32>,
+    triangle: usize,
 }
 
 impl<'a> HitRecord<'a> {
+    /// Record a hit at `r.at(t)`. The geometric normal decides which side was hit, and
+    /// the shading normal, which may differ for smooth meshes, is flipped to face the ray
+    /// on the same terms.
+    pub fn new(
+        r: Ray,
+        t: f32,
+        outward_normal: Vec3,
+        shading_normal: Vec3,
+        mat_ptr: &'a MaterialType,
+        u: f32,
+        v: f32,
+    ) -> Self {
+        let front_face = r.dir().dot(outward_normal) < 0.0;
+        Self {
+            p: r.at(t),
+            t,
+            normal: if front_face {
+                shading_normal
+            } else {
+                -shading_normal
+            },
+            mat_ptr,
+            front_face,
+            u,
+            v,
+            tint: Color::new_singleton(1.0),
+            wavelength: None,
+            triangle: 0,
+        }
+    }
+
+    /// Carry a hit on an instanced object back out to the world, where `r` is the ray in
+    /// world space and `transform` takes the object into it
+    pub fn transformed(self, r: Ray, transform: &Transform) -> Self {
+        Self {
+            p: r.at(self.t),
+            normal: transform.normal(self.normal).unit_vector(),
+            ..self
+        }
+    }
+
+    /// Scale the albedo of the material by `tint`, as vertex colors do
+    pub fn with_tint(self, tint: Color) -> Self {
+        Self { tint, ..self }
+    }
+
+    /// Note which `triangle` of a mesh was hit
+    pub fn with_triangle(self, triangle: usize) -> Self {
+        Self { triangle, ..self }
+    }
+
+    /// Follow the single `wavelength` in nanometres the path arriving here carries, if it
+    /// has picked one
+    pub fn with_wavelength(self, wavelength: Option<f32>) -> Self {
+        Self { wavelength, ..self }
+    }
+
     /// Get the hit record's normal.
     pub fn normal(&self) -> Vec3 {
         self.normal
@@ -33,22 +107,63 @@ impl<'a> HitRecord<'a> {
     pub fn mat_ptr(&self) -> &'a MaterialType {
         self.mat_ptr
     }
+
+    /// Get the hit record's texture u coordinate.
+    pub fn u(&self) -> f32 {
+        self.u
+    }
+
+    /// Get the hit record's texture v coordinate.
+    pub fn v(&self) -> f32 {
+        self.v
+    }
+
+    /// Get the hit record's tint.
+    pub fn tint(&self) -> Color {
+        self.tint
+    }
+
+    /// Get the hit record's wavelength.
+    pub fn wavelength(&self) -> Option<f32> {
+        self.wavelength
+    }
+
+    /// Get the hit record's triangle, the index within a mesh, or 0 for other shapes.
+    pub fn triangle(&self) -> usize {
+        self.triangle
+    }
 }
 pub trait Hittable {
-    fn hit(&self, r: Ray, tmin: f32, tmax: f32) -> Option<HitRecord>;
+    fn hit(&self, r: Ray, tmin: f32, tmax: f32) -> Option<HitRecord<'_>>;
+    /// Box around everything the object can be hit on, or None if it is unbounded or empty
+    fn bounding_box(&self) -> Option<Aabb>;
 }
+#[derive(Clone, Debug, PartialEq)]
 pub enum HittableObject {
     Sphere(Vec3, f32, MaterialType),
     HittableList(Vec<HittableObject>),
+    Bvh(Box<Bvh>),
+    Triangle([Point; 3], MaterialType),
+    Mesh(Arc<Mesh>),
+    /// One triangle of a mesh, so a hierarchy can be built over a mesh's triangles
+    MeshTriangle(Arc<Mesh>, usize),
+    Instance(Box<Instance>),
+    Rect(Rect, MaterialType),
 }
 
-impl<'b> Hittable for HittableObject {
-    fn hit(&self, r: Ray, tmin: f32, tmax: f32) -> Option<HitRecord> {
+impl Hittable for HittableObject {
+    fn hit(&self, r: Ray, tmin: f32, tmax: f32) -> Option<HitRecord<'_>> {
         match self {
+            HittableObject::Bvh(bvh) => bvh.hit(r, tmin, tmax),
+            HittableObject::Triangle(p, material) => mesh::hit_triangle(p, material, r, tmin, tmax),
+            HittableObject::Mesh(mesh) => mesh.hit(r, tmin, tmax),
+            HittableObject::MeshTriangle(mesh, i) => mesh.hit_triangle(*i, r, tmin, tmax),
+            HittableObject::Instance(instance) => instance.hit(r, tmin, tmax),
+            HittableObject::Rect(rect, material) => rect.hit(material, r, tmin, tmax),
             HittableObject::HittableList(a) => a
                 //? Single threaded better than parallel cuz overhead?
                 .iter()
-                .map(|x| -> Option<HitRecord> { x.hit(r, tmin, tmax) })
+                .map(|x| -> Option<HitRecord<'_>> { x.hit(r, tmin, tmax) })
                 .fold(None, |boi, food| match food {
                     Some(hit) => match boi {
                         Some(prev_hit) => {
@@ -81,20 +196,37 @@ impl<'b> Hittable for HittableObject {
                             }
                         }
                         let t = root;
-                        let p = r.at(t);
-                        let out_norm = (p - *center) / *radius;
-                        let front = r.dir().dot(out_norm) < 0.0;
-                        let norm = if front { out_norm } else { -out_norm };
-                        Some(HitRecord {
-                            p,
-                            t,
-                            normal: norm,
-                            mat_ptr,
-                            front_face: (front),
-                        })
+                        let out_norm = (r.at(t) - *center) / *radius;
+                        // Longitude around y from -x, latitude from the south pole
+                        let u = ((-out_norm.z()).atan2(out_norm.x()) + PI) / (2.0 * PI);
+                        let v = (-out_norm.y()).clamp(-1.0, 1.0).acos() / PI;
+                        Some(HitRecord::new(r, t, out_norm, out_norm, mat_ptr, u, v))
                     }
                 }
             }
         }
     }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        match self {
+            HittableObject::Sphere(center, radius, _) => Some(Aabb::new(
+                *center - Vec3::new_singleton(radius.abs()),
+                *center + Vec3::new_singleton(radius.abs()),
+            )),
+            HittableObject::HittableList(objects) => objects
+                .iter()
+                .map(|object| object.bounding_box())
+                .reduce(|a, b| match (a, b) {
+                    (Some(a), Some(b)) => Some(a.union(b)),
+                    _ => None,
+                })
+                .flatten(),
+            HittableObject::Bvh(bvh) => bvh.bounding_box(),
+            HittableObject::Triangle(p, _) => Some(mesh::triangle_bounding_box(*p)),
+            HittableObject::Mesh(mesh) => mesh.bounding_box(),
+            HittableObject::MeshTriangle(mesh, i) => Some(mesh.bounding_box_of(*i)),
+            HittableObject::Instance(instance) => instance.bounding_box(),
+            HittableObject::Rect(rect, _) => Some(rect.bounding_box()),
+        }
+    }
 }
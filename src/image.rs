@@ -0,0 +1,347 @@
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::framebuffer::Framebuffer;
+use crate::hdr::{read_hdr, write_hdr};
+use crate::pfm::{read_pfm, write_pfm};
+use crate::png::{read_png, write_png, PngFormat};
+use crate::quantize::{quantize, Dither, LdrImage};
+use crate::tonemap::ToneMapping;
+
+/// PPM comes in a plain text flavour (P3) and a much smaller and faster binary one (P6)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PpmFormat {
+    Ascii,
+    Binary,
+}
+
+impl FromStr for PpmFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ascii" | "p3" => Ok(PpmFormat::Ascii),
+            "binary" | "p6" => Ok(PpmFormat::Binary),
+            _ => Err("expected ascii or binary".to_string()),
+        }
+    }
+}
+
+/// Write a PPM file. Binary files take two big endian bytes per sample when the maxval
+/// is over 255.
+pub fn write_ppm<W: Write>(out: &mut W, image: &LdrImage, format: PpmFormat) -> io::Result<()> {
+    let magic = match format {
+        PpmFormat::Ascii => "P3",
+        PpmFormat::Binary => "P6",
+    };
+    writeln!(
+        out,
+        "{}\n{} {}\n{}",
+        magic, image.width, image.height, image.maxval
+    )?;
+    // One row's bytes at a time, in a buffer kept across rows
+    let mut bytes = Vec::new();
+    for row in image.rows() {
+        match format {
+            PpmFormat::Ascii => {
+                for [r, g, b] in row.iter() {
+                    writeln!(out, "{} {} {}", r, g, b)?;
+                }
+            }
+            PpmFormat::Binary => {
+                bytes.clear();
+                for &s in row.iter().flatten() {
+                    match image.maxval > 255 {
+                        true => bytes.extend_from_slice(&s.to_be_bytes()),
+                        false => bytes.push(s as u8),
+                    }
+                }
+                out.write_all(&bytes)?
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Read the next header field, skipping whitespace and `#` comments
+fn read_ppm_field<R: BufRead>(input: &mut R) -> io::Result<String> {
+    let mut field = String::new();
+    let mut in_comment = false;
+    loop {
+        let mut byte = [0];
+        input.read_exact(&mut byte)?;
+        match (byte[0], in_comment, field.is_empty()) {
+            (b'\n', true, _) => in_comment = false,
+            (_, true, _) => {}
+            (b'#', false, true) => in_comment = true,
+            (b, false, true) if b.is_ascii_whitespace() => {}
+            (b, false, false) if b.is_ascii_whitespace() => return Ok(field),
+            (b, false, _) => field.push(b as char),
+        }
+    }
+}
+
+/// Read a PPM file, or a PGM one (P2 / P5) as grey RGB
+pub fn read_ppm<R: BufRead>(input: &mut R) -> io::Result<LdrImage> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+    let (channels, binary) = match read_ppm_field(input)?.as_str() {
+        "P2" => (1, false),
+        "P3" => (3, false),
+        "P5" => (1, true),
+        "P6" => (3, true),
+        _ => return Err(invalid("not a PPM file")),
+    };
+    let mut number = || -> io::Result<usize> {
+        read_ppm_field(input)?
+            .parse()
+            .map_err(|_| invalid("bad PPM header"))
+    };
+    let (width, height, maxval) = (number()?, number()?, number()?);
+    if width == 0 || height == 0 || !(1..=65535).contains(&maxval) {
+        return Err(invalid("bad PPM header"));
+    }
+    let size = if maxval > 255 { 2 } else { 1 };
+    let count = width
+        .checked_mul(height)
+        .and_then(|n| n.checked_mul(channels))
+        .filter(|n| n.checked_mul(size).is_some())
+        .ok_or_else(|| invalid("PPM image is too large"))?;
+    // The single whitespace byte after maxval was consumed with it
+    let samples = match binary {
+        true => {
+            // Only as much as is really there, which the check below finds too short
+            let mut bytes = Vec::new();
+            input
+                .by_ref()
+                .take((count * size) as u64)
+                .read_to_end(&mut bytes)?;
+            match size {
+                2 => bytes
+                    .chunks_exact(2)
+                    .map(|b| u16::from_be_bytes([b[0], b[1]]))
+                    .collect(),
+                _ => bytes.iter().map(|&b| b as u16).collect(),
+            }
+        }
+        false => {
+            let mut text = String::new();
+            input.read_to_string(&mut text)?;
+            text.split_whitespace()
+                .take(count)
+                .map(|s| s.parse::<u16>().map_err(|_| invalid("bad PPM sample")))
+                .collect::<io::Result<Vec<u16>>>()?
+        }
+    };
+    if samples.len() < count {
+        return Err(invalid("PPM image data ends early"));
+    }
+    if samples.iter().any(|&s| s as usize > maxval) {
+        return Err(invalid("PPM sample is larger than maxval"));
+    }
+    let data = samples
+        .chunks_exact(channels)
+        .map(|p| match p {
+            [r, g, b] => [*r, *g, *b],
+            _ => [p[0]; 3],
+        })
+        .collect();
+    Ok(LdrImage {
+        width,
+        height,
+        maxval: maxval as u16,
+        data,
+    })
+}
+
+/// Spread pixels out into one sample per channel, adding alpha if the format wants it.
+/// The image must already be quantized to the PNG's bit depth.
+fn png_samples(image: &LdrImage, format: PngFormat) -> Vec<u16> {
+    image
+        .data
+        .iter()
+        .flat_map(|&[r, g, b]| [r, g, b, image.maxval][..format.channels()].to_vec())
+        .collect()
+}
+
+/// How to encode images, for the formats that give a choice
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OutputOptions {
+    pub ppm: PpmFormat,
+    pub png: PngFormat,
+    /// Used for formats that store display values rather than radiance
+    pub tone_mapping: ToneMapping,
+    pub dither: Dither,
+    /// Levels per channel in PPM files, overriding the caller's default
+    pub color_size: Option<usize>,
+}
+
+impl Default for OutputOptions {
+    fn default() -> Self {
+        Self {
+            ppm: PpmFormat::Ascii,
+            png: PngFormat::default(),
+            tone_mapping: ToneMapping::default(),
+            dither: Dither::None,
+            color_size: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImageFormat {
+    Ppm,
+    Png,
+    Hdr,
+    Pfm,
+}
+
+impl ImageFormat {
+    /// Choose the format from a file's extension, treating a missing one as PPM.
+    pub fn from_path(path: &Path) -> Result<Self, String> {
+        let extension = path
+            .extension()
+            .map(|e| e.to_string_lossy().to_ascii_lowercase());
+        match extension.as_deref() {
+            None | Some("ppm") => Ok(ImageFormat::Ppm),
+            Some("png") => Ok(ImageFormat::Png),
+            Some("hdr") => Ok(ImageFormat::Hdr),
+            Some("pfm") => Ok(ImageFormat::Pfm),
+            Some(other) => Err(format!(
+                "unsupported image extension '.{}', expected .ppm, .png, .hdr or .pfm",
+                other
+            )),
+        }
+    }
+}
+
+/// Write a render in the given format, quantizing it first for formats that need it.
+///
+/// PPM files get `colorsize` levels per channel, PNG files as many as their bit depth holds.
+pub fn write_image<W: Write>(
+    out: &mut W,
+    fb: &Framebuffer,
+    colorsize: usize,
+    format: ImageFormat,
+    options: &OutputOptions,
+) -> io::Result<()> {
+    let quantized = |maxval: u16| quantize(fb, &options.tone_mapping, maxval, options.dither);
+    match format {
+        ImageFormat::Ppm => match colorsize {
+            2..=65536 => write_ppm(out, &quantized((colorsize - 1) as u16), options.ppm),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("PPM color size {} is not between 2 and 65536", colorsize),
+            )),
+        },
+        ImageFormat::Png => {
+            let maxval = ((1_u32 << options.png.bit_depth()) - 1) as u16;
+            let samples = png_samples(&quantized(maxval), options.png);
+            write_png(out, fb.width(), fb.height(), &samples, options.png)
+        }
+        ImageFormat::Hdr => write_hdr(out, fb),
+        ImageFormat::Pfm => write_pfm(out, fb),
+    }
+}
+
+/// Write an image to a new file at `path`, replacing any existing file.
+pub fn save_image<P: AsRef<Path>>(
+    path: P,
+    fb: &Framebuffer,
+    colorsize: usize,
+    format: ImageFormat,
+    options: &OutputOptions,
+) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    write_image(&mut out, fb, colorsize, format, options)?;
+    out.flush()
+}
+
+/// The absolute path of an image a scene loads, so the scene still finds it wherever
+/// it is saved to
+pub fn image_source<P: AsRef<Path>>(path: P) -> io::Result<PathBuf> {
+    fs::canonicalize(path)
+}
+
+/// Read a display referred image from a `.ppm` or `.png` file
+pub fn load_ldr_image<P: AsRef<Path>>(path: P) -> io::Result<LdrImage> {
+    let path = path.as_ref();
+    let format =
+        ImageFormat::from_path(path).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let mut input = BufReader::new(File::open(path)?);
+    match format {
+        ImageFormat::Ppm => read_ppm(&mut input),
+        ImageFormat::Png => read_png(&mut input),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "only .ppm and .png images can be read here",
+        )),
+    }
+}
+
+/// Read a linear image from a `.hdr` or `.pfm` file
+pub fn load_hdr_image<P: AsRef<Path>>(path: P) -> io::Result<Framebuffer> {
+    let path = path.as_ref();
+    let format =
+        ImageFormat::from_path(path).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let mut input = BufReader::new(File::open(path)?);
+    match format {
+        ImageFormat::Hdr => read_hdr(&mut input),
+        ImageFormat::Pfm => read_pfm(&mut input),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "only .hdr and .pfm images can be read",
+        )),
+    }
+}
+
+#[test]
+fn test_write_ppm() {
+    let image = LdrImage {
+        width: 2,
+        height: 2,
+        maxval: 255,
+        data: vec![[255, 0, 128], [0, 0, 0], [1, 2, 3], [255, 255, 255]],
+    };
+    let mut ascii = Vec::new();
+    write_ppm(&mut ascii, &image, PpmFormat::Ascii).unwrap();
+    assert_eq!(
+        String::from_utf8(ascii).unwrap(),
+        "P3\n2 2\n255\n255 0 128\n0 0 0\n1 2 3\n255 255 255\n"
+    );
+    let mut binary = Vec::new();
+    write_ppm(&mut binary, &image, PpmFormat::Binary).unwrap();
+    assert_eq!(&binary[..11], b"P6\n2 2\n255\n");
+    assert_eq!(
+        &binary[11..],
+        &[255, 0, 128, 0, 0, 0, 1, 2, 3, 255, 255, 255]
+    );
+    let deep = LdrImage {
+        width: 1,
+        height: 1,
+        maxval: 1023,
+        data: vec![[1023, 256, 1]],
+    };
+    let mut binary = Vec::new();
+    write_ppm(&mut binary, &deep, PpmFormat::Binary).unwrap();
+    assert_eq!(&binary[..12], b"P6\n1 1\n1023\n");
+    assert_eq!(&binary[12..], &[3, 255, 1, 0, 0, 1]);
+    assert_eq!(read_ppm(&mut &binary[..]).unwrap(), deep);
+    let commented = b"P3 # plain\n# size\n2 2 255\n255 0 128\n0 0 0 1 2 3 255 255 255\n";
+    assert_eq!(read_ppm(&mut &commented[..]).unwrap(), image);
+}
+
+#[test]
+fn test_read_ppm_bad_size() {
+    // Sizes the data cannot hold are errors, not allocations
+    for header in [
+        "P6\n100000 100000\n65535\n",
+        "P5\n4294967296 4294967296\n255\n",
+    ]
+    .iter()
+    {
+        let mut data = header.as_bytes().to_vec();
+        data.extend([0; 12].iter());
+        assert!(read_ppm(&mut &data[..]).is_err());
+    }
+}
@@ -0,0 +1,128 @@
+//! Copies of an object placed with an affine transform, sharing the object's memory.
+
+use std::sync::Arc;
+
+use crate::aabb::Aabb;
+use crate::hittable::{HitRecord, Hittable, HittableObject};
+use crate::ray::Ray;
+use crate::vec3::{Point, Transform};
+
+/// An object seen through `transform`, which takes it from its own space into the world
+#[derive(Clone, Debug, PartialEq)]
+pub struct Instance {
+    object: Arc<HittableObject>,
+    transform: Transform,
+}
+
+impl Instance {
+    pub fn new(object: Arc<HittableObject>, transform: Transform) -> Self {
+        Self { object, transform }
+    }
+
+    /// Get the instance's object.
+    pub fn object(&self) -> &Arc<HittableObject> {
+        &self.object
+    }
+
+    /// Get the instance's transform.
+    pub fn transform(&self) -> &Transform {
+        &self.transform
+    }
+}
+
+impl Hittable for Instance {
+    fn hit(&self, r: Ray, tmin: f32, tmax: f32) -> Option<HitRecord<'_>> {
+        // The direction is not renormalized, so t means the same in both spaces
+        let to_object = self.transform.inverse();
+        let local = Ray::new(to_object.point(r.orig()), to_object.vector(r.dir()));
+        self.object
+            .hit(local, tmin, tmax)
+            .map(|rec| rec.transformed(r, &self.transform))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let bbox = self.object.bounding_box()?;
+        let corner = |i: usize| {
+            let pick = |axis: usize| match (i >> axis) & 1 {
+                0 => bbox.min()[axis],
+                _ => bbox.max()[axis],
+            };
+            self.transform.point(Point::new(pick(0), pick(1), pick(2)))
+        };
+        Some((0..8).map(corner).fold(Aabb::empty(), Aabb::grow))
+    }
+}
+
+#[test]
+fn test_instance() {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use crate::bvh::Bvh;
+    use crate::material::MaterialType;
+    use crate::vec3::Vec3;
+
+    let material = MaterialType::Lambertian(Vec3::new_singleton(0.5).into());
+    let unit = Arc::new(HittableObject::Sphere(
+        Point::new_dfl(),
+        1.0,
+        material.clone(),
+    ));
+    let placed = Instance::new(
+        unit,
+        Transform::scale(Vec3::new(2.0, 1.0, 1.0))
+            .unwrap()
+            .then(&Transform::translate(Vec3::new(0.0, 0.0, -10.0))),
+    );
+    let hit = placed
+        .hit(
+            Ray::new(Point::new_dfl(), Vec3::new(0.0, 0.0, -1.0)),
+            0.001,
+            f32::INFINITY,
+        )
+        .unwrap();
+    assert!((hit.t() - 9.0).abs() < 1e-5);
+    assert!((hit.normal() - Vec3::new(0.0, 0.0, 1.0)).length() < 1e-5);
+    // Stretched along x, the side is hit where the unstretched sphere would not be
+    let side = Ray::new(Point::new(1.5, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+    assert!(placed.hit(side, 0.001, f32::INFINITY).is_some());
+    let bbox = placed.bounding_box().unwrap();
+    assert!((bbox.min() - Point::new(-2.0, -1.0, -11.0)).length() < 1e-5);
+
+    // Many rotated copies of one cluster, through a hierarchy and without one
+    let mut rng = StdRng::seed_from_u64(3);
+    let cluster = Arc::new(HittableObject::HittableList(
+        (0..20)
+            .map(|_| {
+                let center = Vec3::random_vec3_range(-2.0, 2.0, &mut rng);
+                HittableObject::Sphere(center, 0.3, material.clone())
+            })
+            .collect(),
+    ));
+    let copies = (0..10)
+        .map(|i| {
+            let transform = Transform::rotate(1, 36.0 * i as f32).then(&Transform::translate(
+                Vec3::new(8.0 * i as f32 - 40.0, 0.0, 0.0),
+            ));
+            HittableObject::Instance(Box::new(Instance::new(cluster.clone(), transform)))
+        })
+        .collect();
+    let list = HittableObject::HittableList(copies);
+    let bvh = Bvh::new(list.clone());
+    let shared = bvh
+        .objects()
+        .map(|object| match object {
+            HittableObject::Instance(instance) => instance.object().clone(),
+            _ => panic!("instances should stay whole"),
+        })
+        .collect::<Vec<_>>();
+    assert!(shared.iter().all(|object| Arc::ptr_eq(object, &shared[0])));
+    for _ in 0..500 {
+        let orig = Point::random_vec3_range(-45.0, 45.0, &mut rng);
+        let r = Ray::new(orig, Vec3::random_unit_vector(&mut rng));
+        assert_eq!(
+            bvh.hit(r, 0.001, f32::INFINITY).map(|hit| hit.t()),
+            list.hit(r, 0.001, f32::INFINITY).map(|hit| hit.t())
+        );
+    }
+}
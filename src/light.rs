@@ -0,0 +1,487 @@
+//! Emissive geometry gathered from the scene, so surfaces can pick a point on a light
+//! and send a shadow ray toward it instead of waiting for a bounce to find it.
+
+use std::collections::HashMap;
+use std::f32::consts::PI;
+use std::marker::PhantomData;
+
+use crate::hittable::{HitRecord, HittableObject};
+use crate::material::MaterialType;
+use crate::mesh::intersect_triangle;
+use crate::ray::Ray;
+use crate::sampling::Distribution1D;
+use crate::texture::{Texture, TextureType};
+use crate::vec3::{luminance, Point, Transform, Vec3};
+
+/// The shape of one light, in world space
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Light {
+    /// Sampled over the cone of directions it fills, or over its area from inside
+    Sphere(Point, f32),
+    /// The corner and two edges, as rectangles become under any transform
+    Parallelogram(Point, Vec3, Vec3),
+    Triangle([Point; 3]),
+}
+
+impl Light {
+    pub fn area(&self) -> f32 {
+        match self {
+            Light::Sphere(_, radius) => 4.0 * PI * radius * radius,
+            Light::Parallelogram(_, a, b) => a.cross(*b).length(),
+            Light::Triangle(p) => 0.5 * (p[1] - p[0]).cross(p[2] - p[0]).length(),
+        }
+    }
+
+    fn centroid(&self) -> Point {
+        match self {
+            Light::Sphere(center, _) => *center,
+            Light::Parallelogram(corner, a, b) => *corner + 0.5 * (*a + *b),
+            Light::Triangle(p) => (1.0 / 3.0) * (p[0] + p[1] + p[2]),
+        }
+    }
+
+    /// A point on the light seen from `from` and its density per unit solid angle there
+    pub fn sample(&self, from: Point, u0: f32, u1: f32) -> Option<(Point, f32)> {
+        let point = match self {
+            Light::Sphere(center, radius) => {
+                let to_center = *center - from;
+                let d2 = to_center.length_squared();
+                if d2 > radius * radius {
+                    // Only the near side can be seen, within the cone around the center
+                    let one_minus_cos_max = cone(d2, *radius);
+                    let cos = 1.0 - u0 * one_minus_cos_max;
+                    let sin = (1.0 - cos * cos).max(0.0).sqrt();
+                    let phi = 2.0 * PI * u1;
+                    let w = to_center / d2.sqrt();
+                    let (s, t) = w.basis();
+                    let dir = sin * phi.cos() * s + sin * phi.sin() * t + cos * w;
+                    let along =
+                        d2.sqrt() * cos - (radius * radius - d2 * sin * sin).max(0.0).sqrt();
+                    return Some((from + along * dir, 1.0 / (2.0 * PI * one_minus_cos_max)));
+                }
+                let z = 1.0 - 2.0 * u0;
+                let r = (1.0 - z * z).max(0.0).sqrt();
+                let phi = 2.0 * PI * u1;
+                *center + *radius * Vec3::new(r * phi.cos(), r * phi.sin(), z)
+            }
+            Light::Parallelogram(corner, a, b) => *corner + u0 * *a + u1 * *b,
+            Light::Triangle(p) => {
+                let su = u0.sqrt();
+                let (b0, b1) = (1.0 - su, u1 * su);
+                b0 * p[0] + b1 * p[1] + (1.0 - b0 - b1) * p[2]
+            }
+        };
+        let pdf = self.pdf(from, point);
+        match pdf > 0.0 && pdf.is_finite() {
+            true => Some((point, pdf)),
+            false => None,
+        }
+    }
+
+    /// The density per unit solid angle of `sample` picking `point` on the light
+    pub fn pdf(&self, from: Point, point: Point) -> f32 {
+        let normal = match self {
+            Light::Sphere(center, radius) => {
+                let d2 = (*center - from).length_squared();
+                if d2 > radius * radius {
+                    return 1.0 / (2.0 * PI * cone(d2, *radius));
+                }
+                point - *center
+            }
+            Light::Parallelogram(_, a, b) => a.cross(*b),
+            Light::Triangle(p) => (p[1] - p[0]).cross(p[2] - p[0]),
+        };
+        // Convert from per unit area, as the light's surface is seen at a slant
+        let to_point = point - from;
+        let cos = normal.unit_vector().dot(to_point.unit_vector()).abs();
+        match cos > 0.0 {
+            true => to_point.length_squared() / (cos * self.area()),
+            false => 0.0,
+        }
+    }
+
+    /// Where `r` first reaches the light past `tmin`, as the ray parameter
+    pub fn intersect(&self, r: Ray, tmin: f32) -> Option<f32> {
+        match self {
+            Light::Sphere(center, radius) => {
+                let oc = r.orig() - *center;
+                let a = r.dir().length_squared();
+                let half_b = oc.dot(r.dir());
+                let c = oc.length_squared() - radius * radius;
+                let discr = half_b * half_b - a * c;
+                if discr < 0.0 {
+                    return None;
+                }
+                let sqrtd = discr.sqrt();
+                [(-half_b - sqrtd) / a, (-half_b + sqrtd) / a]
+                    .iter()
+                    .copied()
+                    .find(|&t| t > tmin)
+            }
+            Light::Parallelogram(corner, a, b) => {
+                let n = a.cross(*b);
+                let denom = n.dot(r.dir());
+                if denom == 0.0 {
+                    return None;
+                }
+                let t = n.dot(*corner - r.orig()) / denom;
+                let w = n / n.dot(n);
+                let offset = r.at(t) - *corner;
+                let (alpha, beta) = (w.dot(offset.cross(*b)), w.dot(a.cross(offset)));
+                match t > tmin && (0.0..=1.0).contains(&alpha) && (0.0..=1.0).contains(&beta) {
+                    true => Some(t),
+                    false => None,
+                }
+            }
+            Light::Triangle(p) => intersect_triangle(r, *p, tmin, f32::INFINITY).map(|(t, _)| t),
+        }
+    }
+}
+
+/// One minus the cosine of the half angle of the cone a sphere of `radius` fills, seen
+/// from `d2` away squared, written to keep its precision for small, far spheres
+fn cone(d2: f32, radius: f32) -> f32 {
+    let sin2 = radius * radius / d2;
+    sin2 / (1.0 + (1.0 - sin2).max(0.0).sqrt())
+}
+
+/// Which primitive a light came from: the address of its material in the world, and
+/// the triangle within a mesh
+type PrimitiveKey = (usize, usize);
+
+fn primitive_key(material: &MaterialType, triangle: usize) -> PrimitiveKey {
+    (material as *const MaterialType as usize, triangle)
+}
+
+/// Every emissive object in a scene, picked in proportion to the light it gives off
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lights<'a> {
+    lights: Vec<Light>,
+    /// None when the scene has no lights
+    distribution: Option<Distribution1D>,
+    /// Emitters no light can stand for, left to be found by bouncing
+    skipped: usize,
+    /// The lights made from each primitive, several when instances share it
+    primitives: HashMap<PrimitiveKey, Vec<usize>>,
+    /// Holds the world in place, so the addresses in `primitives` stay valid
+    world: PhantomData<&'a HittableObject>,
+}
+
+impl<'a> Lights<'a> {
+    /// Gather the lights from everything in `world`, through lists, hierarchies and
+    /// instances
+    pub fn new(world: &'a HittableObject) -> Self {
+        let (mut found, mut skipped) = (Vec::new(), 0);
+        gather(world, &Transform::identity(), &mut found, &mut skipped);
+        let mut primitives = HashMap::new();
+        let (mut lights, mut power) = (Vec::new(), Vec::new());
+        for (i, (light, p, key)) in found.into_iter().enumerate() {
+            primitives.entry(key).or_insert_with(Vec::new).push(i);
+            lights.push(light);
+            power.push(p);
+        }
+        let distribution = match lights.is_empty() {
+            true => None,
+            false => Some(Distribution1D::new(power)),
+        };
+        Self {
+            lights,
+            distribution,
+            skipped,
+            primitives,
+            world: PhantomData,
+        }
+    }
+
+    /// Get the lights.
+    pub fn lights(&self) -> &[Light] {
+        &self.lights
+    }
+
+    /// Get how many emitters could not be sampled, such as spheres stretched unevenly.
+    pub fn skipped(&self) -> usize {
+        self.skipped
+    }
+
+    fn chance_of(&self, distribution: &Distribution1D, i: usize) -> f32 {
+        distribution.pdf_of(i) / self.lights.len() as f32
+    }
+
+    /// Pick a light with `u`, then a point on it seen from `from`, with the density per
+    /// unit solid angle of this whole choice
+    pub fn sample(&self, from: Point, u: f32, u0: f32, u1: f32) -> Option<(Point, f32)> {
+        let distribution = self.distribution.as_ref()?;
+        let (_, _, i) = distribution.sample(u);
+        let (point, pdf) = self.lights[i].sample(from, u0, u1)?;
+        Some((point, self.chance_of(distribution, i) * pdf))
+    }
+
+    /// The density of `sample` choosing the direction of `r`, which first hits the
+    /// primitive in `rec`. Anything that is not a light gives nothing.
+    pub fn pdf(&self, r: Ray, rec: &HitRecord) -> f32 {
+        let (distribution, candidates) = match (
+            &self.distribution,
+            self.primitives
+                .get(&primitive_key(rec.mat_ptr(), rec.triangle())),
+        ) {
+            (Some(distribution), Some(candidates)) => (distribution, candidates),
+            _ => return 0.0,
+        };
+        // Instances of one primitive each make a light, of which only one was hit
+        let t = rec.t();
+        candidates
+            .iter()
+            .filter(|&&i| {
+                self.lights[i]
+                    .intersect(r, (1.0 - 1e-3) * t)
+                    .is_some_and(|hit| hit <= (1.0 + 1e-3) * t)
+            })
+            .map(|&i| self.chance_of(distribution, i) * self.lights[i].pdf(r.orig(), r.at(t)))
+            .sum()
+    }
+}
+
+/// The power of a light, taking the emission where it is sampled to be typical
+fn power(light: &Light, emit: &TextureType) -> f32 {
+    luminance(emit.value(0.5, 0.5, light.centroid())) * light.area()
+}
+
+/// Add the lights in `object`, which `transform` takes into the world, counting the
+/// emitters that cannot be sampled in `skipped`
+fn gather(
+    object: &HittableObject,
+    transform: &Transform,
+    found: &mut Vec<(Light, f32, PrimitiveKey)>,
+    skipped: &mut usize,
+) {
+    let emitter = |material: &MaterialType| match material {
+        MaterialType::DiffuseLight(emit) => Some(emit.clone()),
+        _ => None,
+    };
+    let mut add = |light: Light, emit: TextureType, key: PrimitiveKey| {
+        let power = power(&light, &emit);
+        found.push((light, power, key));
+    };
+    match object {
+        HittableObject::Sphere(center, radius, material) => {
+            if let Some(emit) = emitter(material) {
+                match uniform_scale(transform) {
+                    Some(scale) => add(
+                        Light::Sphere(transform.point(*center), scale * radius.abs()),
+                        emit,
+                        primitive_key(material, 0),
+                    ),
+                    None => *skipped += 1,
+                }
+            }
+        }
+        HittableObject::Rect(rect, material) => {
+            if let Some(emit) = emitter(material) {
+                let (corner, a, b) = rect.corners();
+                add(
+                    Light::Parallelogram(
+                        transform.point(corner),
+                        transform.vector(a),
+                        transform.vector(b),
+                    ),
+                    emit,
+                    primitive_key(material, 0),
+                );
+            }
+        }
+        HittableObject::Triangle(p, material) => {
+            if let Some(emit) = emitter(material) {
+                add(
+                    Light::Triangle(p.map(|p| transform.point(p))),
+                    emit,
+                    primitive_key(material, 0),
+                );
+            }
+        }
+        HittableObject::Mesh(mesh) => {
+            if let Some(emit) = emitter(mesh.material()) {
+                for i in 0..mesh.triangles().len() {
+                    let p = mesh.corners(i).map(|p| transform.point(p));
+                    add(
+                        Light::Triangle(p),
+                        emit.clone(),
+                        primitive_key(mesh.material(), i),
+                    );
+                }
+            }
+        }
+        HittableObject::MeshTriangle(mesh, i) => {
+            if let Some(emit) = emitter(mesh.material()) {
+                add(
+                    Light::Triangle(mesh.corners(*i).map(|p| transform.point(p))),
+                    emit,
+                    primitive_key(mesh.material(), *i),
+                );
+            }
+        }
+        HittableObject::HittableList(objects) => objects
+            .iter()
+            .for_each(|object| gather(object, transform, found, skipped)),
+        HittableObject::Bvh(bvh) => bvh
+            .objects()
+            .for_each(|object| gather(object, transform, found, skipped)),
+        HittableObject::Instance(instance) => gather(
+            instance.object(),
+            &instance.transform().then(transform),
+            found,
+            skipped,
+        ),
+    }
+}
+
+/// How much `transform` scales everything by, if it keeps shapes the same
+fn uniform_scale(transform: &Transform) -> Option<f32> {
+    let [x, y, z] = [
+        transform.vector(Vec3::new(1.0, 0.0, 0.0)),
+        transform.vector(Vec3::new(0.0, 1.0, 0.0)),
+        transform.vector(Vec3::new(0.0, 0.0, 1.0)),
+    ];
+    let scale = x.length();
+    let tolerance = 1e-4 * scale * scale;
+    let same = |a: f32, b: f32| (a - b).abs() <= tolerance;
+    match same(y.length_squared(), scale * scale)
+        && same(z.length_squared(), scale * scale)
+        && same(x.dot(y), 0.0)
+        && same(y.dot(z), 0.0)
+        && same(z.dot(x), 0.0)
+    {
+        true => Some(scale),
+        false => None,
+    }
+}
+
+#[test]
+fn test_lights() {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use crate::hittable::Hittable;
+    use crate::instance::Instance;
+    use crate::mesh::Mesh;
+    use crate::rect::{Plane, Rect};
+    use crate::vec3::Color;
+    use std::sync::Arc;
+
+    let lamp = MaterialType::DiffuseLight(Color::new_singleton(4.0).into());
+    let grey = MaterialType::Lambertian(Color::new_singleton(0.5).into());
+    // A ball lamp doubled in size by an instance, and a square lamp above a grey ball
+    let world = HittableObject::HittableList(vec![
+        HittableObject::Instance(Box::new(Instance::new(
+            Arc::new(HittableObject::Sphere(Point::new_dfl(), 0.5, lamp.clone())),
+            Transform::scale(Vec3::new_singleton(2.0))
+                .unwrap()
+                .then(&Transform::translate(Vec3::new(0.0, 0.0, -10.0))),
+        ))),
+        HittableObject::Rect(Rect::new(Plane::Xz, (-1.0, -1.0), (1.0, 1.0), 5.0), lamp),
+        HittableObject::Sphere(Point::new_dfl(), 1.0, grey),
+    ]);
+    let lights = Lights::new(&world);
+    assert_eq!(
+        lights.lights(),
+        &[
+            Light::Sphere(Point::new(0.0, 0.0, -10.0), 1.0),
+            Light::Parallelogram(
+                Point::new(-1.0, 5.0, -1.0),
+                Vec3::new(2.0, 0.0, 0.0),
+                Vec3::new(0.0, 0.0, 2.0)
+            ),
+        ]
+    );
+
+    // Weighting each sample by its density measures the solid angle the lights fill,
+    // and the density agrees with looking it up along the ray
+    let mut rng = StdRng::seed_from_u64(3);
+    let from = Point::new_dfl();
+    let n = 20000;
+    let mut solid_angle = 0.0;
+    for _ in 0..n {
+        let (point, pdf) = lights
+            .sample(from, rng.gen(), rng.gen(), rng.gen())
+            .unwrap();
+        let r = Ray::new(from, point - from);
+        let rec = world.hit(r, 1.0 - 1e-3, 1.0 + 1e-3).unwrap();
+        assert!((lights.pdf(r, &rec) - pdf).abs() < 1e-3 * pdf);
+        solid_angle += 1.0 / pdf / n as f32;
+    }
+    let sphere = 2.0 * PI * (1.0 - (1.0 - 0.01_f32).sqrt());
+    // The square subtends 4 arctan(a b / (d sqrt(a^2 + b^2 + d^2))) for half sides a, b
+    let square = 4.0 * (1.0 / (5.0 * 27.0_f32.sqrt())).atan();
+    let expected = sphere + square;
+    assert!((solid_angle - expected).abs() < 0.03 * expected);
+    // The grey ball is no light
+    let r = Ray::new(from, Vec3::new(1.0, 0.0, 0.0));
+    assert_eq!(
+        lights.pdf(r, &world.hit(r, 1e-3, f32::INFINITY).unwrap()),
+        0.0
+    );
+
+    // Inside a light, every point on it can be picked
+    let ball_lamp = HittableObject::Sphere(
+        Point::new_dfl(),
+        2.0,
+        MaterialType::DiffuseLight(Color::new_singleton(1.0).into()),
+    );
+    let inside = Lights::new(&ball_lamp);
+    let (point, pdf) = inside.sample(from, 0.3, 0.2, 0.7).unwrap();
+    assert!((point.length() - 2.0).abs() < 1e-5);
+    assert!((pdf - 1.0 / (4.0 * PI)).abs() < 1e-5);
+    assert_eq!(lights.skipped(), 0);
+
+    // A ball lamp squashed into an ellipsoid is left out, and counted
+    let ellipsoid = HittableObject::Instance(Box::new(Instance::new(
+        Arc::new(HittableObject::Sphere(
+            Point::new_dfl(),
+            1.0,
+            MaterialType::DiffuseLight(Color::new_singleton(1.0).into()),
+        )),
+        Transform::scale(Vec3::new(1.0, 0.5, 1.0)).unwrap(),
+    )));
+    let squashed = Lights::new(&ellipsoid);
+    assert!(squashed.lights().is_empty());
+    assert_eq!(squashed.skipped(), 1);
+
+    // A two triangle mesh lamp shared by two instances is four lights, and a hit finds
+    // the one it landed on
+    let panel = Arc::new(HittableObject::Mesh(Arc::new(
+        Mesh::new(
+            vec![
+                Point::new(-1.0, -1.0, 0.0),
+                Point::new(1.0, -1.0, 0.0),
+                Point::new(1.0, 1.0, 0.0),
+                Point::new(-1.0, 1.0, 0.0),
+            ],
+            Vec::new(),
+            Vec::new(),
+            Vec::new(),
+            vec![[0, 1, 2], [0, 2, 3]],
+            MaterialType::DiffuseLight(Color::new_singleton(4.0).into()),
+        )
+        .unwrap(),
+    )));
+    let panels = HittableObject::HittableList(
+        [-3.0, 3.0]
+            .iter()
+            .map(|&z| {
+                HittableObject::Instance(Box::new(Instance::new(
+                    panel.clone(),
+                    Transform::translate(Vec3::new(0.0, 0.0, z)),
+                )))
+            })
+            .collect(),
+    );
+    let lights = Lights::new(&panels);
+    assert_eq!(lights.lights().len(), 4);
+    for _ in 0..100 {
+        let (point, pdf) = lights
+            .sample(from, rng.gen(), rng.gen(), rng.gen())
+            .unwrap();
+        let r = Ray::new(from, point - from);
+        let rec = panels.hit(r, 1e-3, f32::INFINITY).unwrap();
+        assert!((lights.pdf(r, &rec) - pdf).abs() < 1e-3 * pdf);
+    }
+}
@@ -1,65 +1,220 @@
-use crate::{camera::Camera, color::print_output, render::render_scene};
+use std::io::{self, BufWriter, Write};
+use std::process;
+
+use crate::bvh::Bvh;
+use crate::camera::CameraSettings;
+use crate::cli::{
+    parse_args, Accelerator, CliError, Command, CompareConfig, Config, ConvertConfig, ScenePreset,
+    SceneSource, USAGE,
+};
+use crate::hittable::HittableObject;
+use crate::image::{
+    load_hdr_image, load_ldr_image, save_image, write_image, ImageFormat, OutputOptions,
+};
+use crate::light::Lights;
+use crate::render::{render_scene, Background};
+use crate::scene::Scene;
+use crate::scene_file::{load_scene, save_scene, SceneError};
 use rand::prelude::StdRng;
 use rand::SeedableRng;
 
+mod aabb;
+mod bvh;
 mod camera;
-mod color;
+mod cli;
+mod compare;
+mod environment;
+mod framebuffer;
+mod hdr;
 mod hittable;
+mod image;
+mod instance;
+mod light;
 mod material;
+mod mesh;
+mod microfacet;
+mod obj;
+mod perlin;
+mod pfm;
+mod ply;
+mod png;
+mod principled;
+mod quantize;
 mod ray;
+mod rect;
 mod render;
+mod sampling;
 mod scene;
+mod scene_file;
+mod spectrum;
+mod stl;
+mod texture;
+mod tonemap;
 mod utils;
 mod vec3;
+mod zlib;
 
 fn main() {
-    let mut rng: StdRng = SeedableRng::seed_from_u64(0);
-    // let mut rng = thread_rng();
-    // rand::SeedableRng::Seed::rand::SeedableRng::seed_from_u64(0);
-    // thread_rng();
+    let command = match parse_args(std::env::args().skip(1)) {
+        Ok(command) => command,
+        Err(CliError::HelpRequested) => {
+            print!("{}", USAGE);
+            return;
+        }
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+    let result = match &command {
+        Command::Render(config) => {
+            if let Some(threads) = config.threads {
+                rayon::ThreadPoolBuilder::new()
+                    .num_threads(threads)
+                    .build_global()
+                    .expect("thread pool is only built once");
+            }
+            run(config)
+        }
+        Command::Convert(config) => convert(config),
+        Command::Compare(config) => compare(config),
+    };
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
+
+/// Levels per channel in PPM output, unless overridden with --color-size
+const COLOR_SIZE: usize = 256;
+
+fn run(config: &Config) -> Result<(), String> {
+    let mut rng: StdRng = SeedableRng::seed_from_u64(config.seed);
+
+    let mut scene = match &config.scene {
+        SceneSource::Preset(ScenePreset::Random) => {
+            Scene::new(scene::random_scene(&mut rng), CameraSettings::new_random())
+        }
+        SceneSource::Preset(ScenePreset::Img11) => {
+            Scene::new(scene::img_11(), CameraSettings::new_dfl())
+        }
+        SceneSource::Preset(ScenePreset::Debug) => {
+            Scene::new(scene::debug_scene(), CameraSettings::new_debug())
+        }
+        SceneSource::Preset(ScenePreset::Cornell) => {
+            let mut scene = Scene::new(scene::cornell_box(), CameraSettings::new_cornell());
+            // The box is lit only by its ceiling light
+            scene.background = Background::Black;
+            scene.width = Some(300);
+            scene.height = Some(300);
+            scene
+        }
+        SceneSource::File(path) => load_scene(path).map_err(|e| match e {
+            SceneError::Io(_) => format!("{}: {}", path.display(), e),
+            SceneError::Parse { .. } => format!("{}:{}", path.display(), e),
+        })?,
+    };
+    for warning in &scene.warnings {
+        eprintln!("warning: {}", warning);
+    }
+    if let Some(preset) = config.camera {
+        scene.camera = preset.settings();
+    }
+    let settings = config.render_settings(&scene).map_err(|e| e.to_string())?;
+    let output_format = match &config.output {
+        Some(path) => ImageFormat::from_path(path)?,
+        None => ImageFormat::Ppm,
+    };
+    if let Some(path) = &config.export_scene {
+        scene.width = Some(settings.width);
+        scene.height = Some(settings.height);
+        scene.samples_per_pixel = Some(settings.samples_per_pixel);
+        scene.max_depth = Some(settings.max_depth);
+        save_scene(path, &scene).map_err(|e| format!("{}: {}", path.display(), e))?;
+    }
+    let cam = scene
+        .camera
+        .build(settings.width as f32 / settings.height as f32);
+    let world = match config.accelerator {
+        Accelerator::Bvh => {
+            let bvh = Bvh::new(scene.world);
+            if config.stats {
+                eprintln!("{}", bvh.stats());
+            }
+            HittableObject::Bvh(Box::new(bvh))
+        }
+        Accelerator::None => scene.world,
+    };
+    let lights = Lights::new(&world);
+    if lights.skipped() > 0 {
+        eprintln!(
+            "warning: {} light sphere(s) stretched unevenly can only be found by bouncing",
+            lights.skipped()
+        );
+    }
+    if lights.lights().is_empty() && scene.background == Background::Black {
+        eprintln!("warning: nothing lights the scene, so it will render black");
+    }
+    let framebuffer = render_scene(&world, &lights, &scene.background, &settings, cam, &mut rng);
+    let options = &config.output_options;
+    let colorsize = options.color_size.unwrap_or(COLOR_SIZE);
+    match &config.output {
+        Some(path) => save_image(path, &framebuffer, colorsize, output_format, options)
+            .map_err(|e| format!("{}: {}", path.display(), e)),
+        None => {
+            let mut out = BufWriter::new(io::stdout().lock());
+            write_image(&mut out, &framebuffer, colorsize, output_format, options)
+                .and_then(|_| out.flush())
+                .map_err(|e| format!("writing to stdout: {}", e))
+        }
+    }
+}
+
+fn convert(config: &ConvertConfig) -> Result<(), String> {
+    let format = ImageFormat::from_path(&config.output)?;
+    let image =
+        load_hdr_image(&config.input).map_err(|e| format!("{}: {}", config.input.display(), e))?;
+    save_image(
+        &config.output,
+        &image,
+        config.output_options.color_size.unwrap_or(COLOR_SIZE),
+        format,
+        &config.output_options,
+    )
+    .map_err(|e| format!("{}: {}", config.output.display(), e))
+}
 
-    // Image
-    // const COLOR_SIZE: usize = 256;
-    // const ASPECT_RATIO: f32 = 16. / 9.;
-    // const IMAGE_WIDTH: usize = 400;
-    // const IMAGE_HEIGHT: usize = (IMAGE_WIDTH as f32 / ASPECT_RATIO) as usize;
-    // const SAMPLES_PER_PIXEL: usize = 100;
-    // const MAX_DEPTH: usize = 50;
-    // let world = scene::img_11();
-    // let cam = Camera::new_dfl();
+fn compare(config: &CompareConfig) -> Result<(), String> {
+    let load = |path: &std::path::Path| {
+        load_ldr_image(path).map_err(|e| format!("{}: {}", path.display(), e))
+    };
+    let reference = load(&config.reference)?;
+    let test = load(&config.test)?;
+    let diff_format = match &config.diff {
+        Some(path) => Some(ImageFormat::from_path(path)?),
+        None => None,
+    };
+    let comparison = compare::compare(&reference, &test, config.pixels_per_degree)?;
 
-    const COLOR_SIZE: usize = 256;
-    // const SAMPLES_PER_PIXEL: usize = 500;
-    // const ASPECT_RATIO: f32 = 16. / 10.;
-    // const IMAGE_WIDTH: usize = 1920;
-    const SAMPLES_PER_PIXEL: usize = 25;
-    const ASPECT_RATIO: f32 = 16. / 9.;
-    const IMAGE_WIDTH: usize = 240;
-    const IMAGE_HEIGHT: usize = (IMAGE_WIDTH as f32 / ASPECT_RATIO) as usize;
-    const MAX_DEPTH: usize = 20;
-    let world = scene::random_scene(&mut rng);
-    let cam = Camera::new_random(ASPECT_RATIO);
-    let image = render_scene(
-        &world,
-        MAX_DEPTH,
-        IMAGE_WIDTH,
-        IMAGE_HEIGHT,
-        SAMPLES_PER_PIXEL,
-        cam,
-        &mut rng,
-    );
-    print_output(image, IMAGE_WIDTH, IMAGE_HEIGHT, COLOR_SIZE);
+    println!("{:<10}{:>12}{:>12}{:>12}", "", "red", "green", "blue");
+    let row = |name: &str, values: [f32; 3]| {
+        println!(
+            "{:<10}{:>12.6}{:>12.6}{:>12.6}",
+            name, values[0], values[1], values[2]
+        )
+    };
+    row("RMSE", comparison.rmse);
+    row("PSNR (dB)", comparison.psnr);
+    row("SSIM", comparison.ssim);
+    println!("mean FLIP error: {:.6}", comparison.mean_error());
+    if comparison.identical() {
+        println!("images are identical");
+    }
 
-    // //? For debug
-    // const SAMPLES_PER_PIXEL: usize = 100;
-    // let world = scene::debug_scene();
-    // write_color(
-    //     ray_color(
-    //         Ray::new(Point::new(0., 0., 2.), Vec3::new(0., 0., -1.)),
-    //         &world,
-    //         4,
-    //         &mut rng,
-    //     ),
-    //     SAMPLES_PER_PIXEL,
-    // );
+    if let (Some(path), Some(format)) = (&config.diff, diff_format) {
+        let options = OutputOptions::default();
+        save_image(path, &comparison.heatmap(), COLOR_SIZE, format, &options)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+    }
+    Ok(())
 }
@@ -1,90 +1,557 @@
+use std::f32::consts::PI;
+use std::str::FromStr;
+
 use rand::Rng;
 
 use crate::{
     hittable::HitRecord,
-    ray::Ray,
+    microfacet::{fresnel_conductor, fresnel_dielectric, refract, rough_dielectric, Frame, Ggx},
+    principled::Principled,
+    texture::{Texture, TextureType},
     utils::{random, schlick},
     vec3::{Color, Vec3},
 };
 
-#[derive(Clone, Copy, Debug)]
-pub struct ScatterBundle {
-    albedo: Color,
-    ray: Ray,
+/// A direction picked by a material for light to arrive from, with what it does to the
+/// path's throughput
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BsdfSample {
+    weight: Color,
+    wi: Vec3,
+    pdf: f32,
+    delta: bool,
 }
 
-impl ScatterBundle {
-    pub fn new(albedo: Color, ray: Ray) -> Self {
-        Self { albedo, ray }
+impl BsdfSample {
+    /// A direction picked with density `pdf` per unit solid angle
+    pub fn new(weight: Color, wi: Vec3, pdf: f32) -> Self {
+        Self {
+            weight,
+            wi,
+            pdf,
+            delta: false,
+        }
+    }
+
+    /// The one direction a perfectly smooth lobe, picked with chance `chance`, sends
+    /// light into
+    pub fn delta(weight: Color, wi: Vec3, chance: f32) -> Self {
+        Self {
+            weight,
+            wi,
+            pdf: chance,
+            delta: true,
+        }
+    }
+
+    /// Get the bsdf sample's weight, f(wi, wo) times the cosine at wi over the pdf.
+    pub fn weight(&self) -> Color {
+        self.weight
     }
 
-    /// Get the scatter bundle's albedo.
-    pub fn albedo(&self) -> Color {
-        self.albedo
+    /// Get the bsdf sample's direction toward where the light comes from.
+    pub fn wi(&self) -> Vec3 {
+        self.wi
     }
 
-    /// Get the scatter bundle's ray.
-    pub fn ray(&self) -> Ray {
-        self.ray
+    /// Get the bsdf sample's density per unit solid angle, or for a delta lobe the
+    /// chance of picking it.
+    pub fn pdf(&self) -> f32 {
+        self.pdf
+    }
+
+    /// Get if the bsdf sample came from a delta lobe, which no other strategy can find.
+    pub fn is_delta(&self) -> bool {
+        self.delta
     }
 }
+
+/// A surface's response to light. Directions are unit vectors pointing away from the
+/// hit: `wo` back along the incoming ray, toward the viewer, and `wi` toward the light.
 pub trait Material {
-    fn scatter<R: Rng + ?Sized>(
-        &self,
-        r_in: Ray,
-        rec: &HitRecord,
-        rng: &mut R,
-    ) -> Option<ScatterBundle>;
+    /// Pick a direction for light to arrive from, in proportion to how much of it would
+    /// leave toward `wo`, or None if the path ends here
+    fn sample<R: Rng + ?Sized>(&self, wo: Vec3, rec: &HitRecord, rng: &mut R)
+        -> Option<BsdfSample>;
+    /// Light given off at the hit, before anything scattered there is added
+    fn emitted(&self, rec: &HitRecord) -> Color;
+    /// The BSDF f(wi, wo), how much of the light arriving from `wi` leaves toward `wo`.
+    /// Delta lobes give 0, as no direction chosen elsewhere lands on them.
+    fn eval(&self, wo: Vec3, wi: Vec3, rec: &HitRecord) -> Color;
+    /// The density per unit solid angle of `sample` picking `wi`, or 0 for directions
+    /// only a delta lobe sends light into
+    fn pdf(&self, wo: Vec3, wi: Vec3, rec: &HitRecord) -> f32;
+}
+/// Metals measured at the red, green and blue primaries
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConductorPreset {
+    Gold,
+    Copper,
+    Aluminium,
+}
+
+impl ConductorPreset {
+    /// The complex index of refraction `eta + i k`, as eta and k
+    pub fn ior(self) -> (Color, Color) {
+        match self {
+            ConductorPreset::Gold => (
+                Color::new(0.143, 0.374, 1.442),
+                Color::new(3.983, 2.385, 1.603),
+            ),
+            ConductorPreset::Copper => (
+                Color::new(0.200, 0.924, 1.102),
+                Color::new(3.912, 2.452, 2.142),
+            ),
+            ConductorPreset::Aluminium => (
+                Color::new(1.657, 0.880, 0.521),
+                Color::new(9.224, 6.270, 4.837),
+            ),
+        }
+    }
+}
+
+impl FromStr for ConductorPreset {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "gold" => Ok(ConductorPreset::Gold),
+            "copper" => Ok(ConductorPreset::Copper),
+            "aluminium" => Ok(ConductorPreset::Aluminium),
+            _ => Err("expected gold, copper or aluminium".to_string()),
+        }
+    }
+}
+
+/// The index of refraction of glass, which may vary with wavelength
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Ior {
+    Constant(f32),
+    /// `a + b / λ²`, with λ in micrometres
+    Cauchy(f32, f32),
+    /// The square root of `1 + Σ b λ² / (λ² - c)` over three terms, with λ in
+    /// micrometres, as glass makers quote it
+    Sellmeier([f32; 3], [f32; 3]),
+}
+
+impl Ior {
+    /// The wavelength in nanometres an index is quoted at, the yellow sodium d line
+    pub const D_LINE: f32 = 587.6;
+
+    /// The index at `wavelength` nanometres, or at the d line without one
+    pub fn at(self, wavelength: Option<f32>) -> f32 {
+        let l2 = (wavelength.unwrap_or(Ior::D_LINE) * 1e-3).powi(2);
+        match self {
+            Ior::Constant(ior) => ior,
+            Ior::Cauchy(a, b) => a + b / l2,
+            Ior::Sellmeier(b, c) => (1.0
+                + b.iter()
+                    .zip(c.iter())
+                    .map(|(b, c)| b * l2 / (l2 - c))
+                    .sum::<f32>())
+            .sqrt(),
+        }
+    }
+
+    /// Bends each wavelength by a different amount, splitting white light
+    pub fn is_dispersive(self) -> bool {
+        match self {
+            Ior::Constant(_) => false,
+            Ior::Cauchy(_, b) => b != 0.0,
+            Ior::Sellmeier(..) => true,
+        }
+    }
 }
+
+impl FromStr for Ior {
+    type Err = String;
+    /// A constant index, or the Sellmeier coefficients of a common glass
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bk7" => Ok(Ior::Sellmeier(
+                [1.039_612, 0.231_792, 1.010_469],
+                [0.006_000_7, 0.020_017_9, 103.560_7],
+            )),
+            "fused_silica" => Ok(Ior::Sellmeier(
+                [0.696_166, 0.407_943, 0.897_479],
+                [0.004_679_1, 0.013_512_1, 97.934_0],
+            )),
+            "sf11" => Ok(Ior::Sellmeier(
+                [1.737_597, 0.313_747, 1.898_781],
+                [0.013_188_7, 0.062_306_8, 155.236_3],
+            )),
+            _ => match s.parse::<f32>() {
+                Ok(ior) if ior.is_finite() && ior > 0.0 => Ok(Ior::Constant(ior)),
+                _ => Err(format!(
+                    "expected a positive number, bk7, fused_silica or sf11, found '{}'",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum MaterialType {
-    Lambertian(Vec3),
-    Metal(Vec3, f32),
-    Dielectric(f32),
+    Lambertian(TextureType),
+    Metal(TextureType, f32),
+    /// Clear glass of the given index, absorbing each channel at the given rate per unit
+    /// of distance travelled inside
+    Dielectric(Ior, Color),
+    /// Gives off its color from both sides and scatters nothing
+    DiffuseLight(TextureType),
+    /// A metal with complex index of refraction `eta + i k` per channel, given as eta
+    /// and k, and GGX roughness from 0 for a mirror to 1
+    Conductor(Color, Color, f32),
+    /// Glass like a dielectric with GGX roughness, frosted rather than clear
+    RoughDielectric(Ior, Color, f32),
+    Principled(Box<Principled>),
 }
 
-impl MaterialType {}
 impl Material for MaterialType {
-    fn scatter<R: Rng + ?Sized>(
+    fn sample<R: Rng + ?Sized>(
         &self,
-        r_in: Ray,
+        wo: Vec3,
         rec: &HitRecord,
         rng: &mut R,
-    ) -> Option<ScatterBundle> {
+    ) -> Option<BsdfSample> {
         match self {
             MaterialType::Lambertian(albedo) => {
+                let albedo = albedo.value(rec.u(), rec.v(), rec.p()) * rec.tint();
+                // The normal plus a random unit vector is cosine weighted
                 let scatter_dir = rec.normal() + Vec3::random_unit_vector(rng);
-                match scatter_dir.near_zero() {
-                    true => Some(ScatterBundle::new(*albedo, Ray::new(rec.p(), rec.normal()))),
-                    false => Some(ScatterBundle::new(*albedo, Ray::new(rec.p(), scatter_dir))),
-                }
+                let wi = match scatter_dir.near_zero() {
+                    true => rec.normal(),
+                    false => scatter_dir.unit_vector(),
+                };
+                Some(BsdfSample::new(albedo, wi, self.pdf(wo, wi, rec)))
             }
             MaterialType::Metal(albedo, fuzz) => {
-                let reflected = r_in.dir().unit_vector().reflect(rec.normal());
-                let scattered = Ray::new(
-                    rec.p(),
-                    reflected + *fuzz * Vec3::random_in_unit_sphere(rng),
-                );
-                match scattered.dir().dot(rec.normal()) > 0.0 {
-                    true => Some(ScatterBundle::new(*albedo, scattered)),
+                let albedo = albedo.value(rec.u(), rec.v(), rec.p()) * rec.tint();
+                let reflected = (-wo).reflect(rec.normal());
+                if *fuzz <= 0.0 {
+                    return Some(BsdfSample::delta(albedo, reflected, 1.0));
+                }
+                let wi = (reflected + *fuzz * Vec3::random_in_unit_sphere(rng)).unit_vector();
+                match wi.dot(rec.normal()) > 0.0 {
+                    true => Some(BsdfSample::new(albedo, wi, fuzz_pdf(reflected, *fuzz, wi))),
                     false => None,
                 }
             }
-            MaterialType::Dielectric(ir) => {
-                let refrac_ratio = if rec.front_face() { 1.0 / ir } else { *ir };
-                let unit_dir = r_in.dir().unit_vector();
-                let cos = (-1. * unit_dir).dot(rec.normal()).min(1.);
+            MaterialType::Dielectric(ior, _) => {
+                let ir = ior.at(rec.wavelength());
+                let refrac_ratio = if rec.front_face() { 1.0 / ir } else { ir };
+                let unit_dir = -wo;
+                let cos = wo.dot(rec.normal()).min(1.);
                 let cannot_refract = refrac_ratio * (1. - cos * cos).sqrt() > 1.;
-                Some(ScatterBundle::new(
-                    Color::new_singleton(1.0),
-                    Ray::new(
-                        rec.p(),
-                        match cannot_refract || schlick(cos, refrac_ratio) > random(rng) {
-                            true => unit_dir.reflect(rec.normal()),
-                            false => Vec3::refract(unit_dir, rec.normal(), refrac_ratio),
-                        },
-                    ),
-                ))
+                let reflectance = match cannot_refract {
+                    true => 1.0,
+                    false => schlick(cos, refrac_ratio),
+                };
+                let white = Color::new_singleton(1.0);
+                match reflectance > random(rng) {
+                    true => Some(BsdfSample::delta(
+                        white,
+                        unit_dir.reflect(rec.normal()),
+                        reflectance,
+                    )),
+                    false => Some(BsdfSample::delta(
+                        white,
+                        Vec3::refract(unit_dir, rec.normal(), refrac_ratio),
+                        1.0 - reflectance,
+                    )),
+                }
+            }
+            MaterialType::DiffuseLight(_) => None,
+            MaterialType::Principled(principled) => principled.sample(wo, rec, rng),
+            MaterialType::Conductor(eta, k, roughness) => {
+                let frame = Frame::new(rec.normal());
+                let wo = frame.to_local(wo);
+                let ggx = Ggx::new(*roughness);
+                if wo.z() <= 0.0 {
+                    return None;
+                }
+                if ggx.is_smooth() {
+                    let wi = frame.to_world(Vec3::new(-wo.x(), -wo.y(), wo.z()));
+                    return Some(BsdfSample::delta(
+                        fresnel_conductor(wo.z(), *eta, *k),
+                        wi,
+                        1.0,
+                    ));
+                }
+                let h = ggx.sample_visible(wo, random(rng), random(rng));
+                let wi = (-wo).reflect(h);
+                if wi.z() <= 0.0 {
+                    return None;
+                }
+                // f cos / pdf, where D and one G1 cancel against the density
+                let weight = fresnel_conductor(wo.dot(h), *eta, *k) * (ggx.g(wo, wi) / ggx.g1(wo));
+                let pdf = ggx.pdf_visible(wo, h) / (4.0 * wo.dot(h));
+                Some(BsdfSample::new(weight, frame.to_world(wi), pdf))
+            }
+            MaterialType::RoughDielectric(ior, _, roughness) => {
+                let ir = ior.at(rec.wavelength());
+                let frame = Frame::new(rec.normal());
+                let wo = frame.to_local(wo);
+                let ggx = Ggx::new(*roughness);
+                // The index of the far side over that of the side wo is on
+                let eta = if rec.front_face() { ir } else { 1.0 / ir };
+                if wo.z() <= 0.0 {
+                    return None;
+                }
+                let h = match ggx.is_smooth() {
+                    true => Vec3::new(0.0, 0.0, 1.0),
+                    false => ggx.sample_visible(wo, random(rng), random(rng)),
+                };
+                let reflectance = fresnel_dielectric(wo.dot(h), eta);
+                let wi = match reflectance > random(rng) {
+                    true => (-wo).reflect(h),
+                    false => refract(wo, h, eta)?,
+                };
+                if ggx.is_smooth() {
+                    let chance = match wi.z() > 0.0 {
+                        true => reflectance,
+                        false => 1.0 - reflectance,
+                    };
+                    let white = Color::new_singleton(1.0);
+                    return Some(BsdfSample::delta(white, frame.to_world(wi), chance));
+                }
+                let (f, pdf) = rough_dielectric(&ggx, eta, wo, wi);
+                match pdf > 0.0 {
+                    true => Some(BsdfSample::new(
+                        f * (wi.z().abs() / pdf),
+                        frame.to_world(wi),
+                        pdf,
+                    )),
+                    false => None,
+                }
+            }
+        }
+    }
+
+    fn emitted(&self, rec: &HitRecord) -> Color {
+        match self {
+            MaterialType::DiffuseLight(emit) => emit.value(rec.u(), rec.v(), rec.p()) * rec.tint(),
+            _ => Color::new_dfl(),
+        }
+    }
+
+    fn eval(&self, wo: Vec3, wi: Vec3, rec: &HitRecord) -> Color {
+        match self {
+            MaterialType::Conductor(eta, k, roughness) => {
+                let frame = Frame::new(rec.normal());
+                let (wo, wi) = (frame.to_local(wo), frame.to_local(wi));
+                let ggx = Ggx::new(*roughness);
+                if ggx.is_smooth() || wo.z() <= 0.0 || wi.z() <= 0.0 {
+                    return Color::new_dfl();
+                }
+                let h = (wo + wi).unit_vector();
+                fresnel_conductor(wo.dot(h), *eta, *k)
+                    * (ggx.d(h) * ggx.g(wo, wi) / (4.0 * wo.z() * wi.z()))
+            }
+            MaterialType::RoughDielectric(ior, _, roughness) => {
+                let ir = ior.at(rec.wavelength());
+                let frame = Frame::new(rec.normal());
+                let ggx = Ggx::new(*roughness);
+                let eta = if rec.front_face() { ir } else { 1.0 / ir };
+                match ggx.is_smooth() {
+                    true => Color::new_dfl(),
+                    false => rough_dielectric(&ggx, eta, frame.to_local(wo), frame.to_local(wi)).0,
+                }
+            }
+            MaterialType::Principled(principled) => principled.eval(wo, wi, rec),
+            _ => self.eval_reflection(wo, wi, rec),
+        }
+    }
+
+    fn pdf(&self, wo: Vec3, wi: Vec3, rec: &HitRecord) -> f32 {
+        match self {
+            MaterialType::Conductor(_, _, roughness) => {
+                let frame = Frame::new(rec.normal());
+                let (wo, wi) = (frame.to_local(wo), frame.to_local(wi));
+                let ggx = Ggx::new(*roughness);
+                if ggx.is_smooth() || wo.z() <= 0.0 || wi.z() <= 0.0 {
+                    return 0.0;
+                }
+                let h = (wo + wi).unit_vector();
+                ggx.pdf_visible(wo, h) / (4.0 * wo.dot(h))
+            }
+            MaterialType::RoughDielectric(ior, _, roughness) => {
+                let ir = ior.at(rec.wavelength());
+                let frame = Frame::new(rec.normal());
+                let ggx = Ggx::new(*roughness);
+                let eta = if rec.front_face() { ir } else { 1.0 / ir };
+                match ggx.is_smooth() {
+                    true => 0.0,
+                    false => rough_dielectric(&ggx, eta, frame.to_local(wo), frame.to_local(wi)).1,
+                }
+            }
+            MaterialType::Principled(principled) => principled.pdf(wo, wi, rec),
+            _ => self.pdf_reflection(wo, wi, rec),
+        }
+    }
+}
+
+impl MaterialType {
+    /// The rate the inside of the material absorbs each channel per unit of distance
+    pub fn absorption(&self) -> Color {
+        match self {
+            MaterialType::Dielectric(_, absorption)
+            | MaterialType::RoughDielectric(_, absorption, _) => *absorption,
+            _ => Color::new_dfl(),
+        }
+    }
+
+    /// Refracts each wavelength differently, so a path must pick one to follow
+    pub fn is_dispersive(&self) -> bool {
+        match self {
+            MaterialType::Dielectric(ior, _) | MaterialType::RoughDielectric(ior, ..) => {
+                ior.is_dispersive()
+            }
+            _ => false,
+        }
+    }
+
+    /// `eval` for the materials that only reflect, into the hemisphere of the normal
+    fn eval_reflection(&self, wo: Vec3, wi: Vec3, rec: &HitRecord) -> Color {
+        let cos = rec.normal().dot(wi);
+        if cos <= 0.0 {
+            return Color::new_dfl();
+        }
+        match self {
+            MaterialType::Lambertian(albedo) => {
+                albedo.value(rec.u(), rec.v(), rec.p()) * rec.tint() * (1.0 / PI)
             }
+            // Whatever sampling keeps is reflected in full, so f is the albedo scaled
+            // by the density over the cosine
+            MaterialType::Metal(albedo, fuzz) if *fuzz > 0.0 => {
+                albedo.value(rec.u(), rec.v(), rec.p()) * rec.tint() * (self.pdf(wo, wi, rec) / cos)
+            }
+            _ => Color::new_dfl(),
         }
     }
+
+    /// `pdf` for the materials that only reflect, into the hemisphere of the normal
+    fn pdf_reflection(&self, wo: Vec3, wi: Vec3, rec: &HitRecord) -> f32 {
+        let cos = rec.normal().dot(wi);
+        if cos <= 0.0 {
+            return 0.0;
+        }
+        match self {
+            MaterialType::Lambertian(_) => cos / PI,
+            MaterialType::Metal(_, fuzz) if *fuzz > 0.0 => {
+                fuzz_pdf((-wo).reflect(rec.normal()), *fuzz, wi)
+            }
+            _ => 0.0,
+        }
+    }
+}
+
+/// The density per unit solid angle of the direction toward a point picked evenly in
+/// the ball of radius `fuzz` around the unit vector `reflected` being `wi`. That is the
+/// part of the ball's volume along `wi`, from r^2 dr between where it enters and leaves.
+fn fuzz_pdf(reflected: Vec3, fuzz: f32, wi: Vec3) -> f32 {
+    let along = wi.dot(reflected);
+    let discr = along * along - reflected.length_squared() + fuzz * fuzz;
+    if discr < 0.0 {
+        return 0.0;
+    }
+    let (near, far) = ((along - discr.sqrt()).max(0.0), along + discr.sqrt());
+    match far > 0.0 {
+        true => (far.powi(3) - near.powi(3)) / (4.0 * PI * fuzz.powi(3)),
+        false => 0.0,
+    }
+}
+
+#[test]
+fn test_material() {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use crate::ray::Ray;
+    use crate::vec3::Point;
+
+    let mut rng = StdRng::seed_from_u64(6);
+    let up = Vec3::new(0.0, 1.0, 0.0);
+    let r = Ray::new(Point::new(-1.0, 1.0, 0.0), Vec3::new(1.0, -1.0, 0.0));
+    let wo = -r.dir().unit_vector();
+    let red = Color::new(0.8, 0.2, 0.2);
+    let lambertian = MaterialType::Lambertian(red.into());
+    let rough = MaterialType::Metal(red.into(), 0.4);
+    let (eta, k) = ConductorPreset::Gold.ior();
+    let gold = MaterialType::Conductor(eta, k, 0.4);
+    let frosted = MaterialType::RoughDielectric(Ior::Constant(1.5), Color::new_dfl(), 0.4);
+    // A sample's weight is what f and the pdf give for its direction, so light sampling
+    // and bounces agree on how a direction is lit
+    for material in [lambertian, rough.clone(), gold, frosted.clone()].iter() {
+        let rec = HitRecord::new(r, 1.0, up, up, material, 0.0, 0.0);
+        let mut kept = 0;
+        for _ in 0..1000 {
+            if let Some(sample) = material.sample(wo, &rec, &mut rng) {
+                assert!(!sample.is_delta());
+                let (wi, pdf) = (sample.wi(), sample.pdf());
+                assert!((pdf - material.pdf(wo, wi, &rec)).abs() < 1e-3 * pdf);
+                let f = material.eval(wo, wi, &rec);
+                let weight = f * (wi.dot(up).abs() / pdf);
+                assert!((weight - sample.weight()).length() < 1e-3 * weight.length());
+                kept += 1;
+            }
+        }
+        assert!(kept > 900);
+        if material != &frosted {
+            assert_eq!(material.pdf(wo, -up, &rec), 0.0);
+        }
+    }
+
+    // Frosted glass passes on nearly all the light, only losing what its facets shadow,
+    // and most of it goes through
+    let rec = HitRecord::new(r, 1.0, up, up, &frosted, 0.0, 0.0);
+    let n = 4000;
+    let (mut total, mut through) = (0.0, 0);
+    for _ in 0..n {
+        if let Some(sample) = frosted.sample(wo, &rec, &mut rng) {
+            total += sample.weight().x() / n as f32;
+            through += (sample.wi().dot(up) < 0.0) as usize;
+        }
+    }
+    assert!(total > 0.9 && total <= 1.0);
+    assert!(through > n * 8 / 10);
+
+    // A fuzzy metal's density covers every direction its fuzz reaches, once
+    let rec = HitRecord::new(r, 1.0, up, up, &rough, 0.0, 0.0);
+    let reflected = Vec3::new(1.0, 1.0, 0.0).unit_vector();
+    let n = 200;
+    let mut total = 0.0;
+    for i in 0..n {
+        for j in 0..n {
+            let z = 1.0 - 2.0 * (i as f32 + 0.5) / n as f32;
+            let phi = 2.0 * PI * (j as f32 + 0.5) / n as f32;
+            let s = (1.0 - z * z).sqrt();
+            let wi = Vec3::new(s * phi.cos(), s * phi.sin(), z);
+            total += fuzz_pdf(reflected, 0.4, wi) * 4.0 * PI / (n * n) as f32;
+        }
+    }
+    assert!((total - 1.0).abs() < 0.01);
+    assert!(rough.pdf(wo, reflected, &rec) > rough.pdf(wo, up, &rec));
+
+    // Mirrors and glass only send light one way, which nothing else can sample
+    let mirror = MaterialType::Metal(red.into(), 0.0);
+    let rec = HitRecord::new(r, 1.0, up, up, &mirror, 0.0, 0.0);
+    let sample = mirror.sample(wo, &rec, &mut rng).unwrap();
+    assert!(sample.is_delta());
+    assert!((sample.wi() - reflected).length() < 1e-6);
+    assert_eq!(mirror.eval(wo, reflected, &rec), Color::new_dfl());
+    assert_eq!(mirror.pdf(wo, reflected, &rec), 0.0);
+    let glass = MaterialType::Dielectric(Ior::Constant(1.5), Color::new_dfl());
+    let rec = HitRecord::new(r, 1.0, up, up, &glass, 0.0, 0.0);
+    assert!(glass.sample(wo, &rec, &mut rng).unwrap().is_delta());
+
+    // Glass bends blue more than red, and a constant index has no colors to split
+    let bk7 = "bk7".parse::<Ior>().unwrap();
+    assert!((bk7.at(None) - 1.5168).abs() < 1e-3);
+    assert!(bk7.at(Some(450.0)) > bk7.at(Some(650.0)));
+    assert!(bk7.is_dispersive());
+    assert_eq!("1.5".parse::<Ior>(), Ok(Ior::Constant(1.5)));
+    assert!(!Ior::Constant(1.5).is_dispersive());
+    assert!("-1".parse::<Ior>().is_err());
+    assert!((Ior::Cauchy(1.5, 0.01).at(Some(500.0)) - 1.54).abs() < 1e-5);
 }
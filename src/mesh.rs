@@ -0,0 +1,285 @@
+//! Triangles, on their own or as indexed meshes sharing vertex buffers.
+
+use crate::aabb::Aabb;
+use crate::hittable::HitRecord;
+use crate::material::MaterialType;
+use crate::ray::Ray;
+use crate::vec3::{Color, Point, Vec3};
+
+/// Where a ray crosses the triangle `p`, as the ray parameter and the weights of each
+/// corner, or None if it misses or the hit is outside `tmin..tmax`.
+///
+/// This is the watertight test of Woop, Benthin and Wald (2013): the triangle is moved
+/// into a space where the ray runs along +z from the origin, so deciding which side of
+/// each edge the ray passes is a 2D cross product that neighbouring triangles agree on
+/// exactly, and rays cannot slip through shared edges.
+pub fn intersect_triangle(r: Ray, p: [Point; 3], tmin: f32, tmax: f32) -> Option<(f32, [f32; 3])> {
+    let dir = r.dir();
+    // Make z the dimension where the direction is largest, keeping the winding
+    let kz = match (dir.x().abs(), dir.y().abs(), dir.z().abs()) {
+        (x, y, z) if x >= y && x >= z => 0,
+        (_, y, z) if y >= z => 1,
+        _ => 2,
+    };
+    let (kx, ky) = match dir[kz] < 0.0 {
+        true => ((kz + 2) % 3, (kz + 1) % 3),
+        false => ((kz + 1) % 3, (kz + 2) % 3),
+    };
+    let (sx, sy, sz) = (dir[kx] / dir[kz], dir[ky] / dir[kz], 1.0 / dir[kz]);
+    let [a, b, c] = [p[0] - r.orig(), p[1] - r.orig(), p[2] - r.orig()];
+    let shear = |v: Vec3| (v[kx] - sx * v[kz], v[ky] - sy * v[kz]);
+    let ((ax, ay), (bx, by), (cx, cy)) = (shear(a), shear(b), shear(c));
+
+    let mut e = [cx * by - cy * bx, ax * cy - ay * cx, bx * ay - by * ax];
+    // Fall back to double precision when the ray passes exactly through an edge
+    if e.contains(&0.0) {
+        let cross = |px: f32, py: f32, qx: f32, qy: f32| {
+            (px as f64 * qy as f64 - py as f64 * qx as f64) as f32
+        };
+        e = [
+            cross(cx, cy, bx, by),
+            cross(ax, ay, cx, cy),
+            cross(bx, by, ax, ay),
+        ];
+    }
+    let [u, v, w] = e;
+    if (u < 0.0 || v < 0.0 || w < 0.0) && (u > 0.0 || v > 0.0 || w > 0.0) {
+        return None;
+    }
+    let det = u + v + w;
+    if det == 0.0 {
+        return None;
+    }
+    let t = (u * sz * a[kz] + v * sz * b[kz] + w * sz * c[kz]) / det;
+    match t > tmin && t < tmax {
+        true => Some((t, [u / det, v / det, w / det])),
+        false => None,
+    }
+}
+
+pub fn triangle_bounding_box(p: [Point; 3]) -> Aabb {
+    // Pad the box so triangles lying in an axis plane still have some volume to hit
+    let bbox = Aabb::new(p[0], p[1]).grow(p[2]);
+    let pad = Vec3::new_singleton(1e-4);
+    Aabb::new(bbox.min() - pad, bbox.max() + pad)
+}
+
+/// A single flat triangle, whose UVs are the weights of its second and third corners
+pub fn hit_triangle<'a>(
+    p: &[Point; 3],
+    material: &'a MaterialType,
+    r: Ray,
+    tmin: f32,
+    tmax: f32,
+) -> Option<HitRecord<'a>> {
+    let (t, [_, u, v]) = intersect_triangle(r, *p, tmin, tmax)?;
+    let normal = (p[1] - p[0]).cross(p[2] - p[0]).unit_vector();
+    Some(HitRecord::new(r, t, normal, normal, material, u, v))
+}
+
+/// Triangles sharing one set of vertex buffers and one material. Normals, UVs and
+/// colors are each either given for every vertex or not at all. Vertex colors tint the
+/// material's albedo.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mesh {
+    positions: Vec<Point>,
+    normals: Vec<Vec3>,
+    uvs: Vec<[f32; 2]>,
+    colors: Vec<Color>,
+    triangles: Vec<[usize; 3]>,
+    material: MaterialType,
+}
+
+impl Mesh {
+    pub fn new(
+        positions: Vec<Point>,
+        normals: Vec<Vec3>,
+        uvs: Vec<[f32; 2]>,
+        colors: Vec<Color>,
+        triangles: Vec<[usize; 3]>,
+        material: MaterialType,
+    ) -> Result<Self, String> {
+        if !normals.is_empty() && normals.len() != positions.len() {
+            return Err(format!(
+                "{} normals given for {} vertices",
+                normals.len(),
+                positions.len()
+            ));
+        }
+        if !uvs.is_empty() && uvs.len() != positions.len() {
+            return Err(format!(
+                "{} uvs given for {} vertices",
+                uvs.len(),
+                positions.len()
+            ));
+        }
+        if !colors.is_empty() && colors.len() != positions.len() {
+            return Err(format!(
+                "{} colors given for {} vertices",
+                colors.len(),
+                positions.len()
+            ));
+        }
+        if let Some(i) = triangles.iter().flatten().find(|&&i| i >= positions.len()) {
+            return Err(format!(
+                "vertex index {} is out of range for {} vertices",
+                i,
+                positions.len()
+            ));
+        }
+        Ok(Self {
+            positions,
+            normals,
+            uvs,
+            colors,
+            triangles,
+            material,
+        })
+    }
+
+    /// This mesh with every triangle in `material`
+    pub fn with_material(self, material: MaterialType) -> Self {
+        Self { material, ..self }
+    }
+
+    /// Get the mesh's vertex positions.
+    pub fn positions(&self) -> &[Point] {
+        &self.positions
+    }
+
+    /// Get the mesh's vertex normals, empty if it has none.
+    pub fn normals(&self) -> &[Vec3] {
+        &self.normals
+    }
+
+    /// Get the mesh's vertex uvs, empty if it has none.
+    pub fn uvs(&self) -> &[[f32; 2]] {
+        &self.uvs
+    }
+
+    /// Get the mesh's vertex colors, empty if it has none.
+    pub fn colors(&self) -> &[Color] {
+        &self.colors
+    }
+
+    /// Get the mesh's triangles, as indices into the vertex buffers.
+    pub fn triangles(&self) -> &[[usize; 3]] {
+        &self.triangles
+    }
+
+    /// Get the mesh's material.
+    pub fn material(&self) -> &MaterialType {
+        &self.material
+    }
+
+    /// The positions of the corners of `triangle`
+    pub fn corners(&self, triangle: usize) -> [Point; 3] {
+        let [a, b, c] = self.triangles[triangle];
+        [self.positions[a], self.positions[b], self.positions[c]]
+    }
+
+    pub fn bounding_box_of(&self, triangle: usize) -> Aabb {
+        triangle_bounding_box(self.corners(triangle))
+    }
+
+    /// Intersect one triangle, interpolating the vertex normals and UVs at the hit
+    pub fn hit_triangle(
+        &self,
+        triangle: usize,
+        r: Ray,
+        tmin: f32,
+        tmax: f32,
+    ) -> Option<HitRecord<'_>> {
+        let p = self.corners(triangle);
+        let (t, w) = intersect_triangle(r, p, tmin, tmax)?;
+        let geometric = (p[1] - p[0]).cross(p[2] - p[0]).unit_vector();
+        let index = self.triangles[triangle];
+        let (geometric, shading) = match self.normals.is_empty() {
+            true => (geometric, geometric),
+            false => {
+                let shading = (0..3)
+                    .map(|k| w[k] * self.normals[index[k]])
+                    .fold(Vec3::new_dfl(), |sum, n| sum + n)
+                    .unit_vector();
+                // Trust the vertex normals over the winding for which side is outside
+                match geometric.dot(shading) < 0.0 {
+                    true => (-geometric, shading),
+                    false => (geometric, shading),
+                }
+            }
+        };
+        let (u, v) = match self.uvs.is_empty() {
+            true => (w[1], w[2]),
+            false => (0..3).fold((0.0, 0.0), |(u, v), k| {
+                let [tu, tv] = self.uvs[index[k]];
+                (u + w[k] * tu, v + w[k] * tv)
+            }),
+        };
+        let hit =
+            HitRecord::new(r, t, geometric, shading, &self.material, u, v).with_triangle(triangle);
+        match self.colors.is_empty() {
+            true => Some(hit),
+            false => Some(
+                hit.with_tint(
+                    (0..3)
+                        .map(|k| w[k] * self.colors[index[k]])
+                        .fold(Color::new_dfl(), |sum, c| sum + c),
+                ),
+            ),
+        }
+    }
+
+    pub fn hit(&self, r: Ray, tmin: f32, tmax: f32) -> Option<HitRecord<'_>> {
+        (0..self.triangles.len()).fold(None, |closest, i| {
+            let tmax = closest.as_ref().map_or(tmax, |hit: &HitRecord| hit.t());
+            self.hit_triangle(i, r, tmin, tmax).or(closest)
+        })
+    }
+
+    pub fn bounding_box(&self) -> Option<Aabb> {
+        (0..self.triangles.len())
+            .map(|i| self.bounding_box_of(i))
+            .reduce(Aabb::union)
+    }
+}
+
+#[test]
+fn test_watertight_triangles() {
+    // A unit square split along its diagonal; rays straight down the shared edge and
+    // through the shared corners must hit exactly one or both triangles, never neither
+    let corners = [
+        Point::new(0.0, 0.0, 0.0),
+        Point::new(1.0, 0.0, 0.0),
+        Point::new(1.0, 1.0, 0.0),
+        Point::new(0.0, 1.0, 0.0),
+    ];
+    let mesh = Mesh::new(
+        corners.to_vec(),
+        Vec::new(),
+        vec![[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]],
+        Vec::new(),
+        vec![[0, 1, 2], [0, 2, 3]],
+        MaterialType::Lambertian(Vec3::new_singleton(0.5).into()),
+    )
+    .unwrap();
+    for i in 0..=100 {
+        let x = i as f32 / 100.0;
+        let r = Ray::new(Point::new(x, x, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        let hit = mesh
+            .hit(r, 0.001, f32::INFINITY)
+            .expect("ray slipped through");
+        assert!((hit.t() - 1.0).abs() < 1e-6);
+        assert!((hit.u() - x).abs() < 1e-5 && (hit.v() - x).abs() < 1e-5);
+    }
+    let miss = Ray::new(Point::new(1.5, 0.5, 1.0), Vec3::new(0.0, 0.0, -1.0));
+    assert!(mesh.hit(miss, 0.001, f32::INFINITY).is_none());
+    assert!(Mesh::new(
+        corners.to_vec(),
+        Vec::new(),
+        Vec::new(),
+        Vec::new(),
+        vec![[0, 1, 4]],
+        mesh.material().clone()
+    )
+    .is_err());
+}
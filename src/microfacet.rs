@@ -0,0 +1,252 @@
+//! Rough surfaces as a sea of tiny mirrors, with the GGX (Trowbridge-Reitz)
+//! distribution of their orientations, and the Fresnel reflectance of each facet.
+//!
+//! Directions are in a local frame around the surface normal, which is +z.
+
+use std::f32::consts::PI;
+
+use crate::vec3::{Color, Vec3};
+
+/// Directions around a surface normal, which becomes +z
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Frame {
+    s: Vec3,
+    t: Vec3,
+    n: Vec3,
+}
+
+impl Frame {
+    /// `n` must be a unit vector
+    pub fn new(n: Vec3) -> Self {
+        let (s, t) = n.basis();
+        Self { s, t, n }
+    }
+
+    pub fn to_local(self, v: Vec3) -> Vec3 {
+        Vec3::new(v.dot(self.s), v.dot(self.t), v.dot(self.n))
+    }
+
+    pub fn to_world(self, v: Vec3) -> Vec3 {
+        v.x() * self.s + v.y() * self.t + v.z() * self.n
+    }
+}
+
+/// The facet orientations of an isotropic rough surface
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ggx {
+    alpha: f32,
+}
+
+impl Ggx {
+    /// `roughness` from 0 for a mirror to 1, squared to give the slope spread alpha so
+    /// that equal steps look about evenly rougher
+    pub fn new(roughness: f32) -> Self {
+        Self {
+            alpha: roughness * roughness,
+        }
+    }
+
+    /// Too smooth to sample as a spread of facets without the density overflowing
+    pub fn is_smooth(&self) -> bool {
+        self.alpha < 1e-3
+    }
+
+    /// The density of facets facing `h`, per unit solid angle of projected area
+    pub fn d(&self, h: Vec3) -> f32 {
+        let a2 = self.alpha * self.alpha;
+        let t = h.z() * h.z() * (a2 - 1.0) + 1.0;
+        match h.z() > 0.0 {
+            true => a2 / (PI * t * t),
+            false => 0.0,
+        }
+    }
+
+    /// Smith's ratio of hidden to visible facet area seen from `w`
+    fn lambda(&self, w: Vec3) -> f32 {
+        let cos2 = w.z() * w.z();
+        let tan2 = (1.0 - cos2).max(0.0) / cos2;
+        match tan2.is_finite() {
+            true => 0.5 * ((1.0 + self.alpha * self.alpha * tan2).sqrt() - 1.0),
+            false => f32::INFINITY,
+        }
+    }
+
+    /// The fraction of facets facing `w` that are not hidden by others
+    pub fn g1(&self, w: Vec3) -> f32 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    /// The fraction of facets seen from both `wo` and `wi`, allowing for facets high
+    /// enough to be seen from one being more likely seen from the other
+    pub fn g(&self, wo: Vec3, wi: Vec3) -> f32 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// A facet normal picked in proportion to how much of it `wo` sees (Heitz 2018),
+    /// which keeps the weights of rough samples near 1
+    pub fn sample_visible(&self, wo: Vec3, u0: f32, u1: f32) -> Vec3 {
+        // Stretch to where the surface is a hemisphere, and sample its projected disk
+        let v = Vec3::new(self.alpha * wo.x(), self.alpha * wo.y(), wo.z()).unit_vector();
+        let len2 = v.x() * v.x() + v.y() * v.y();
+        let t1 = match len2 > 0.0 {
+            true => Vec3::new(-v.y(), v.x(), 0.0) / len2.sqrt(),
+            false => Vec3::new(1.0, 0.0, 0.0),
+        };
+        let t2 = v.cross(t1);
+        let (r, phi) = (u0.sqrt(), 2.0 * PI * u1);
+        let (p1, p2) = (r * phi.cos(), r * phi.sin());
+        // Squash the far half of the disk, which the tilted hemisphere hides
+        let s = 0.5 * (1.0 + v.z());
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * p2;
+        let n = p1 * t1 + p2 * t2 + (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt() * v;
+        Vec3::new(self.alpha * n.x(), self.alpha * n.y(), n.z().max(0.0)).unit_vector()
+    }
+
+    /// The density per unit solid angle of `sample_visible` picking `h`
+    pub fn pdf_visible(&self, wo: Vec3, h: Vec3) -> f32 {
+        match wo.z() > 0.0 {
+            true => self.g1(wo) * wo.dot(h).max(0.0) * self.d(h) / wo.z(),
+            false => 0.0,
+        }
+    }
+}
+
+/// The fraction of light reflected at an angle with cosine `cos` to the normal by a
+/// boundary into a medium of relative index `eta`. A negative `cos` comes from inside.
+pub fn fresnel_dielectric(cos: f32, eta: f32) -> f32 {
+    let (cos, eta) = match cos < 0.0 {
+        true => (-cos.max(-1.0), 1.0 / eta),
+        false => (cos.min(1.0), eta),
+    };
+    let sin2_t = (1.0 - cos * cos) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let parallel = (eta * cos - cos_t) / (eta * cos + cos_t);
+    let perpendicular = (cos - eta * cos_t) / (cos + eta * cos_t);
+    0.5 * (parallel * parallel + perpendicular * perpendicular)
+}
+
+/// The fraction of light a metal with complex index `eta + i k` reflects, per channel,
+/// at an angle with cosine `cos` to the normal
+pub fn fresnel_conductor(cos: f32, eta: Color, k: Color) -> Color {
+    let channel = |eta: f32, k: f32| {
+        let cos2 = cos.clamp(0.0, 1.0).powi(2);
+        let sin2 = 1.0 - cos2;
+        let t0 = eta * eta - k * k - sin2;
+        let a2_plus_b2 = (t0 * t0 + 4.0 * eta * eta * k * k).sqrt();
+        let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+        let t1 = a2_plus_b2 + cos2;
+        let t2 = 2.0 * cos.clamp(0.0, 1.0) * a;
+        let perpendicular = (t1 - t2) / (t1 + t2);
+        let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+        let t4 = t2 * sin2;
+        let parallel = perpendicular * (t3 - t4) / (t3 + t4);
+        0.5 * (parallel + perpendicular)
+    };
+    Color::new(
+        channel(eta.x(), k.x()),
+        channel(eta.y(), k.y()),
+        channel(eta.z(), k.z()),
+    )
+}
+
+/// `wo` bent through a facet facing `h` into a medium of relative index `eta`, or None
+/// if it is reflected in full
+pub fn refract(wo: Vec3, h: Vec3, eta: f32) -> Option<Vec3> {
+    let cos = wo.dot(h);
+    let sin2_t = (1.0 - cos * cos).max(0.0) / (eta * eta);
+    match sin2_t < 1.0 {
+        true => Some(-wo / eta + (cos / eta - (1.0 - sin2_t).sqrt()) * h),
+        false => None,
+    }
+}
+
+/// f(wi, wo) and the density of sampling wi for rough glass, in the local frame with
+/// `wo` above the surface and `eta` the index below over the index above (Walter et
+/// al. 2007). Like smooth glass, light keeps its radiance crossing the boundary, which
+/// evens out for closed objects.
+pub fn rough_dielectric(ggx: &Ggx, eta: f32, wo: Vec3, wi: Vec3) -> (Color, f32) {
+    if wo.z() <= 0.0 || wi.z() == 0.0 {
+        return (Color::new_dfl(), 0.0);
+    }
+    let reflect = wi.z() > 0.0;
+    // The facet that turns wo into wi, facing up
+    let h = match reflect {
+        true => wo + wi,
+        false => wo + eta * wi,
+    };
+    let h = match h.z() < 0.0 {
+        true => -h.unit_vector(),
+        false => h.unit_vector(),
+    };
+    // Facets seen from behind by either direction cannot connect them
+    if wo.dot(h) <= 0.0 || wi.dot(h) * wi.z() <= 0.0 {
+        return (Color::new_dfl(), 0.0);
+    }
+    let reflectance = fresnel_dielectric(wo.dot(h), eta);
+    let (d, g, visible) = (ggx.d(h), ggx.g(wo, wi), ggx.pdf_visible(wo, h));
+    match reflect {
+        true => (
+            Color::new_singleton(reflectance * d * g / (4.0 * wo.z() * wi.z())),
+            reflectance * visible / (4.0 * wo.dot(h)),
+        ),
+        false => {
+            let denom = (wi.dot(h) + wo.dot(h) / eta).powi(2);
+            let f = (1.0 - reflectance) * d * g * (wi.dot(h) * wo.dot(h)).abs()
+                / (wi.z().abs() * wo.z() * denom);
+            let pdf = (1.0 - reflectance) * visible * wi.dot(h).abs() / denom;
+            (Color::new_singleton(f), pdf)
+        }
+    }
+}
+
+#[test]
+fn test_ggx() {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    let ggx = Ggx::new(0.5);
+    let wo = Vec3::new(0.6, 0.0, 0.8);
+    // Directions over the upper hemisphere, each with the solid angle it stands for
+    let n = 400;
+    let grid = (0..n * n)
+        .map(|i| {
+            let z = ((i / n) as f32 + 0.5) / n as f32;
+            let phi = 2.0 * PI * ((i % n) as f32 + 0.5) / n as f32;
+            let s = (1.0 - z * z).sqrt();
+            let h = Vec3::new(s * phi.cos(), s * phi.sin(), z);
+            (h, 2.0 * PI / (n * n) as f32)
+        })
+        .collect::<Vec<_>>();
+    // Facets cover the surface once when projected onto it
+    let projected = grid.iter().map(|(h, area)| ggx.d(*h) * h.z() * area);
+    assert!((projected.sum::<f32>() - 1.0).abs() < 0.01);
+    let visible = grid.iter().map(|(h, area)| ggx.pdf_visible(wo, *h) * area);
+    assert!((visible.sum::<f32>() - 1.0).abs() < 0.01);
+
+    // Samples face wo, and land where the density says
+    let mut rng = StdRng::seed_from_u64(7);
+    let toward = |h: Vec3| h.x() > 0.0;
+    let m = 20000;
+    let hits = (0..m)
+        .map(|_| ggx.sample_visible(wo, rng.gen(), rng.gen()))
+        .inspect(|h| assert!(h.z() >= 0.0 && wo.dot(*h) >= 0.0))
+        .filter(|h| toward(*h))
+        .count();
+    let expected = grid
+        .iter()
+        .filter(|(h, _)| toward(*h))
+        .map(|(h, area)| ggx.pdf_visible(wo, *h) * area)
+        .sum::<f32>();
+    assert!((hits as f32 / m as f32 - expected).abs() < 0.01);
+
+    // Glass reflects 4% head on and everything past the critical angle from inside
+    assert!((fresnel_dielectric(1.0, 1.5) - 0.04).abs() < 1e-4);
+    assert_eq!(fresnel_dielectric(-0.5, 1.5), 1.0);
+    assert!(fresnel_dielectric(0.0, 1.5) > 0.99);
+    // With no absorption a conductor is a dielectric
+    let f = fresnel_conductor(0.7, Color::new_singleton(1.5), Color::new_dfl());
+    assert!((f.x() - fresnel_dielectric(0.7, 1.5)).abs() < 1e-5);
+}
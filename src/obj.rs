@@ -0,0 +1,504 @@
+//! Wavefront `.obj` models and their `.mtl` material libraries.
+//!
+//! Vertices (`v`), texture coordinates (`vt`), normals (`vn`) and polygonal faces (`f`)
+//! are read, with indices counted from 1 or, when negative, back from the latest one.
+//! Polygons are split into fans of triangles. Every object, group or change of material
+//! starts a new mesh, since a mesh has a single material.
+//!
+//! MTL materials map onto the renderer's materials as closely as they can:
+//! - emissive ones (a non-black `Ke`) become lights of colour `Ke`,
+//! - transparent ones (`d` below 1, or an `illum` model with refraction) become
+//!   dielectrics with index `Ni`,
+//! - reflective ones (an `illum` model with ray traced reflection, or a black `Kd` with a
+//!   coloured `Ks`) become metals of colour `Ks`, rougher the lower `Ns` is,
+//! - and the rest are lambertian with albedo `Kd`, or the image `map_Kd` if it has one.
+//!
+//! Texture maps are found relative to the library. Ones that cannot be loaded, and
+//! unknown materials, are skipped, and the reader returns a warning for each.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::material::{Ior, MaterialType};
+use crate::mesh::Mesh;
+use crate::texture::{ImageTexture, TextureType};
+use crate::vec3::{Point, Vec3};
+
+fn invalid<S: Into<String>>(message: S) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// Join lines ending in a backslash with the next, and drop comments and blank lines,
+/// giving each remaining line with its 1-based number
+fn logical_lines<R: BufRead>(input: R) -> io::Result<Vec<(usize, String)>> {
+    let mut lines = Vec::new();
+    let mut pending: Option<(usize, String)> = None;
+    for (i, line) in input.lines().enumerate() {
+        let line = line?;
+        let line = line.split('#').next().unwrap().trim_end();
+        let (number, mut text) = pending.take().unwrap_or((i + 1, String::new()));
+        match line.strip_suffix('\\') {
+            Some(start) => {
+                text.push_str(start);
+                text.push(' ');
+                pending = Some((number, text));
+            }
+            None => {
+                text.push_str(line);
+                if !text.trim().is_empty() {
+                    lines.push((number, text));
+                }
+            }
+        }
+    }
+    lines.extend(pending.filter(|(_, text)| !text.trim().is_empty()));
+    Ok(lines)
+}
+
+fn parse_floats(line: usize, args: &[&str], min: usize, max: usize) -> io::Result<Vec<f32>> {
+    if args.len() < min || args.len() > max {
+        return Err(invalid(format!(
+            "line {}: expected {} to {} numbers, found {}",
+            line,
+            min,
+            max,
+            args.len()
+        )));
+    }
+    args.iter()
+        .map(|a| match a.parse::<f32>() {
+            Ok(x) if x.is_finite() => Ok(x),
+            _ => Err(invalid(format!(
+                "line {}: expected a number, found '{}'",
+                line, a
+            ))),
+        })
+        .collect()
+}
+
+/// The MTL statements that matter when picking a material
+#[derive(Clone, Debug, PartialEq)]
+struct MtlEntry {
+    diffuse: Vec3,
+    diffuse_map: Option<TextureType>,
+    specular: Vec3,
+    shininess: f32,
+    ior: Option<f32>,
+    emission: Vec3,
+    opacity: f32,
+    illum: Option<u32>,
+}
+
+impl Default for MtlEntry {
+    fn default() -> Self {
+        Self {
+            diffuse: Vec3::new_singleton(0.8),
+            diffuse_map: None,
+            specular: Vec3::new_dfl(),
+            shininess: 0.0,
+            ior: None,
+            emission: Vec3::new_dfl(),
+            opacity: 1.0,
+            illum: None,
+        }
+    }
+}
+
+impl MtlEntry {
+    fn material(&self) -> MaterialType {
+        let transparent =
+            self.opacity < 1.0 || matches!(self.illum, Some(4) | Some(6) | Some(7) | Some(9));
+        let black = |c: Vec3| c.x().max(c.y()).max(c.z()) <= 0.0;
+        let reflective = matches!(self.illum, Some(3) | Some(5) | Some(8))
+            || (black(self.diffuse) && !black(self.specular));
+        if !black(self.emission) {
+            return MaterialType::DiffuseLight(self.emission.into());
+        }
+        match (transparent, reflective) {
+            // An index of 1 would make the surface vanish, so fall back to glass
+            (true, _) => MaterialType::Dielectric(
+                Ior::Constant(self.ior.filter(|&ior| ior > 1.0).unwrap_or(1.5)),
+                Vec3::new_dfl(),
+            ),
+            // The usual conversion of a Phong exponent to a microfacet roughness
+            (false, true) => MaterialType::Metal(
+                self.specular.into(),
+                (2.0 / (self.shininess.max(0.0) + 2.0)).sqrt(),
+            ),
+            (false, false) => MaterialType::Lambertian(match &self.diffuse_map {
+                Some(map) => map.clone(),
+                None => self.diffuse.into(),
+            }),
+        }
+    }
+}
+
+/// A material library's named materials, with warnings about what in it was skipped
+pub type Library = (HashMap<String, MaterialType>, Vec<String>);
+
+/// Read a material library into named materials, loading texture maps by file name
+/// with `texture`
+pub fn read_mtl<R, T>(input: R, mut texture: T) -> io::Result<Library>
+where
+    R: BufRead,
+    T: FnMut(&str) -> io::Result<TextureType>,
+{
+    let mut entries: Vec<(String, MtlEntry)> = Vec::new();
+    let mut warnings = Vec::new();
+    for (line, text) in logical_lines(input)? {
+        let mut words = text.split_whitespace();
+        let keyword = words.next().unwrap();
+        let args = words.collect::<Vec<_>>();
+        if keyword == "newmtl" {
+            match args.as_slice() {
+                [name] => entries.push((name.to_string(), MtlEntry::default())),
+                _ => {
+                    return Err(invalid(format!(
+                        "line {}: expected one material name",
+                        line
+                    )))
+                }
+            }
+            continue;
+        }
+        let entry = match entries.last_mut() {
+            Some((_, entry)) => entry,
+            None => {
+                return Err(invalid(format!(
+                    "line {}: '{}' before any 'newmtl'",
+                    line, keyword
+                )))
+            }
+        };
+        let color = |args: &[&str]| -> io::Result<Vec3> {
+            // A single value is a grey, and spectral or XYZ colours are not supported
+            match parse_floats(line, args, 1, 3)?.as_slice() {
+                [v] => Ok(Vec3::new_singleton(*v)),
+                [r, g, b] => Ok(Vec3::new(*r, *g, *b)),
+                _ => Err(invalid(format!("line {}: expected 1 or 3 numbers", line))),
+            }
+        };
+        let number = |args: &[&str]| -> io::Result<f32> { Ok(parse_floats(line, args, 1, 1)?[0]) };
+        match keyword {
+            "Kd" => entry.diffuse = color(&args)?,
+            // Any options come before the file name
+            "map_Kd" => match args.last() {
+                Some(name) => match texture(name) {
+                    Ok(map) => entry.diffuse_map = Some(map),
+                    Err(e) => warnings.push(format!(
+                        "line {}: texture '{}' is skipped: {}",
+                        line, name, e
+                    )),
+                },
+                None => return Err(invalid(format!("line {}: expected a file name", line))),
+            },
+            "Ks" => entry.specular = color(&args)?,
+            "Ke" => entry.emission = color(&args)?,
+            "Ns" => entry.shininess = number(&args)?,
+            "Ni" => entry.ior = Some(number(&args)?),
+            "d" => entry.opacity = number(&args)?,
+            "Tr" => entry.opacity = 1.0 - number(&args)?,
+            "illum" => entry.illum = Some(number(&args)? as u32),
+            // Ambient colour, texture maps and the rest have nothing to map onto
+            _ => {}
+        }
+    }
+    let materials = entries
+        .into_iter()
+        .map(|(name, entry)| (name, entry.material()))
+        .collect();
+    Ok((materials, warnings))
+}
+
+/// The faces read since the last change of group or material
+struct Part {
+    material: MaterialType,
+    /// Each distinct position/uv/normal index triple gets one vertex in the mesh
+    vertices: HashMap<(usize, Option<usize>, Option<usize>), usize>,
+    corners: Vec<(usize, Option<usize>, Option<usize>)>,
+    triangles: Vec<[usize; 3]>,
+}
+
+impl Part {
+    fn new(material: MaterialType) -> Self {
+        Self {
+            material,
+            vertices: HashMap::new(),
+            corners: Vec::new(),
+            triangles: Vec::new(),
+        }
+    }
+
+    fn vertex(&mut self, corner: (usize, Option<usize>, Option<usize>)) -> usize {
+        let corners = &mut self.corners;
+        *self.vertices.entry(corner).or_insert_with(|| {
+            corners.push(corner);
+            corners.len() - 1
+        })
+    }
+
+    /// Build the mesh, keeping uvs and normals only when every vertex has them
+    fn finish(self, positions: &[Point], uvs: &[[f32; 2]], normals: &[Vec3]) -> Option<Mesh> {
+        if self.triangles.is_empty() {
+            return None;
+        }
+        let uvs = match self.corners.iter().all(|c| c.1.is_some()) {
+            true => self.corners.iter().map(|c| uvs[c.1.unwrap()]).collect(),
+            false => Vec::new(),
+        };
+        let normals = match self.corners.iter().all(|c| c.2.is_some()) {
+            true => self.corners.iter().map(|c| normals[c.2.unwrap()]).collect(),
+            false => Vec::new(),
+        };
+        let positions = self.corners.iter().map(|c| positions[c.0]).collect();
+        Some(
+            Mesh::new(
+                positions,
+                normals,
+                uvs,
+                Vec::new(),
+                self.triangles,
+                self.material,
+            )
+            .unwrap(),
+        )
+    }
+}
+
+/// Read a model, loading material libraries through `library`. Faces without a known
+/// material use `default`. The meshes come with warnings about what was skipped, from
+/// the model and its libraries.
+pub fn read_obj<R: BufRead, F>(
+    input: R,
+    default: &MaterialType,
+    mut library: F,
+) -> io::Result<(Vec<Mesh>, Vec<String>)>
+where
+    F: FnMut(&str) -> io::Result<Library>,
+{
+    let mut positions: Vec<Point> = Vec::new();
+    let mut uvs: Vec<[f32; 2]> = Vec::new();
+    let mut normals: Vec<Vec3> = Vec::new();
+    let mut materials: HashMap<String, MaterialType> = HashMap::new();
+    let mut meshes = Vec::new();
+    let mut warnings = Vec::new();
+    let mut part = Part::new(default.clone());
+
+    for (line, text) in logical_lines(input)? {
+        let mut words = text.split_whitespace();
+        let keyword = words.next().unwrap();
+        let args = words.collect::<Vec<_>>();
+        match keyword {
+            "v" => {
+                // An optional w or vertex colour after the position is ignored
+                let v = parse_floats(line, &args, 3, 7)?;
+                positions.push(Point::new(v[0], v[1], v[2]));
+            }
+            "vt" => {
+                let v = parse_floats(line, &args, 1, 3)?;
+                uvs.push([v[0], v.get(1).copied().unwrap_or(0.0)]);
+            }
+            "vn" => {
+                let v = parse_floats(line, &args, 3, 3)?;
+                normals.push(Vec3::new(v[0], v[1], v[2]).unit_vector());
+            }
+            "f" => {
+                if args.len() < 3 {
+                    return Err(invalid(format!(
+                        "line {}: a face needs at least 3 vertices",
+                        line
+                    )));
+                }
+                let resolve = |index: &str, count: usize, what: &str| -> io::Result<usize> {
+                    let i = index.parse::<i64>().map_err(|_| {
+                        invalid(format!(
+                            "line {}: expected an index, found '{}'",
+                            line, index
+                        ))
+                    })?;
+                    let resolved = match i < 0 {
+                        true => count as i64 + i,
+                        false => i - 1,
+                    };
+                    match i != 0 && resolved >= 0 && resolved < count as i64 {
+                        true => Ok(resolved as usize),
+                        false => Err(invalid(format!(
+                            "line {}: {} index {} is out of range, there are {}",
+                            line, what, i, count
+                        ))),
+                    }
+                };
+                let corners = args
+                    .iter()
+                    .map(|arg| {
+                        let mut parts = arg.split('/');
+                        let p = resolve(parts.next().unwrap(), positions.len(), "vertex")?;
+                        let mut optional = |count: usize, what: &str| match parts.next() {
+                            Some("") | None => Ok(None),
+                            Some(index) => resolve(index, count, what).map(Some),
+                        };
+                        let t = optional(uvs.len(), "texture coordinate")?;
+                        let n = optional(normals.len(), "normal")?;
+                        Ok(part.vertex((p, t, n)))
+                    })
+                    .collect::<io::Result<Vec<_>>>()?;
+                for k in 1..corners.len() - 1 {
+                    part.triangles
+                        .push([corners[0], corners[k], corners[k + 1]]);
+                }
+            }
+            "mtllib" => {
+                for name in args {
+                    let (found, skipped) = library(name)?;
+                    materials.extend(found);
+                    warnings.extend(skipped);
+                }
+            }
+            "usemtl" | "g" | "o" => {
+                let material = match keyword {
+                    "usemtl" => match args.as_slice() {
+                        [name] => materials.get(*name).cloned().unwrap_or_else(|| {
+                            warnings.push(format!("line {}: unknown material '{}'", line, name));
+                            default.clone()
+                        }),
+                        _ => {
+                            return Err(invalid(format!(
+                                "line {}: expected one material name",
+                                line
+                            )))
+                        }
+                    },
+                    _ => part.material.clone(),
+                };
+                let done = std::mem::replace(&mut part, Part::new(material));
+                meshes.extend(done.finish(&positions, &uvs, &normals));
+            }
+            // Smoothing groups, lines, points and free-form geometry are not supported
+            _ => {}
+        }
+    }
+    meshes.extend(part.finish(&positions, &uvs, &normals));
+    Ok((meshes, warnings))
+}
+
+/// Load a model, finding its material libraries next to it. Warnings name the file
+/// they are about.
+pub fn load_obj<P: AsRef<Path>>(
+    path: P,
+    default: &MaterialType,
+) -> io::Result<(Vec<Mesh>, Vec<String>)> {
+    let path = path.as_ref();
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    // Libraries often share one picture between several materials
+    let mut textures = HashMap::<PathBuf, TextureType>::new();
+    let library = |name: &str| {
+        let mtl = dir.join(name);
+        let mtl_dir = mtl.parent().unwrap_or_else(|| Path::new("")).to_path_buf();
+        let texture = |name: &str| {
+            let file = mtl_dir.join(name);
+            match textures.get(&file) {
+                Some(texture) => Ok(texture.clone()),
+                None => {
+                    let texture = TextureType::Image(Arc::new(ImageTexture::load(&file)?));
+                    textures.insert(file, texture.clone());
+                    Ok(texture)
+                }
+            }
+        };
+        let (materials, warnings) = File::open(&mtl)
+            .and_then(|file| read_mtl(BufReader::new(file), texture))
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", mtl.display(), e)))?;
+        let warnings = warnings
+            .into_iter()
+            .map(|w| format!("{}: {}", mtl.display(), w))
+            .collect();
+        Ok((materials, warnings))
+    };
+    let (meshes, warnings) = read_obj(BufReader::new(File::open(path)?), default, library)?;
+    // The libraries' warnings already name their own file
+    let warnings = warnings
+        .into_iter()
+        .map(|w| match w.starts_with("line ") {
+            true => format!("{}: {}", path.display(), w),
+            false => w,
+        })
+        .collect();
+    Ok((meshes, warnings))
+}
+
+#[test]
+fn test_read_obj() {
+    let mtl = "newmtl red\nKd 0.8 0.1 0.1\nnewmtl mirror\nillum 3\nKs 0.9\nNs 1e6\n\
+               newmtl glass\nd 0.1\nNi 1.33\nnewmtl lamp\nKd 0.5\nKe 4 4 3\n\
+               newmtl wood\nKd 0.8\nmap_Kd -s 2 2 1 wood.png\nnewmtl lost\nmap_Kd missing.png\n";
+    let wood = TextureType::Solid(Vec3::new(0.6, 0.4, 0.2));
+    let texture = |name: &str| match name {
+        "wood.png" => Ok(wood.clone()),
+        _ => Err(io::Error::new(io::ErrorKind::NotFound, "not found")),
+    };
+    let (materials, warnings) = read_mtl(mtl.as_bytes(), texture).unwrap();
+    assert_eq!(
+        warnings,
+        vec!["line 17: texture 'missing.png' is skipped: not found".to_string()]
+    );
+    assert_eq!(
+        materials["red"],
+        MaterialType::Lambertian(Vec3::new(0.8, 0.1, 0.1).into())
+    );
+    assert!(matches!(materials["mirror"], MaterialType::Metal(_, fuzz) if fuzz < 0.01));
+    assert_eq!(
+        materials["glass"],
+        MaterialType::Dielectric(Ior::Constant(1.33), Vec3::new_dfl())
+    );
+    assert_eq!(
+        materials["lamp"],
+        MaterialType::DiffuseLight(Vec3::new(4.0, 4.0, 3.0).into())
+    );
+    assert_eq!(materials["wood"], MaterialType::Lambertian(wood.clone()));
+    assert_eq!(
+        materials["lost"],
+        MaterialType::Lambertian(Vec3::new_singleton(0.8).into())
+    );
+
+    // A quad split into two triangles, then a triangle with negative indices and normals
+    // in a second group with another material
+    let obj = "mtllib test.mtl\nv 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nvt 0 0\nvt 1 0\nvt 1 1\n\
+               vt 0 1\nvn 0 0 1\nusemtl red\nf 1/1 2/2 3/3 4/4\ng other\nusemtl glass\n\
+               f -4//1 -3//1 \\\n -2//1\n";
+    let default = MaterialType::Lambertian(Vec3::new_singleton(0.5).into());
+    let (meshes, warnings) = read_obj(obj.as_bytes(), &default, |name| {
+        assert_eq!(name, "test.mtl");
+        read_mtl(mtl.as_bytes(), texture)
+    })
+    .unwrap();
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(meshes.len(), 2);
+    assert_eq!(meshes[0].triangles(), &[[0, 1, 2], [0, 2, 3]]);
+    assert_eq!(meshes[0].uvs()[2], [1.0, 1.0]);
+    assert!(meshes[0].normals().is_empty());
+    assert_eq!(meshes[0].material(), &materials["red"]);
+    assert_eq!(meshes[1].positions()[2], Point::new(1.0, 1.0, 0.0));
+    assert_eq!(meshes[1].normals().len(), 3);
+    assert_eq!(meshes[1].material(), &materials["glass"]);
+
+    let error = read_obj("v 0 0 0\nf 1 2 3".as_bytes(), &default, |_| {
+        Ok((HashMap::new(), Vec::new()))
+    });
+    assert!(error.unwrap_err().to_string().contains("line 2"));
+
+    // A material the libraries do not have falls back to the default, with a warning
+    let (meshes, warnings) = read_obj(
+        "v 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl chrome\nf 1 2 3".as_bytes(),
+        &default,
+        |_| Ok((HashMap::new(), Vec::new())),
+    )
+    .unwrap();
+    assert_eq!(meshes[0].material(), &default);
+    assert_eq!(
+        warnings,
+        vec!["line 4: unknown material 'chrome'".to_string()]
+    );
+}
@@ -0,0 +1,101 @@
+//! Ken Perlin's gradient noise, and the turbulence built from summing its octaves.
+
+use rand::prelude::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+
+use crate::vec3::{Point, Vec3};
+
+const POINT_COUNT: usize = 256;
+
+/// A lattice of random gradients, hashed by three permutations of the lattice indices.
+/// The same seed always gives the same noise, so scenes render the same every time.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Perlin {
+    seed: u64,
+    gradients: Vec<Vec3>,
+    perm: [Vec<usize>; 3],
+}
+
+impl Perlin {
+    pub fn new(seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let gradients = (0..POINT_COUNT)
+            .map(|_| Vec3::random_vec3_range(-1.0, 1.0, &mut rng).unit_vector())
+            .collect();
+        let mut permutation = || {
+            let mut p = (0..POINT_COUNT).collect::<Vec<_>>();
+            p.shuffle(&mut rng);
+            p
+        };
+        let perm = [permutation(), permutation(), permutation()];
+        Self {
+            seed,
+            gradients,
+            perm,
+        }
+    }
+
+    /// Get the perlin's seed.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Smooth noise in about [-1, 1], zero at every lattice point
+    pub fn noise(&self, p: Point) -> f32 {
+        let cell = [p.x().floor(), p.y().floor(), p.z().floor()];
+        let frac = [p.x() - cell[0], p.y() - cell[1], p.z() - cell[2]];
+        // Hermite smoothing hides the lattice's grid lines
+        let smooth = frac.map(|f| f * f * (3.0 - 2.0 * f));
+        let mut accum = 0.0;
+        for corner in 0..8 {
+            let d = [corner & 1, (corner >> 1) & 1, (corner >> 2) & 1];
+            let hash = (0..3)
+                .map(|axis| {
+                    let i = (cell[axis] as i64 + d[axis] as i64) as usize & (POINT_COUNT - 1);
+                    self.perm[axis][i]
+                })
+                .fold(0, |h, p| h ^ p);
+            let offset = Vec3::new(
+                frac[0] - d[0] as f32,
+                frac[1] - d[1] as f32,
+                frac[2] - d[2] as f32,
+            );
+            let weight = (0..3)
+                .map(|axis| match d[axis] {
+                    1 => smooth[axis],
+                    _ => 1.0 - smooth[axis],
+                })
+                .product::<f32>();
+            accum += weight * self.gradients[hash].dot(offset);
+        }
+        accum
+    }
+
+    /// The sum of `depth` octaves of noise, each twice the frequency and half the
+    /// weight of the last
+    pub fn turbulence(&self, p: Point, depth: usize) -> f32 {
+        let (accum, _, _) = (0..depth).fold((0.0, p, 1.0), |(accum, p, weight), _| {
+            (accum + weight * self.noise(p), 2.0 * p, 0.5 * weight)
+        });
+        f32::abs(accum)
+    }
+}
+
+#[test]
+fn test_perlin() {
+    let perlin = Perlin::new(5);
+    assert_eq!(perlin, Perlin::new(5));
+    assert_ne!(perlin, Perlin::new(6));
+    let mut previous = perlin.noise(Point::new(0.5, 0.5, 0.5));
+    for i in 1..100 {
+        let p = Point::new(0.5 + 0.001 * i as f32, 0.5, 0.5);
+        let noise = perlin.noise(p);
+        assert!((-1.0..=1.0).contains(&noise));
+        // Continuous, so nearby points give nearby values
+        assert!((noise - previous).abs() < 0.01);
+        previous = noise;
+    }
+    assert_eq!(perlin.noise(Point::new(3.0, -7.0, 12.0)), 0.0);
+    assert!(perlin.turbulence(Point::new(1.3, 2.1, 0.2), 7) >= 0.0);
+}
@@ -0,0 +1,115 @@
+//! Portable Float Map: a PPM style header followed by raw 32 bit floats, bottom row first.
+
+use std::io::{self, BufRead, Read, Write};
+
+use crate::framebuffer::Framebuffer;
+use crate::vec3::Color;
+
+fn invalid<S: Into<String>>(message: S) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+pub fn write_pfm<W: Write>(out: &mut W, fb: &Framebuffer) -> io::Result<()> {
+    // A negative scale marks the data as little endian
+    write!(out, "PF\n{} {}\n-1.0\n", fb.width(), fb.height())?;
+    for y in (0..fb.height()).rev() {
+        let row = (0..fb.width())
+            .flat_map(|x| {
+                let c = fb.pixel(x, y);
+                [c.x(), c.y(), c.z()]
+            })
+            .flat_map(f32::to_le_bytes)
+            .collect::<Vec<u8>>();
+        out.write_all(&row)?;
+    }
+    Ok(())
+}
+
+/// Read the next whitespace separated header field
+fn read_field<R: BufRead>(input: &mut R) -> io::Result<String> {
+    let mut field = String::new();
+    loop {
+        let mut byte = [0];
+        input.read_exact(&mut byte)?;
+        match (byte[0].is_ascii_whitespace(), field.is_empty()) {
+            (true, true) => continue,
+            (true, false) => return Ok(field),
+            (false, _) => field.push(byte[0] as char),
+        }
+    }
+}
+
+pub fn read_pfm<R: BufRead>(input: &mut R) -> io::Result<Framebuffer> {
+    let channels = match read_field(input)?.as_str() {
+        "PF" => 3,
+        "Pf" => 1,
+        _ => return Err(invalid("not a PFM file")),
+    };
+    let mut number = |what: &str| -> io::Result<String> {
+        read_field(input).map_err(|_| invalid(format!("missing {}", what)))
+    };
+    let width = number("width")?.parse::<usize>();
+    let height = number("height")?.parse::<usize>();
+    let scale = number("scale")?.parse::<f32>();
+    let (width, height, little_endian) = match (width, height, scale) {
+        (Ok(w), Ok(h), Ok(s)) if w > 0 && h > 0 && s != 0.0 => (w, h, s < 0.0),
+        _ => return Err(invalid("bad PFM header")),
+    };
+
+    // Trust the header's size only as far as the data that is actually there
+    let size = width
+        .checked_mul(height)
+        .and_then(|n| n.checked_mul(channels * 4))
+        .ok_or_else(|| invalid("image too large"))?;
+    let mut data = Vec::new();
+    input.by_ref().take(size as u64).read_to_end(&mut data)?;
+    if data.len() < size {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "image data ends early",
+        ));
+    }
+    let floats = data
+        .chunks_exact(4)
+        .map(|b| {
+            let bytes = [b[0], b[1], b[2], b[3]];
+            match little_endian {
+                true => f32::from_le_bytes(bytes),
+                false => f32::from_be_bytes(bytes),
+            }
+        })
+        .collect::<Vec<f32>>();
+    let mut pixels = Vec::with_capacity(width * height);
+    for row in floats.chunks_exact(width * channels).rev() {
+        pixels.extend(row.chunks_exact(channels).map(|p| match p {
+            [r, g, b] => Color::new(*r, *g, *b),
+            _ => Color::new_singleton(p[0]),
+        }));
+    }
+    Ok(Framebuffer::from_pixels(width, height, pixels))
+}
+
+#[test]
+fn test_pfm_round_trip() {
+    let pixels = (0..6)
+        .map(|i| Color::new(i as f32, 1e-6 * i as f32, 1e6 * i as f32))
+        .collect::<Vec<_>>();
+    let fb = Framebuffer::from_pixels(3, 2, pixels);
+    let mut bytes = Vec::new();
+    write_pfm(&mut bytes, &fb).unwrap();
+    assert_eq!(read_pfm(&mut &bytes[..]).unwrap(), fb);
+}
+
+#[test]
+fn test_pfm_bad_size() {
+    // Sizes that overflow, or that the data falls short of, are errors
+    for header in ["PF\n4000000000 4000000000\n-1\n", "PF\n100000 100000\n-1\n"].iter() {
+        let mut data = header.as_bytes().to_vec();
+        data.extend([0; 24].iter());
+        let error = read_pfm(&mut &data[..]).unwrap_err();
+        assert!(matches!(
+            error.kind(),
+            io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof
+        ));
+    }
+}
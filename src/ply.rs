@@ -0,0 +1,379 @@
+//! Stanford `.ply` polygon files, in ASCII or little or big endian binary.
+//!
+//! Positions, and normals, texture coordinates and colors when every vertex has them,
+//! are read from the `vertex` element, and polygons from the `vertex_indices` list of
+//! the `face` element, split into fans of triangles. Other elements are skipped. Colors
+//! are taken to be sRGB encoded, as scanners write them, and tint the mesh's material.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+
+use crate::material::MaterialType;
+use crate::mesh::Mesh;
+use crate::tonemap::Transfer;
+use crate::vec3::{Color, Point, Vec3};
+
+fn invalid<S: Into<String>>(message: S) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> io::Result<Self> {
+        match name {
+            "char" | "int8" => Ok(Scalar::I8),
+            "uchar" | "uint8" => Ok(Scalar::U8),
+            "short" | "int16" => Ok(Scalar::I16),
+            "ushort" | "uint16" => Ok(Scalar::U16),
+            "int" | "int32" => Ok(Scalar::I32),
+            "uint" | "uint32" => Ok(Scalar::U32),
+            "float" | "float32" => Ok(Scalar::F32),
+            "double" | "float64" => Ok(Scalar::F64),
+            _ => Err(invalid(format!("unknown property type '{}'", name))),
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Scalar::I8 | Scalar::U8 => 1,
+            Scalar::I16 | Scalar::U16 => 2,
+            Scalar::I32 | Scalar::U32 | Scalar::F32 => 4,
+            Scalar::F64 => 8,
+        }
+    }
+
+    /// The value meaning full intensity for a color channel of this type
+    fn full_scale(self) -> f64 {
+        match self {
+            Scalar::I8 => 127.0,
+            Scalar::U8 => 255.0,
+            Scalar::I16 => 32767.0,
+            Scalar::U16 => 65535.0,
+            Scalar::I32 => 2_147_483_647.0,
+            Scalar::U32 => 4_294_967_295.0,
+            Scalar::F32 | Scalar::F64 => 1.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Property {
+    Scalar(String, Scalar),
+    /// A count of the first type, then that many values of the second
+    List(String, Scalar, Scalar),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+impl Element {
+    fn find(&self, names: &[&str]) -> Option<usize> {
+        self.properties.iter().position(|p| match p {
+            Property::Scalar(name, _) => names.contains(&name.as_str()),
+            Property::List(..) => false,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+fn read_header<R: BufRead>(input: &mut R) -> io::Result<(Format, Vec<Element>)> {
+    let mut line = String::new();
+    let mut next_line = |input: &mut R| -> io::Result<Vec<String>> {
+        line.clear();
+        match input.read_line(&mut line)? {
+            0 => Err(invalid("the header has no 'end_header'")),
+            _ => Ok(line.split_whitespace().map(String::from).collect()),
+        }
+    };
+    if next_line(input)? != ["ply"] {
+        return Err(invalid("not a PLY file"));
+    }
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    loop {
+        let words = next_line(input)?;
+        let words = words.iter().map(String::as_str).collect::<Vec<_>>();
+        match words.as_slice() {
+            ["end_header"] => break,
+            ["format", name, "1.0"] => {
+                format = Some(match *name {
+                    "ascii" => Format::Ascii,
+                    "binary_little_endian" => Format::BinaryLittleEndian,
+                    "binary_big_endian" => Format::BinaryBigEndian,
+                    _ => return Err(invalid(format!("unknown format '{}'", name))),
+                })
+            }
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count
+                    .parse()
+                    .map_err(|_| invalid(format!("bad element count '{}'", count)))?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count, item, name] => match elements.last_mut() {
+                Some(element) => element.properties.push(Property::List(
+                    name.to_string(),
+                    Scalar::parse(count)?,
+                    Scalar::parse(item)?,
+                )),
+                None => return Err(invalid("a property before any element")),
+            },
+            ["property", ty, name] => match elements.last_mut() {
+                Some(element) => element
+                    .properties
+                    .push(Property::Scalar(name.to_string(), Scalar::parse(ty)?)),
+                None => return Err(invalid("a property before any element")),
+            },
+            ["comment", ..] | ["obj_info", ..] | [] => {}
+            _ => return Err(invalid(format!("bad header line '{}'", words.join(" ")))),
+        }
+    }
+    match format {
+        Some(format) => Ok((format, elements)),
+        None => Err(invalid("the header has no format")),
+    }
+}
+
+/// Values from the body of the file, one at a time
+struct Values<'a> {
+    format: Format,
+    bytes: &'a [u8],
+    tokens: std::str::SplitAsciiWhitespace<'a>,
+}
+
+impl<'a> Values<'a> {
+    fn new(format: Format, bytes: &'a [u8]) -> io::Result<Self> {
+        let text = match format {
+            Format::Ascii => {
+                std::str::from_utf8(bytes).map_err(|_| invalid("the body is not text"))?
+            }
+            _ => "",
+        };
+        Ok(Self {
+            format,
+            bytes,
+            tokens: text.split_ascii_whitespace(),
+        })
+    }
+
+    fn next(&mut self, ty: Scalar) -> io::Result<f64> {
+        if self.format == Format::Ascii {
+            let token = self
+                .tokens
+                .next()
+                .ok_or_else(|| invalid("the file ends early"))?;
+            return token
+                .parse::<f64>()
+                .map_err(|_| invalid(format!("expected a number, found '{}'", token)));
+        }
+        if self.bytes.len() < ty.size() {
+            return Err(invalid("the file ends early"));
+        }
+        let (value, rest) = self.bytes.split_at(ty.size());
+        self.bytes = rest;
+        // Put the bytes in little endian order, then widen
+        let mut le = [0; 8];
+        le[..value.len()].copy_from_slice(value);
+        if self.format == Format::BinaryBigEndian {
+            le[..value.len()].reverse();
+        }
+        let [a, b, c, d, ..] = le;
+        Ok(match ty {
+            Scalar::I8 => a as i8 as f64,
+            Scalar::U8 => a as f64,
+            Scalar::I16 => i16::from_le_bytes([a, b]) as f64,
+            Scalar::U16 => u16::from_le_bytes([a, b]) as f64,
+            Scalar::I32 => i32::from_le_bytes([a, b, c, d]) as f64,
+            Scalar::U32 => u32::from_le_bytes([a, b, c, d]) as f64,
+            Scalar::F32 => f32::from_le_bytes([a, b, c, d]) as f64,
+            Scalar::F64 => f64::from_le_bytes(le),
+        })
+    }
+
+    /// Read one instance of `element`, with a single value for each scalar property
+    /// and the items of each list
+    fn element(&mut self, element: &Element) -> io::Result<Vec<Vec<f64>>> {
+        element
+            .properties
+            .iter()
+            .map(|property| match property {
+                Property::Scalar(_, ty) => Ok(vec![self.next(*ty)?]),
+                Property::List(_, count, item) => {
+                    let n = self.next(*count)?;
+                    (0..n as usize).map(|_| self.next(*item)).collect()
+                }
+            })
+            .collect()
+    }
+}
+
+/// Read a mesh with the material `default`, which any vertex colors tint.
+pub fn read_ply<R: BufRead>(mut input: R, default: &MaterialType) -> io::Result<Mesh> {
+    let (format, elements) = read_header(&mut input)?;
+    let mut body = Vec::new();
+    input.read_to_end(&mut body)?;
+    let mut values = Values::new(format, &body)?;
+
+    let mut positions = Vec::new();
+    let mut normals = Vec::new();
+    let mut uvs = Vec::new();
+    let mut colors = Vec::new();
+    let mut triangles = Vec::new();
+    for element in elements.iter() {
+        match element.name.as_str() {
+            "vertex" => {
+                let find = |names: &[&[&str]]| {
+                    names
+                        .iter()
+                        .map(|n| element.find(n))
+                        .collect::<Option<Vec<_>>>()
+                };
+                let position = find(&[&["x"], &["y"], &["z"]])
+                    .ok_or_else(|| invalid("vertices have no x, y and z"))?;
+                let normal = find(&[&["nx"], &["ny"], &["nz"]]);
+                let uv = find(&[
+                    &["u", "s", "texture_u", "texture_s"],
+                    &["v", "t", "texture_v", "texture_t"],
+                ]);
+                let color = find(&[
+                    &["red", "diffuse_red"],
+                    &["green", "diffuse_green"],
+                    &["blue", "diffuse_blue"],
+                ]);
+                let color_scale = match color.as_ref().map(|c| &element.properties[c[0]]) {
+                    Some(Property::Scalar(_, ty)) => ty.full_scale(),
+                    _ => 1.0,
+                };
+                for _ in 0..element.count {
+                    let values = values.element(element)?;
+                    let get = |indices: &[usize]| {
+                        indices
+                            .iter()
+                            .map(|&i| values[i][0] as f32)
+                            .collect::<Vec<_>>()
+                    };
+                    let p = get(&position);
+                    positions.push(Point::new(p[0], p[1], p[2]));
+                    if let Some(n) = normal.as_ref().map(|n| get(n)) {
+                        normals.push(Vec3::new(n[0], n[1], n[2]).unit_vector());
+                    }
+                    if let Some(uv) = uv.as_ref().map(|uv| get(uv)) {
+                        uvs.push([uv[0], uv[1]]);
+                    }
+                    if let Some(c) = color.as_ref().map(|c| get(c)) {
+                        let decode = |x: f32| Transfer::Srgb.decode(x / color_scale as f32);
+                        colors.push(Color::new(decode(c[0]), decode(c[1]), decode(c[2])));
+                    }
+                }
+            }
+            "face" => {
+                let list = element
+                    .properties
+                    .iter()
+                    .position(|p| match p {
+                        Property::List(name, ..) => {
+                            name == "vertex_indices" || name == "vertex_index"
+                        }
+                        Property::Scalar(..) => false,
+                    })
+                    .ok_or_else(|| invalid("faces have no vertex_indices list"))?;
+                for _ in 0..element.count {
+                    let face = values.element(element)?.swap_remove(list);
+                    if face.len() < 3 || face.iter().any(|&i| i < 0.0) {
+                        return Err(invalid("a face needs 3 or more non-negative indices"));
+                    }
+                    let indices = face.iter().map(|&i| i as usize).collect::<Vec<_>>();
+                    for k in 1..indices.len() - 1 {
+                        triangles.push([indices[0], indices[k], indices[k + 1]]);
+                    }
+                }
+            }
+            _ => {
+                for _ in 0..element.count {
+                    values.element(element)?;
+                }
+            }
+        }
+    }
+    Mesh::new(positions, normals, uvs, colors, triangles, default.clone()).map_err(invalid)
+}
+
+pub fn load_ply<P: AsRef<Path>>(path: P, default: &MaterialType) -> io::Result<Mesh> {
+    read_ply(BufReader::new(File::open(path)?), default)
+}
+
+#[test]
+fn test_read_ply() {
+    let header = |format: &str| {
+        format!(
+            "ply\nformat {} 1.0\ncomment a coloured quad\nelement vertex 4\n\
+             property float x\nproperty float y\nproperty float z\nproperty uchar red\n\
+             property uchar green\nproperty uchar blue\nelement face 1\n\
+             property list uchar int vertex_indices\nend_header\n",
+            format
+        )
+    };
+    let corners = [
+        [0.0f32, 0.0, 0.0],
+        [1.0, 0.0, 0.0],
+        [1.0, 1.0, 0.0],
+        [0.0, 1.0, 0.0],
+    ];
+    let rgb = [[255u8, 0, 0], [0, 255, 0], [0, 0, 255], [255, 255, 255]];
+
+    let mut ascii = header("ascii");
+    for (p, c) in corners.iter().zip(rgb.iter()) {
+        ascii += &format!("{} {} {} {} {} {}\n", p[0], p[1], p[2], c[0], c[1], c[2]);
+    }
+    ascii += "4 0 1 2 3\n";
+    let default = MaterialType::Lambertian(Vec3::new_singleton(0.5).into());
+    let mesh = read_ply(ascii.as_bytes(), &default).unwrap();
+    assert_eq!(mesh.triangles(), &[[0, 1, 2], [0, 2, 3]]);
+    assert_eq!(mesh.positions()[2], Point::new(1.0, 1.0, 0.0));
+    assert_eq!(mesh.colors()[1], Color::new(0.0, 1.0, 0.0));
+    // The colors tint the caller's material rather than replacing it
+    assert_eq!(mesh.material(), &default);
+    let r = crate::ray::Ray::new(Point::new(0.75, 0.25, 1.0), Vec3::new(0.0, 0.0, -1.0));
+    let rec = mesh.hit(r, 0.001, f32::INFINITY).unwrap();
+    assert!((rec.tint() - Color::new(0.25, 0.5, 0.25)).length() < 1e-5);
+
+    for &(format, big) in [("binary_little_endian", false), ("binary_big_endian", true)].iter() {
+        let mut binary = header(format).into_bytes();
+        let mut push = |bytes: &[u8]| match big {
+            true => binary.extend(bytes.iter().rev()),
+            false => binary.extend(bytes),
+        };
+        for (p, c) in corners.iter().zip(rgb.iter()) {
+            p.iter().for_each(|x| push(&x.to_le_bytes()));
+            c.iter().for_each(|x| push(&[*x]));
+        }
+        push(&[4]);
+        (0..4i32).for_each(|i| push(&i.to_le_bytes()));
+        assert_eq!(read_ply(binary.as_slice(), &default).unwrap(), mesh);
+    }
+
+    let truncated = ascii.replace("4 0 1 2 3\n", "4 0 1 2\n");
+    assert!(read_ply(truncated.as_bytes(), &default).is_err());
+}
@@ -0,0 +1,366 @@
+use std::io::{self, BufRead, Read, Write};
+use std::str::FromStr;
+
+use crate::quantize::LdrImage;
+use crate::zlib;
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+
+/// Lookup table for the CRC-32 used by PNG chunks
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0_u32; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = match c & 1 {
+                1 => 0xEDB8_8320 ^ (c >> 1),
+                _ => c >> 1,
+            };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+};
+
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(0xFFFF_FFFF_u32, |c, &byte| {
+        CRC_TABLE[((c ^ byte as u32) & 0xFF) as usize] ^ (c >> 8)
+    })
+}
+
+/// Channel layout and bit depth of a PNG file
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PngFormat {
+    pub alpha: bool,
+    pub sixteen_bit: bool,
+}
+
+impl PngFormat {
+    pub fn channels(&self) -> usize {
+        if self.alpha {
+            4
+        } else {
+            3
+        }
+    }
+
+    pub fn bit_depth(&self) -> u8 {
+        if self.sixteen_bit {
+            16
+        } else {
+            8
+        }
+    }
+
+    fn color_type(&self) -> u8 {
+        if self.alpha {
+            6
+        } else {
+            2
+        }
+    }
+}
+
+impl FromStr for PngFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (alpha, sixteen_bit) = match s {
+            "rgb8" => (false, false),
+            "rgb16" => (false, true),
+            "rgba8" => (true, false),
+            "rgba16" => (true, true),
+            _ => return Err("expected one of rgb8, rgb16, rgba8, rgba16".to_string()),
+        };
+        Ok(Self { alpha, sixteen_bit })
+    }
+}
+
+fn write_chunk<W: Write>(out: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    out.write_all(kind)?;
+    out.write_all(data)?;
+    let mut crc_input = kind.to_vec();
+    crc_input.extend_from_slice(data);
+    out.write_all(&crc32(&crc_input).to_be_bytes())
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = (
+        (p - a as i16).abs(),
+        (p - b as i16).abs(),
+        (p - c as i16).abs(),
+    );
+    match (pa <= pb && pa <= pc, pb <= pc) {
+        (true, _) => a,
+        (false, true) => b,
+        (false, false) => c,
+    }
+}
+
+/// Filter one scanline with each of the five PNG filters and keep the one whose output
+/// looks smallest, using the usual sum of absolute differences heuristic.
+fn filter_row(row: &[u8], prior: &[u8], bpp: usize, out: &mut Vec<u8>) {
+    let left = |i: usize| if i >= bpp { row[i - bpp] } else { 0 };
+    let up_left = |i: usize| if i >= bpp { prior[i - bpp] } else { 0 };
+    let filtered = |filter: u8| -> Vec<u8> {
+        (0..row.len())
+            .map(|i| {
+                let predictor = match filter {
+                    0 => 0,
+                    1 => left(i),
+                    2 => prior[i],
+                    3 => ((left(i) as u16 + prior[i] as u16) / 2) as u8,
+                    _ => paeth(left(i), prior[i], up_left(i)),
+                };
+                row[i].wrapping_sub(predictor)
+            })
+            .collect()
+    };
+    let cost =
+        |bytes: &[u8]| -> u64 { bytes.iter().map(|&b| (b as i8).unsigned_abs() as u64).sum() };
+    let (filter, bytes) = (0..5_u8)
+        .map(|filter| (filter, filtered(filter)))
+        .min_by_key(|(_, bytes)| cost(bytes))
+        .unwrap();
+    out.push(filter);
+    out.extend(bytes);
+}
+
+/// Encode an image as PNG.
+///
+/// `samples` holds `format.channels()` values per pixel, rows top first, each already
+/// scaled to the format's bit depth (so at most 255 for 8 bit images).
+pub fn write_png<W: Write>(
+    out: &mut W,
+    width: usize,
+    height: usize,
+    samples: &[u16],
+    format: PngFormat,
+) -> io::Result<()> {
+    assert_eq!(samples.len(), width * height * format.channels());
+    out.write_all(&SIGNATURE)?;
+
+    let mut header = Vec::with_capacity(13);
+    header.extend((width as u32).to_be_bytes());
+    header.extend((height as u32).to_be_bytes());
+    // bit depth, color type, then deflate compression, adaptive filtering, no interlace
+    header.extend([format.bit_depth(), format.color_type(), 0, 0, 0]);
+    write_chunk(out, b"IHDR", &header)?;
+
+    let bpp = format.channels() * format.bit_depth() as usize / 8;
+    let mut bytes = Vec::with_capacity(width * height * bpp);
+    for &s in samples {
+        match format.sixteen_bit {
+            true => bytes.extend_from_slice(&s.to_be_bytes()),
+            false => bytes.push(s as u8),
+        }
+    }
+    let stride = width * bpp;
+    let mut filtered = Vec::with_capacity((stride + 1) * height);
+    let zeros = vec![0_u8; stride];
+    for y in 0..height {
+        let prior = match y {
+            0 => &zeros[..],
+            _ => &bytes[(y - 1) * stride..y * stride],
+        };
+        filter_row(
+            &bytes[y * stride..(y + 1) * stride],
+            prior,
+            bpp,
+            &mut filtered,
+        );
+    }
+    write_chunk(out, b"IDAT", &zlib::compress(&filtered))?;
+    write_chunk(out, b"IEND", &[])
+}
+
+fn invalid<S: Into<String>>(message: S) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// Undo the per scanline filters. `data` holds each row's filter byte followed
+/// by its `stride` bytes; the result has the filter bytes removed.
+fn unfilter(data: &[u8], stride: usize, bpp: usize) -> io::Result<Vec<u8>> {
+    let mut out = Vec::<u8>::with_capacity(data.len());
+    for (y, row) in data.chunks_exact(stride + 1).enumerate() {
+        let start = out.len();
+        for i in 0..stride {
+            let left = if i >= bpp { out[start + i - bpp] } else { 0 };
+            let up = if y > 0 { out[start + i - stride] } else { 0 };
+            let up_left = if y > 0 && i >= bpp {
+                out[start + i - stride - bpp]
+            } else {
+                0
+            };
+            let predictor = match row[0] {
+                0 => 0,
+                1 => left,
+                2 => up,
+                3 => ((left as u16 + up as u16) / 2) as u8,
+                4 => paeth(left, up, up_left),
+                f => return Err(invalid(format!("unknown PNG filter type {}", f))),
+            };
+            out.push(row[1 + i].wrapping_add(predictor));
+        }
+    }
+    Ok(out)
+}
+
+/// Decode a non interlaced PNG of any bit depth and colour type into RGB, dropping alpha.
+/// Sixteen bit files keep their full precision, everything else becomes 8 bits.
+pub fn read_png<R: BufRead>(input: &mut R) -> io::Result<LdrImage> {
+    let mut signature = [0; 8];
+    input.read_exact(&mut signature)?;
+    if signature != SIGNATURE {
+        return Err(invalid("not a PNG file"));
+    }
+    let mut header = None;
+    let mut palette = Vec::new();
+    let mut compressed = Vec::new();
+    loop {
+        let mut length = [0; 4];
+        input.read_exact(&mut length)?;
+        // The type and data, read only as far as they are there
+        let size = u32::from_be_bytes(length) as usize + 4;
+        let mut chunk = Vec::new();
+        input.by_ref().take(size as u64).read_to_end(&mut chunk)?;
+        if chunk.len() < size {
+            return Err(invalid("PNG chunk ends early"));
+        }
+        let mut crc = [0; 4];
+        input.read_exact(&mut crc)?;
+        if crc32(&chunk) != u32::from_be_bytes(crc) {
+            return Err(invalid("PNG chunk CRC mismatch"));
+        }
+        let (kind, data) = chunk.split_at(4);
+        match kind {
+            b"IHDR" if data.len() == 13 => header = Some(data.to_vec()),
+            b"PLTE" => palette = data.to_vec(),
+            b"IDAT" => compressed.extend_from_slice(data),
+            b"IEND" => break,
+            _ => {}
+        }
+    }
+    let header = header.ok_or_else(|| invalid("PNG has no IHDR chunk"))?;
+    let field =
+        |i: usize| u32::from_be_bytes([header[i], header[i + 1], header[i + 2], header[i + 3]]);
+    let (width, height) = (field(0) as usize, field(4) as usize);
+    let (depth, color_type) = (header[8] as usize, header[9]);
+    if header[12] != 0 {
+        return Err(invalid("interlaced PNG files are not supported"));
+    }
+    let channels = match (color_type, depth) {
+        (0, 1) | (0, 2) | (0, 4) | (0, 8) | (0, 16) => 1,
+        (3, 1) | (3, 2) | (3, 4) | (3, 8) => 1,
+        (4, 8) | (4, 16) => 2,
+        (2, 8) | (2, 16) => 3,
+        (6, 8) | (6, 16) => 4,
+        _ => {
+            return Err(invalid(format!(
+                "bad PNG colour type {} with bit depth {}",
+                color_type, depth
+            )))
+        }
+    };
+    if width == 0 || height == 0 {
+        return Err(invalid("PNG has no pixels"));
+    }
+    let too_large = || invalid("PNG image is too large");
+    let stride = width
+        .checked_mul(channels * depth)
+        .ok_or_else(too_large)?
+        .div_ceil(8);
+    let size = (stride + 1).checked_mul(height).ok_or_else(too_large)?;
+    let bpp = (channels * depth).div_ceil(8);
+    let raw = zlib::decompress(&compressed)?;
+    if raw.len() < size {
+        return Err(invalid("PNG image data ends early"));
+    }
+    let bytes = unfilter(&raw[..size], stride, bpp)?;
+
+    // Sample n of a row, at any bit depth
+    let sample = |row: &[u8], n: usize| -> u16 {
+        match depth {
+            16 => u16::from_be_bytes([row[2 * n], row[2 * n + 1]]),
+            8 => row[n] as u16,
+            _ => {
+                let bit = n * depth;
+                (row[bit / 8] >> (8 - depth - bit % 8)) as u16 & ((1 << depth) - 1)
+            }
+        }
+    };
+    let maxval = match depth {
+        16 => u16::MAX,
+        _ => 255,
+    };
+    // Grey levels below 8 bits stretch to the full 8 bit range
+    let grey_scale = match depth {
+        16 | 8 => 1,
+        _ => 255 / ((1 << depth) - 1),
+    };
+    let mut data = Vec::new();
+    for row in bytes.chunks_exact(stride) {
+        for x in 0..width {
+            let s = |c: usize| sample(row, x * channels + c);
+            data.push(match color_type {
+                3 => {
+                    let i = s(0) as usize * 3;
+                    match palette.get(i..i + 3) {
+                        Some(rgb) => [rgb[0] as u16, rgb[1] as u16, rgb[2] as u16],
+                        None => return Err(invalid("PNG palette index out of range")),
+                    }
+                }
+                0 | 4 => [s(0) * grey_scale; 3],
+                _ => [s(0), s(1), s(2)],
+            });
+        }
+    }
+    Ok(LdrImage {
+        width,
+        height,
+        maxval,
+        data,
+    })
+}
+
+#[test]
+fn test_crc32() {
+    assert_eq!(crc32(b"IEND"), 0xAE42_6082);
+    assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+}
+
+#[test]
+fn test_png_round_trip() {
+    let format = PngFormat {
+        alpha: true,
+        sixteen_bit: true,
+    };
+    let samples = (0..5 * 3 * 4).map(|i| i as u16 * 997).collect::<Vec<_>>();
+    let mut bytes = Vec::new();
+    write_png(&mut bytes, 5, 3, &samples, format).unwrap();
+    let image = read_png(&mut &bytes[..]).unwrap();
+    assert_eq!((image.width, image.height, image.maxval), (5, 3, u16::MAX));
+    let rgb = samples
+        .chunks_exact(4)
+        .map(|p| [p[0], p[1], p[2]])
+        .collect::<Vec<_>>();
+    assert_eq!(image.data, rgb);
+
+    // A chunk longer than the file, or an IHDR size the data cannot hold, is an error
+    let mut long_chunk = bytes[..8].to_vec();
+    long_chunk.extend([0xFF, 0xFF, 0xFF, 0xF0].iter().chain(b"IHDR"));
+    assert!(read_png(&mut &long_chunk[..]).is_err());
+    let mut huge = bytes.clone();
+    huge[16..24].copy_from_slice(&[0xFF; 8]);
+    let crc = crc32(&huge[12..29]).to_be_bytes();
+    huge[29..33].copy_from_slice(&crc);
+    assert!(read_png(&mut &huge[..])
+        .unwrap_err()
+        .to_string()
+        .contains("too large"));
+}
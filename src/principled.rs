@@ -0,0 +1,337 @@
+//! One material whose parameters blend between diffuse, metal, glass and coated looks,
+//! after Burley's "Physically Based Shading at Disney" (2012, 2015).
+
+use std::f32::consts::PI;
+
+use rand::Rng;
+
+use crate::hittable::HitRecord;
+use crate::material::{BsdfSample, Material};
+use crate::microfacet::{fresnel_dielectric, refract, rough_dielectric, Frame, Ggx};
+use crate::texture::{Texture, TextureType};
+use crate::utils::random;
+use crate::vec3::{luminance, Color, Vec3};
+
+/// Roughness is kept above this, so every lobe can be both sampled and evaluated
+const MIN_ROUGHNESS: f32 = 0.05;
+/// The clearcoat is a fixed, fairly glossy varnish
+const CLEARCOAT_ROUGHNESS: f32 = 0.1;
+
+/// Every parameter is a texture. Colors are used as they are, and the others read the
+/// luminance of theirs, so plain numbers can be given as grey.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Principled {
+    pub base_color: TextureType,
+    /// 0 for a dielectric, 1 for a metal tinted by the base color
+    pub metallic: TextureType,
+    pub roughness: TextureType,
+    /// Reflectance head on of the dielectric part, 0.5 for 4%
+    pub specular: TextureType,
+    /// How far the dielectric reflection is tinted toward the base color
+    pub specular_tint: TextureType,
+    /// A soft, lighter rim at grazing angles, as on cloth
+    pub sheen: TextureType,
+    /// A clear varnish over everything else
+    pub clearcoat: TextureType,
+    /// How much of the dielectric part is glass rather than diffuse
+    pub transmission: TextureType,
+    pub ior: TextureType,
+}
+
+impl Default for Principled {
+    fn default() -> Self {
+        Self {
+            base_color: Color::new_singleton(0.8).into(),
+            metallic: Color::new_dfl().into(),
+            roughness: Color::new_singleton(0.5).into(),
+            specular: Color::new_singleton(0.5).into(),
+            specular_tint: Color::new_dfl().into(),
+            sheen: Color::new_dfl().into(),
+            clearcoat: Color::new_dfl().into(),
+            transmission: Color::new_dfl().into(),
+            ior: Color::new_singleton(1.5).into(),
+        }
+    }
+}
+
+impl Principled {
+    /// The parameters' names, as scene files write them
+    pub const INPUTS: [&'static str; 9] = [
+        "base_color",
+        "metallic",
+        "roughness",
+        "specular",
+        "specular_tint",
+        "sheen",
+        "clearcoat",
+        "transmission",
+        "ior",
+    ];
+
+    /// The parameters, in the order of `INPUTS`
+    pub fn inputs(&self) -> [&TextureType; 9] {
+        [
+            &self.base_color,
+            &self.metallic,
+            &self.roughness,
+            &self.specular,
+            &self.specular_tint,
+            &self.sheen,
+            &self.clearcoat,
+            &self.transmission,
+            &self.ior,
+        ]
+    }
+
+    /// The parameters to set, in the order of `INPUTS`
+    pub fn inputs_mut(&mut self) -> [&mut TextureType; 9] {
+        [
+            &mut self.base_color,
+            &mut self.metallic,
+            &mut self.roughness,
+            &mut self.specular,
+            &mut self.specular_tint,
+            &mut self.sheen,
+            &mut self.clearcoat,
+            &mut self.transmission,
+            &mut self.ior,
+        ]
+    }
+
+    /// Look up the parameters at a hit
+    fn at(&self, rec: &HitRecord) -> Lobes {
+        let color = |t: &TextureType| t.value(rec.u(), rec.v(), rec.p());
+        let number = |t: &TextureType| luminance(color(t));
+        let unit = |t: &TextureType| number(t).clamp(0.0, 1.0);
+        let base = color(&self.base_color) * rec.tint();
+        let (metallic, transmission) = (unit(&self.metallic), unit(&self.transmission));
+        let roughness = unit(&self.roughness).max(MIN_ROUGHNESS);
+        // Hue without brightness, for tinting
+        let tint = match luminance(base) > 0.0 {
+            true => base / luminance(base),
+            false => Color::new_singleton(1.0),
+        };
+        let white = Color::new_singleton(1.0);
+        let specular_tint = unit(&self.specular_tint);
+        let dielectric_f0 =
+            0.08 * unit(&self.specular) * ((1.0 - specular_tint) * white + specular_tint * tint);
+        let ior = number(&self.ior).max(1.0 + 1e-3);
+        let weights = [
+            (1.0 - metallic) * (1.0 - transmission),
+            1.0 - (1.0 - metallic) * transmission,
+            (1.0 - metallic) * transmission,
+            0.25 * unit(&self.clearcoat),
+        ];
+        Lobes {
+            base,
+            roughness,
+            sheen: unit(&self.sheen) * (1.0 - metallic) * (0.5 * white + 0.5 * tint),
+            f0: (1.0 - metallic) * dielectric_f0 + metallic * base,
+            // The index of the far side over that of the side wo is on
+            eta: match rec.front_face() {
+                true => ior,
+                false => 1.0 / ior,
+            },
+            specular: Ggx::new(roughness),
+            coat: Ggx::new(CLEARCOAT_ROUGHNESS),
+            weights,
+            chances: {
+                let total = weights.iter().sum::<f32>();
+                [
+                    weights[0] / total,
+                    weights[1] / total,
+                    weights[2] / total,
+                    weights[3] / total,
+                ]
+            },
+        }
+    }
+}
+
+/// The parameters at one hit, as the lobes they make up: diffuse with sheen, specular
+/// reflection, glass and clearcoat, in that order in `weights` and `chances`
+struct Lobes {
+    base: Color,
+    roughness: f32,
+    sheen: Color,
+    f0: Color,
+    eta: f32,
+    specular: Ggx,
+    coat: Ggx,
+    /// How much of each lobe the surface has
+    weights: [f32; 4],
+    /// How often each lobe is sampled
+    chances: [f32; 4],
+}
+
+/// Schlick's approximation to the Fresnel factor, rising from `f0` head on to 1 at
+/// grazing angles
+fn schlick(f0: Color, cos: f32) -> Color {
+    let w = (1.0 - cos).clamp(0.0, 1.0).powi(5);
+    (1.0 - w) * f0 + w * Color::new_singleton(1.0)
+}
+
+impl Lobes {
+    /// f(wi, wo) in the local frame
+    fn f(&self, wo: Vec3, wi: Vec3) -> Color {
+        if wo.z() <= 0.0 || wi.z() == 0.0 {
+            return Color::new_dfl();
+        }
+        let glass = self.weights[2] * rough_dielectric(&self.specular, self.eta, wo, wi).0;
+        if wi.z() < 0.0 {
+            return glass * self.base;
+        }
+        let h = (wo + wi).unit_vector();
+        let cos_d = wi.dot(h);
+        // Diffuse, brighter at grazing angles on rough surfaces, and sheen
+        let fd90 = 0.5 + 2.0 * self.roughness * cos_d * cos_d;
+        let retro = |cos: f32| 1.0 + (fd90 - 1.0) * (1.0 - cos).powi(5);
+        let diffuse = self.weights[0] * retro(wo.z()) * retro(wi.z()) / PI * self.base
+            + (1.0 - cos_d).powi(5) * self.sheen;
+        let microfacet = |ggx: &Ggx| ggx.d(h) * ggx.g(wo, wi) / (4.0 * wo.z() * wi.z());
+        let specular = self.weights[1] * microfacet(&self.specular) * schlick(self.f0, cos_d);
+        let coat = self.weights[3] * microfacet(&self.coat) * fresnel_dielectric(cos_d, 1.5);
+        diffuse + specular + glass + Color::new_singleton(coat)
+    }
+
+    /// The density of `sample` picking wi, in the local frame
+    fn pdf(&self, wo: Vec3, wi: Vec3) -> f32 {
+        if wo.z() <= 0.0 || wi.z() == 0.0 {
+            return 0.0;
+        }
+        let glass = self.chances[2] * rough_dielectric(&self.specular, self.eta, wo, wi).1;
+        if wi.z() < 0.0 {
+            return glass;
+        }
+        let h = (wo + wi).unit_vector();
+        let reflection = |ggx: &Ggx| ggx.pdf_visible(wo, h) / (4.0 * wo.dot(h));
+        self.chances[0] * wi.z() / PI
+            + self.chances[1] * reflection(&self.specular)
+            + glass
+            + self.chances[3] * reflection(&self.coat)
+    }
+}
+
+impl Material for Principled {
+    fn sample<R: Rng + ?Sized>(
+        &self,
+        wo: Vec3,
+        rec: &HitRecord,
+        rng: &mut R,
+    ) -> Option<BsdfSample> {
+        let lobes = self.at(rec);
+        let frame = Frame::new(rec.normal());
+        let wo = frame.to_local(wo);
+        if wo.z() <= 0.0 {
+            return None;
+        }
+        // Pick a lobe to sample, then weigh the direction by every lobe
+        let u = random(rng);
+        let (mut lobe, mut below) = (0, lobes.chances[0]);
+        while u >= below && lobe < 3 {
+            lobe += 1;
+            below += lobes.chances[lobe];
+        }
+        let wi = match lobe {
+            0 => {
+                let dir = Vec3::new(0.0, 0.0, 1.0) + Vec3::random_unit_vector(rng);
+                match dir.near_zero() {
+                    true => Vec3::new(0.0, 0.0, 1.0),
+                    false => dir.unit_vector(),
+                }
+            }
+            1 | 3 => {
+                let ggx = match lobe {
+                    1 => &lobes.specular,
+                    _ => &lobes.coat,
+                };
+                (-wo).reflect(ggx.sample_visible(wo, random(rng), random(rng)))
+            }
+            _ => {
+                let h = lobes.specular.sample_visible(wo, random(rng), random(rng));
+                match fresnel_dielectric(wo.dot(h), lobes.eta) > random(rng) {
+                    true => (-wo).reflect(h),
+                    false => refract(wo, h, lobes.eta)?,
+                }
+            }
+        };
+        let pdf = lobes.pdf(wo, wi);
+        match pdf > 0.0 {
+            true => Some(BsdfSample::new(
+                lobes.f(wo, wi) * (wi.z().abs() / pdf),
+                frame.to_world(wi),
+                pdf,
+            )),
+            false => None,
+        }
+    }
+
+    fn emitted(&self, _rec: &HitRecord) -> Color {
+        Color::new_dfl()
+    }
+
+    fn eval(&self, wo: Vec3, wi: Vec3, rec: &HitRecord) -> Color {
+        let frame = Frame::new(rec.normal());
+        self.at(rec).f(frame.to_local(wo), frame.to_local(wi))
+    }
+
+    fn pdf(&self, wo: Vec3, wi: Vec3, rec: &HitRecord) -> f32 {
+        let frame = Frame::new(rec.normal());
+        self.at(rec).pdf(frame.to_local(wo), frame.to_local(wi))
+    }
+}
+
+#[test]
+fn test_principled() {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use crate::material::MaterialType;
+    use crate::ray::Ray;
+    use crate::vec3::Point;
+
+    let mut rng = StdRng::seed_from_u64(8);
+    let up = Vec3::new(0.0, 1.0, 0.0);
+    let r = Ray::new(Point::new(-1.0, 2.0, 0.0), Vec3::new(1.0, -2.0, 0.0));
+    let wo = -r.dir().unit_vector();
+    let grey = |x: f32| TextureType::from(Color::new_singleton(x));
+    let red = TextureType::from(Color::new(0.8, 0.1, 0.1));
+    let plastic = Principled {
+        base_color: red.clone(),
+        clearcoat: grey(1.0),
+        sheen: grey(0.5),
+        ..Principled::default()
+    };
+    let metal = Principled {
+        base_color: red,
+        metallic: grey(1.0),
+        roughness: grey(0.3),
+        ..Principled::default()
+    };
+    let glass = Principled {
+        transmission: grey(1.0),
+        roughness: grey(0.2),
+        base_color: grey(1.0),
+        ..Principled::default()
+    };
+    for (principled, reflects_most) in [(plastic, 0.6), (metal, 0.5), (glass, 0.9)].iter() {
+        let material = MaterialType::Principled(Box::new(principled.clone()));
+        let rec = HitRecord::new(r, 1.0, up, up, &material, 0.0, 0.0);
+        let n = 2000;
+        let mut total = Color::new_dfl();
+        for _ in 0..n {
+            if let Some(sample) = material.sample(wo, &rec, &mut rng) {
+                // Sampling, evaluating and the density agree
+                let (wi, pdf) = (sample.wi(), sample.pdf());
+                assert!(!sample.is_delta());
+                assert!((pdf - material.pdf(wo, wi, &rec)).abs() < 1e-3 * pdf);
+                let weight = material.eval(wo, wi, &rec) * (wi.dot(up).abs() / pdf);
+                assert!((weight - sample.weight()).length() < 1e-3 * weight.length());
+                total += (1.0 / n as f32) * sample.weight();
+            }
+        }
+        // The albedo is plausible: not more light than came in, and most of it for
+        // red light off red things and clear glass
+        assert!(total.x() > *reflects_most && total.x() < 1.05);
+    }
+}
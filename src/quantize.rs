@@ -0,0 +1,211 @@
+use std::str::FromStr;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::framebuffer::Framebuffer;
+use crate::tonemap::ToneMapping;
+
+/// A displayable image with up to 16 bits per channel, rows top first
+#[derive(Clone, Debug, PartialEq)]
+pub struct LdrImage {
+    pub width: usize,
+    pub height: usize,
+    /// The sample value that means full intensity
+    pub maxval: u16,
+    pub data: Vec<[u16; 3]>,
+}
+
+impl LdrImage {
+    pub fn rows(&self) -> impl Iterator<Item = &[[u16; 3]]> {
+        self.data.chunks(self.width)
+    }
+}
+
+/// How to spread the rounding error when quantizing
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Dither {
+    None,
+    /// An 8x8 Bayer matrix, cheap but with a visible cross hatch
+    Ordered,
+    /// A tiled void and cluster threshold map, whose error has no low frequencies
+    BlueNoise,
+}
+
+impl FromStr for Dither {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Dither::None),
+            "ordered" | "bayer" => Ok(Dither::Ordered),
+            "blue-noise" => Ok(Dither::BlueNoise),
+            _ => Err("expected none, ordered or blue-noise".to_string()),
+        }
+    }
+}
+
+/// Rank of (x, y) in the 8x8 Bayer matrix, in 0..64. The lowest coordinate bits pick
+/// the most significant base 4 digit, which is what keeps neighbouring ranks far apart.
+fn bayer(x: usize, y: usize) -> usize {
+    (0..3).fold(0, |rank, bit| {
+        let (xb, yb) = ((x >> bit) & 1, (y >> bit) & 1);
+        rank * 4 + 2 * (xb ^ yb) + yb
+    })
+}
+
+/// Build a blue noise threshold map of side `size` with Ulichney's void and cluster
+/// method, returning the rank of every cell in 0..size * size.
+fn void_and_cluster(size: usize) -> Vec<usize> {
+    const SIGMA: f32 = 1.5;
+    let n = size * size;
+    // Gaussian energy each point adds to a cell at a given wrapped offset
+    let kernel = (0..n)
+        .map(|i| {
+            let wrap = |d: usize| d.min(size - d) as f32;
+            let (dx, dy) = (wrap(i % size), wrap(i / size));
+            (-(dx * dx + dy * dy) / (2.0 * SIGMA * SIGMA)).exp()
+        })
+        .collect::<Vec<f32>>();
+    let update = |energy: &mut [f32], p: usize, sign: f32| {
+        let (px, py) = (p % size, p / size);
+        for (i, e) in energy.iter_mut().enumerate() {
+            let dx = (i % size + size - px) % size;
+            let dy = (i / size + size - py) % size;
+            *e += sign * kernel[dy * size + dx];
+        }
+    };
+    // The tightest cluster is the set cell with the most energy, the largest void the
+    // empty cell with the least
+    let extreme = |energy: &[f32], set: &[bool], want: bool| -> usize {
+        let candidates = (0..n).filter(|&i| set[i] == want);
+        let by_energy = |&a: &usize, &b: &usize| energy[a].partial_cmp(&energy[b]).unwrap();
+        match want {
+            true => candidates.max_by(by_energy).unwrap(),
+            false => candidates.min_by(by_energy).unwrap(),
+        }
+    };
+
+    // Start from a random tenth of the cells and relax them until evenly spread
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut set = vec![false; n];
+    let mut energy = vec![0.0; n];
+    let initial = (n / 10).max(1);
+    let mut ones = 0;
+    while ones < initial {
+        let p = rng.gen_range(0..n);
+        if !set[p] {
+            set[p] = true;
+            update(&mut energy, p, 1.0);
+            ones += 1;
+        }
+    }
+    loop {
+        let cluster = extreme(&energy, &set, true);
+        set[cluster] = false;
+        update(&mut energy, cluster, -1.0);
+        let void = extreme(&energy, &set, false);
+        set[void] = true;
+        update(&mut energy, void, 1.0);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut rank = vec![0; n];
+    // Ranks below the prototype come from taking its points away one cluster at a time
+    let (mut set_down, mut energy_down) = (set.clone(), energy.clone());
+    for r in (0..initial).rev() {
+        let cluster = extreme(&energy_down, &set_down, true);
+        set_down[cluster] = false;
+        update(&mut energy_down, cluster, -1.0);
+        rank[cluster] = r;
+    }
+    // and the rest from filling in the largest voids
+    for r in initial..n {
+        let void = extreme(&energy, &set, false);
+        set[void] = true;
+        update(&mut energy, void, 1.0);
+        rank[void] = r;
+    }
+    rank
+}
+
+/// Per pixel offsets in [0, 1) added before truncating to an integer level
+struct ThresholdMap {
+    size: usize,
+    thresholds: Vec<f32>,
+}
+
+impl ThresholdMap {
+    fn new(dither: Dither) -> Self {
+        const BLUE_NOISE_SIZE: usize = 32;
+        let (size, ranks) = match dither {
+            // Truncating after adding a half rounds to the nearest level
+            Dither::None => (1, vec![0]),
+            Dither::Ordered => (8, (0..64).map(|i| bayer(i % 8, i / 8)).collect()),
+            Dither::BlueNoise => (BLUE_NOISE_SIZE, void_and_cluster(BLUE_NOISE_SIZE)),
+        };
+        let n = ranks.len() as f32;
+        Self {
+            size,
+            thresholds: ranks.iter().map(|&r| (r as f32 + 0.5) / n).collect(),
+        }
+    }
+
+    fn get(&self, x: usize, y: usize) -> f32 {
+        self.thresholds[(y % self.size) * self.size + x % self.size]
+    }
+}
+
+/// Tone map a render and quantize it to `maxval + 1` levels per channel
+pub fn quantize(
+    fb: &Framebuffer,
+    tone_mapping: &ToneMapping,
+    maxval: u16,
+    dither: Dither,
+) -> LdrImage {
+    let map = ThresholdMap::new(dither);
+    let scale = maxval as f32;
+    let data = fb
+        .pixels()
+        .enumerate()
+        .map(|(i, c)| {
+            let c = tone_mapping.apply(c);
+            let t = map.get(i % fb.width(), i / fb.width());
+            let level = |v: f32| (v.clamp(0.0, 1.0) * scale + t).min(scale) as u16;
+            [level(c.x()), level(c.y()), level(c.z())]
+        })
+        .collect();
+    LdrImage {
+        width: fb.width(),
+        height: fb.height(),
+        maxval,
+        data,
+    }
+}
+
+#[test]
+fn test_quantize() {
+    use crate::tonemap::Transfer;
+    use crate::vec3::Color;
+
+    let linear = ToneMapping {
+        transfer: Transfer::Gamma(1.0),
+        ..ToneMapping::default()
+    };
+    let ramp = (0..4)
+        .map(|i| Color::new_singleton(i as f32 / 3.0))
+        .collect();
+    let fb = Framebuffer::from_pixels(4, 1, ramp);
+    let image = quantize(&fb, &linear, 65535, Dither::None);
+    let reds = image.data.iter().map(|p| p[0]).collect::<Vec<_>>();
+    assert_eq!(reds, vec![0, 21845, 43690, 65535]);
+
+    // A flat 30% grey with 2 levels dithers to about 30% of the pixels being on
+    let fb = Framebuffer::from_pixels(64, 64, vec![Color::new_singleton(0.3); 64 * 64]);
+    for dither in [Dither::Ordered, Dither::BlueNoise].iter() {
+        let image = quantize(&fb, &linear, 1, *dither);
+        let on = image.data.iter().filter(|p| p[0] == 1).count();
+        assert!((on as f32 / (64.0 * 64.0) - 0.3).abs() < 0.01);
+    }
+}
@@ -0,0 +1,219 @@
+//! Axis aligned rectangles, and boxes made of six of them.
+
+use std::str::FromStr;
+
+use crate::aabb::Aabb;
+use crate::hittable::{HitRecord, HittableObject};
+use crate::material::MaterialType;
+use crate::ray::Ray;
+use crate::vec3::{Point, Vec3};
+
+/// The plane a rectangle lies in. The axis it does not name is the rectangle's normal.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Plane {
+    Xy,
+    Xz,
+    Yz,
+}
+
+impl Plane {
+    /// The two axes spanning the plane, then the normal axis
+    pub fn axes(self) -> (usize, usize, usize) {
+        match self {
+            Plane::Xy => (0, 1, 2),
+            Plane::Xz => (0, 2, 1),
+            Plane::Yz => (1, 2, 0),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Plane::Xy => "xy",
+            Plane::Xz => "xz",
+            Plane::Yz => "yz",
+        }
+    }
+}
+
+impl FromStr for Plane {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "xy" => Ok(Plane::Xy),
+            "xz" => Ok(Plane::Xz),
+            "yz" => Ok(Plane::Yz),
+            _ => Err("expected xy, xz or yz".to_string()),
+        }
+    }
+}
+
+/// The rectangle from `min` to `max` in `plane`, at `offset` along its normal. Its
+/// outside faces up the normal axis, or down it once flipped.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rect {
+    plane: Plane,
+    min: (f32, f32),
+    max: (f32, f32),
+    offset: f32,
+    flipped: bool,
+}
+
+impl Rect {
+    pub fn new(plane: Plane, min: (f32, f32), max: (f32, f32), offset: f32) -> Self {
+        Self {
+            plane,
+            min: (min.0.min(max.0), min.1.min(max.1)),
+            max: (min.0.max(max.0), min.1.max(max.1)),
+            offset,
+            flipped: false,
+        }
+    }
+
+    /// The same rectangle with its outside on the other side
+    pub fn flipped(self) -> Self {
+        Self {
+            flipped: !self.flipped,
+            ..self
+        }
+    }
+
+    /// Get if the rect's outside faces down the normal axis.
+    pub fn is_flipped(&self) -> bool {
+        self.flipped
+    }
+
+    /// Get the rect's plane.
+    pub fn plane(&self) -> Plane {
+        self.plane
+    }
+
+    /// Get the rect's min corner, in the plane's two axes.
+    pub fn min(&self) -> (f32, f32) {
+        self.min
+    }
+
+    /// Get the rect's max corner, in the plane's two axes.
+    pub fn max(&self) -> (f32, f32) {
+        self.max
+    }
+
+    /// Get the rect's offset along its normal.
+    pub fn offset(&self) -> f32 {
+        self.offset
+    }
+
+    /// The rectangle's min corner and its edges along the plane's first and second axes
+    pub fn corners(&self) -> (Point, Vec3, Vec3) {
+        let (a, b, n) = self.plane.axes();
+        (
+            axis(a, self.min.0) + axis(b, self.min.1) + axis(n, self.offset),
+            axis(a, self.max.0 - self.min.0),
+            axis(b, self.max.1 - self.min.1),
+        )
+    }
+
+    pub fn hit<'a>(
+        &self,
+        material: &'a MaterialType,
+        r: Ray,
+        tmin: f32,
+        tmax: f32,
+    ) -> Option<HitRecord<'a>> {
+        let (a, b, n) = self.plane.axes();
+        let t = (self.offset - r.orig()[n]) / r.dir()[n];
+        if !(t >= tmin && t <= tmax) {
+            return None;
+        }
+        let p = r.at(t);
+        let (u, v) = (
+            (p[a] - self.min.0) / (self.max.0 - self.min.0),
+            (p[b] - self.min.1) / (self.max.1 - self.min.1),
+        );
+        if !((0.0..=1.0).contains(&u) && (0.0..=1.0).contains(&v)) {
+            return None;
+        }
+        let normal = match self.flipped {
+            true => axis(n, -1.0),
+            false => axis(n, 1.0),
+        };
+        Some(HitRecord::new(r, t, normal, normal, material, u, v))
+    }
+
+    pub fn bounding_box(&self) -> Aabb {
+        let (a, b, n) = self.plane.axes();
+        // Pad along the normal so the box has some thickness to hit
+        let corner =
+            |(x, y): (f32, f32), pad: f32| axis(a, x) + axis(b, y) + axis(n, self.offset + pad);
+        Aabb::new(corner(self.min, -1e-4), corner(self.max, 1e-4))
+    }
+}
+
+/// The vector of length `length` along `axis`
+fn axis(axis: usize, length: f32) -> Vec3 {
+    match axis {
+        0 => Vec3::new(length, 0.0, 0.0),
+        1 => Vec3::new(0.0, length, 0.0),
+        _ => Vec3::new(0.0, 0.0, length),
+    }
+}
+
+/// The six faces of the box between corners `a` and `b`, all facing out
+pub fn cuboid(a: Point, b: Point, material: MaterialType) -> HittableObject {
+    let (min, max) = (a.min(b), a.max(b));
+    let faces = [Plane::Xy, Plane::Xz, Plane::Yz]
+        .iter()
+        .flat_map(|&plane| {
+            let (a, b, n) = plane.axes();
+            let face = |offset: f32| Rect::new(plane, (min[a], min[b]), (max[a], max[b]), offset);
+            vec![face(min[n]).flipped(), face(max[n])]
+        })
+        .map(|rect| HittableObject::Rect(rect, material.clone()))
+        .collect();
+    HittableObject::HittableList(faces)
+}
+
+#[test]
+fn test_rect() {
+    use crate::hittable::Hittable;
+
+    let material = MaterialType::Lambertian(Vec3::new_singleton(0.5).into());
+    let rect = Rect::new(Plane::Xz, (2.0, -1.0), (0.0, 1.0), 3.0);
+    assert_eq!(rect.min(), (0.0, -1.0));
+    let down = Ray::new(Point::new(0.5, 5.0, 0.5), Vec3::new(0.0, -1.0, 0.0));
+    let hit = rect.hit(&material, down, 0.001, f32::INFINITY).unwrap();
+    assert_eq!(hit.t(), 2.0);
+    assert_eq!((hit.u(), hit.v()), (0.25, 0.75));
+    assert!(hit.front_face());
+    let beside = Ray::new(Point::new(2.5, 5.0, 0.5), Vec3::new(0.0, -1.0, 0.0));
+    assert!(rect.hit(&material, beside, 0.001, f32::INFINITY).is_none());
+    // A ray in the plane never hits it
+    let along = Ray::new(Point::new(-1.0, 3.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+    assert!(rect.hit(&material, along, 0.001, f32::INFINITY).is_none());
+
+    // Any ray from inside a box leaves through exactly one face, from behind
+    let cube = cuboid(
+        Point::new_singleton(-1.0),
+        Point::new_singleton(1.0),
+        material,
+    );
+    let faces = match &cube {
+        HittableObject::HittableList(faces) => faces,
+        _ => panic!("expected a list of faces"),
+    };
+    assert_eq!(faces.len(), 6);
+    for dir in [
+        Vec3::new(1.0, 0.3, 0.2),
+        Vec3::new(-0.1, -1.0, 0.5),
+        Vec3::new(0.2, 0.1, -1.0),
+    ]
+    .iter()
+    {
+        let r = Ray::new(Point::new_dfl(), *dir);
+        let hits = faces
+            .iter()
+            .filter_map(|f| f.hit(r, 0.001, f32::INFINITY))
+            .collect::<Vec<_>>();
+        assert_eq!(hits.len(), 1);
+        assert!(!hits[0].front_face());
+    }
+}
@@ -1,5 +1,6 @@
 use std::marker::{Send, Sync};
-use std::{f32::INFINITY, u8, usize};
+use std::str::FromStr;
+use std::sync::Arc;
 
 use rand::prelude::StdRng;
 use rand::{Rng, SeedableRng};
@@ -8,16 +9,152 @@ use rayon::prelude::*;
 
 use crate::{
     camera::Camera,
-    hittable::{Hittable, HittableObject},
+    environment::EnvironmentMap,
+    framebuffer::Framebuffer,
+    hittable::{HitRecord, Hittable, HittableObject},
+    light::Lights,
     material::Material,
     ray::Ray,
+    sampling::power_heuristic,
+    spectrum::{sample_wavelength, wavelength_weight, Wavelengths},
     utils::random,
     vec3::{Color, Vec3},
 };
 
-pub fn ray_color<'b, R: Rng + ?Sized>(
+/// Image dimensions and sampling budget for a render
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RenderSettings {
+    pub width: usize,
+    pub height: usize,
+    pub samples_per_pixel: usize,
+    pub max_depth: usize,
+    pub integrator: Integrator,
+}
+
+/// How light is carried along each path
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Integrator {
+    /// Red, green and blue, until dispersion picks a single wavelength
+    Rgb,
+    /// A few wavelengths per path, with RGB colors turned into spectra
+    Spectral,
+}
+
+impl FromStr for Integrator {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rgb" => Ok(Integrator::Rgb),
+            "spectral" => Ok(Integrator::Spectral),
+            _ => Err("expected rgb or spectral".to_string()),
+        }
+    }
+}
+
+/// What a ray that escapes the scene sees, and so the light the scene gets from outside
+#[derive(Clone, Debug, PartialEq)]
+pub enum Background {
+    /// Blend from the horizon color to the zenith color as the ray turns upward
+    Gradient(Color, Color),
+    Constant(Color),
+    /// No light from outside, so only emissive objects light the scene
+    Black,
+    /// A panorama, which surfaces also sample directly toward its bright parts
+    Environment(Arc<EnvironmentMap>),
+}
+
+impl Background {
+    pub fn color(&self, r: Ray) -> Color {
+        match self {
+            Background::Gradient(horizon, zenith) => {
+                let t = r.dir().unit_vector().y();
+                (1.0 - t) * *horizon + t * *zenith
+            }
+            Background::Constant(color) => *color,
+            Background::Black => Color::new_dfl(),
+            Background::Environment(map) => map.color(r.dir()),
+        }
+    }
+}
+
+impl Default for Background {
+    fn default() -> Self {
+        Background::Gradient(Color::new_singleton(1.0), Color::new(0.5, 0.7, 1.0))
+    }
+}
+
+/// Light from one direction toward the environment, chosen in proportion to its
+/// brightness, that reaches the hit and scatters toward `wo`
+fn sample_environment<R: Rng + ?Sized>(
+    map: &EnvironmentMap,
+    world: &HittableObject,
+    wo: Vec3,
+    rec: &HitRecord,
+    wavelengths: Option<Wavelengths>,
+    rng: &mut R,
+) -> Color {
+    let (dir, light, pdf) = match map.sample(random(rng), random(rng)) {
+        Some(sample) => sample,
+        None => return Color::new_dfl(),
+    };
+    let f = rec.mat_ptr().eval(wo, dir, rec) * rec.normal().dot(dir).abs();
+    // Skip the shadow ray when nothing would come of it
+    if f.near_zero()
+        || world
+            .hit(Ray::new(rec.p(), dir), 0.001, f32::INFINITY)
+            .is_some()
+    {
+        return Color::new_dfl();
+    }
+    let weight = power_heuristic(pdf, rec.mat_ptr().pdf(wo, dir, rec));
+    carried(wavelengths, f) * carried(wavelengths, light) * (weight / pdf)
+}
+
+/// Light from a point picked on one of the scene's lights that reaches the hit and
+/// scatters toward `wo`
+fn sample_lights<R: Rng + ?Sized>(
+    lights: &Lights,
+    world: &HittableObject,
+    wo: Vec3,
+    rec: &HitRecord,
+    wavelengths: Option<Wavelengths>,
+    rng: &mut R,
+) -> Color {
+    let (point, pdf) = match lights.sample(rec.p(), random(rng), random(rng), random(rng)) {
+        Some(sample) => sample,
+        None => return Color::new_dfl(),
+    };
+    let distance = (point - rec.p()).length();
+    let dir = (point - rec.p()) / distance;
+    let f = rec.mat_ptr().eval(wo, dir, rec) * rec.normal().dot(dir).abs();
+    if f.near_zero() {
+        return Color::new_dfl();
+    }
+    // The light only counts if the shadow ray's first hit is the point picked on it
+    let shadow = Ray::new(rec.p(), dir);
+    let light = match world.hit(shadow, 0.001, (1.0 + 1e-3) * distance) {
+        Some(hit) if hit.t() >= (1.0 - 1e-3) * distance => hit.mat_ptr().emitted(&hit),
+        _ => return Color::new_dfl(),
+    };
+    let weight = power_heuristic(pdf, rec.mat_ptr().pdf(wo, dir, rec));
+    carried(wavelengths, f) * carried(wavelengths, light) * (weight / pdf)
+}
+
+/// A color as a path carries it, which for a spectral path is its spectrum at the
+/// path's wavelengths
+fn carried(wavelengths: Option<Wavelengths>, rgb: Color) -> Color {
+    match wavelengths {
+        Some(wavelengths) => wavelengths.upsample(rgb),
+        None => rgb,
+    }
+}
+
+pub fn ray_color<R: Rng + ?Sized>(
     r: Ray,
-    world: &'b HittableObject,
+    world: &HittableObject,
+    lights: &Lights,
+    background: &Background,
+    integrator: Integrator,
     depth: usize,
     rng: &mut R,
 ) -> Color {
@@ -37,24 +174,96 @@ pub fn ray_color<'b, R: Rng + ?Sized>(
     //     }
     // }
     //? Iterative, dump2, 11.124s
+    // Light picked up along the path so far, and how much of the next bounce gets through
+    let mut radiance = Color::new_dfl();
     let mut ret_color = Color::new_singleton(1.);
     let mut cur_ray = r;
+    // The density the last bounce chose cur_ray's direction with, when the lights or the
+    // environment could have been sampled toward it too
+    let mut bounce_pdf = None;
+    // The one wavelength the path follows once dispersive glass splits it, and the
+    // absorption of the medium cur_ray travels through
+    let mut wavelength = None;
+    let mut absorption = Color::new_dfl();
+    // A spectral path carries a value for each of its wavelengths in place of red, green
+    // and blue, so colors it meets are turned into spectra, and its light back into RGB.
+    // Each color is turned into a spectrum on its own before any are combined.
+    let wavelengths = match integrator {
+        Integrator::Rgb => None,
+        Integrator::Spectral => Some(Wavelengths::sample(random(rng))),
+    };
+    let output = |radiance: Color| match wavelengths {
+        Some(wavelengths) => wavelengths.to_rgb(radiance),
+        None => radiance,
+    };
     for tmp in (0..depth).rev() {
         if tmp == 0 {
-            return Color::new_dfl();
+            return output(radiance);
         }
-        if let Some(rec) = world.hit(cur_ray, 0.001, INFINITY) {
-            if let Some(scatter_bundle) = rec.mat_ptr().scatter(cur_ray, &rec, rng) {
-                ret_color = scatter_bundle.albedo() * ret_color;
-                cur_ray = scatter_bundle.ray();
+        if let Some(rec) = world.hit(cur_ray, 0.001, f32::INFINITY) {
+            if !absorption.near_zero() {
+                let distance = rec.t() * cur_ray.dir().length();
+                ret_color = ret_color * transmittance(carried(wavelengths, absorption), distance);
+            }
+            if wavelength.is_none() && rec.mat_ptr().is_dispersive() {
+                let lambda = match wavelengths {
+                    // The other wavelengths would bend elsewhere, so only the hero goes on
+                    Some(wavelengths) => {
+                        ret_color = wavelengths.hero_only(ret_color);
+                        wavelengths.hero()
+                    }
+                    None => {
+                        let lambda = sample_wavelength(random(rng));
+                        ret_color = ret_color * wavelength_weight(lambda);
+                        lambda
+                    }
+                };
+                wavelength = Some(lambda);
+            }
+            let rec = rec.with_wavelength(wavelength);
+            let wo = -cur_ray.dir().unit_vector();
+            let emitted = rec.mat_ptr().emitted(&rec);
+            if !emitted.near_zero() {
+                let weight = match bounce_pdf {
+                    Some(pdf) => power_heuristic(pdf, lights.pdf(cur_ray, &rec)),
+                    None => 1.0,
+                };
+                radiance += ret_color * carried(wavelengths, emitted) * weight;
+            }
+            radiance += ret_color * sample_lights(lights, world, wo, &rec, wavelengths, rng);
+            if let Background::Environment(map) = background {
+                radiance += ret_color * sample_environment(map, world, wo, &rec, wavelengths, rng);
+            }
+            if let Some(sample) = rec.mat_ptr().sample(wo, &rec, rng) {
+                bounce_pdf = match sample.is_delta() {
+                    true => None,
+                    false => Some(sample.pdf()),
+                };
+                ret_color = carried(wavelengths, sample.weight()) * ret_color;
+                // Passing through the surface enters its medium from the front, and
+                // leaves it for empty space from the back
+                if sample.wi().dot(rec.normal()) < 0.0 {
+                    absorption = match rec.front_face() {
+                        true => rec.mat_ptr().absorption(),
+                        false => Color::new_dfl(),
+                    };
+                }
+                cur_ray = Ray::new(rec.p(), sample.wi());
             } else {
-                return Color::new_dfl();
+                return output(radiance);
             }
         } else {
-            return ret_color * sky_color(cur_ray);
+            let weight = match (background, bounce_pdf) {
+                (Background::Environment(map), Some(pdf)) => {
+                    power_heuristic(pdf, map.pdf(cur_ray.dir()))
+                }
+                _ => 1.0,
+            };
+            let light = carried(wavelengths, background.color(cur_ray));
+            return output(radiance + ret_color * light * weight);
         }
     }
-    ret_color
+    output(radiance)
     //? (hopefully) tail call optimized recursion, dump3, 11.225s
     // #[tailcall]
     // fn ray_color_tail<'b, R: Rng + ?Sized>(
@@ -84,26 +293,27 @@ pub fn ray_color<'b, R: Rng + ?Sized>(
     // ray_color_tail(r, world, depth, rng, Color::new_singleton(1.))
 }
 
-fn sky_color(r: Ray) -> Color {
-    let t = r.dir().unit_vector().y();
-    (1.0 - t) * Color::new_singleton(1.0) + t * Color::new(0.5, 0.7, 1.0)
+/// The fraction of each channel left after `distance` through a medium absorbing at
+/// the rates `absorption` (Beer-Lambert)
+fn transmittance(absorption: Color, distance: f32) -> Color {
+    Color::new(
+        (-absorption.x() * distance).exp(),
+        (-absorption.y() * distance).exp(),
+        (-absorption.z() * distance).exp(),
+    )
 }
 
-/// Note: colors are 3 u8 in u32 with 8 msb set to 0
-
 fn render_a_row<R: Rng + ?Sized>(
     world: &HittableObject,
-    max_depth: usize,
-    width: usize,
-    height: usize,
+    lights: &Lights,
+    background: &Background,
+    settings: &RenderSettings,
     curr_row: usize,
-    samples_per_pixel: usize,
     cam: &Camera,
     rng: &mut R,
-) -> Vec<u32> {
-    (0..width)
+) -> Vec<Color> {
+    (0..settings.width)
         // .into_par_iter()
-        .into_iter()
         .map(|curr_col| -> Vec3 {
             // (0..samples_per_pixel)
             //     .into_par_iter()
@@ -115,69 +325,365 @@ fn render_a_row<R: Rng + ?Sized>(
             //     })
             //     .reduce(|| Vec3::new_dfl(), |boi, food| boi + food)
             // let mut rng = thread_rng();
-            (0..samples_per_pixel)
-                .into_iter()
+            (0..settings.samples_per_pixel)
                 .map(|_x| -> Vec3 {
                     get_ray_color(
-                        world, max_depth, width, height, curr_row, curr_col, &cam, rng,
+                        world,
+                        lights,
+                        background,
+                        settings,
+                        (curr_row, curr_col),
+                        cam,
+                        rng,
                     )
                 })
                 .fold(Vec3::new_dfl(), |boi, food| boi + food)
         })
-        .map(|color| color_to_bitboi(color, samples_per_pixel))
-        .collect::<Vec<u32>>()
+        .collect::<Vec<Color>>()
 }
 
-fn color_to_bitboi(c: Color, samples_per_pixel: usize) -> u32 {
-    let scale = 1.0 / samples_per_pixel as f32;
-    let color_scale: f32 = 255.999;
-    let zeroish: f32 = 0.0;
-    let oneish: f32 = 0.999;
-    let map_to_u8 = |x: f32| ((x * scale).sqrt().clamp(zeroish, oneish) * color_scale) as u8;
-    0 | (map_to_u8(c.x()) as u32) << 16 | (map_to_u8(c.y()) as u32) << 8 | (map_to_u8(c.z()) as u32)
-}
 /// Do once for each in samplesperpixel
 fn get_ray_color<R: Rng + ?Sized>(
     world: &HittableObject,
-    max_depth: usize,
-    width: usize,
-    height: usize,
-    curr_row: usize,
-    curr_col: usize,
+    lights: &Lights,
+    background: &Background,
+    settings: &RenderSettings,
+    (curr_row, curr_col): (usize, usize),
     cam: &Camera,
     rng: &mut R,
 ) -> Color {
-    let u = (curr_col as f32 + random(rng)) / (width - 1) as f32;
-    let v = (curr_row as f32 + random(rng)) / (height - 1) as f32;
+    let u = (curr_col as f32 + random(rng)) / (settings.width - 1) as f32;
+    let v = (curr_row as f32 + random(rng)) / (settings.height - 1) as f32;
     let r = cam.get_ray(u, v, rng);
-    ray_color(r, world, max_depth, rng)
+    ray_color(
+        r,
+        world,
+        lights,
+        background,
+        settings.integrator,
+        settings.max_depth,
+        rng,
+    )
 }
 
 pub fn render_scene<R: Rng + ?Sized + Sync + Send>(
     world: &HittableObject,
-    max_depth: usize,
-    width: usize,
-    height: usize,
-    samples_per_pixel: usize,
+    lights: &Lights,
+    background: &Background,
+    settings: &RenderSettings,
     cam: Camera,
     _rng: &mut R,
-) -> Vec<Vec<u32>> {
-    (0..height)
+) -> Framebuffer {
+    let rows = (0..settings.height)
         .into_par_iter()
         .rev()
         .map(|row| {
             let mut rng: StdRng = SeedableRng::seed_from_u64(row as u64);
             // eprint!("\rlines remaining: {}", row);
-            render_a_row(
-                world,
-                max_depth,
-                width,
-                height,
-                row,
-                samples_per_pixel,
-                &cam,
+            render_a_row(world, lights, background, settings, row, &cam, &mut rng)
+        })
+        .collect::<Vec<_>>();
+    Framebuffer::from_rows(settings.width, rows, settings.samples_per_pixel as u32)
+}
+
+#[test]
+fn test_ray_color() {
+    use crate::material::MaterialType;
+    use crate::vec3::Point;
+
+    let mut rng = StdRng::seed_from_u64(1);
+    let lamp = HittableObject::Sphere(
+        Point::new(0.0, 0.0, -2.0),
+        0.5,
+        MaterialType::DiffuseLight(Color::new(4.0, 2.0, 1.0).into()),
+    );
+    let world = HittableObject::HittableList(vec![lamp]);
+    let lights = Lights::new(&world);
+    let at_lamp = Ray::new(Point::new_dfl(), Vec3::new(0.0, 0.0, -1.0));
+    let away = Ray::new(Point::new_dfl(), Vec3::new(0.0, 0.0, 1.0));
+    // A light looks the same whatever the background, and a black one adds nothing
+    for background in [Background::Black, Background::default()].iter() {
+        let color = ray_color(
+            at_lamp,
+            &world,
+            &lights,
+            background,
+            Integrator::Rgb,
+            10,
+            &mut rng,
+        );
+        assert_eq!(color, Color::new(4.0, 2.0, 1.0));
+    }
+    assert_eq!(
+        ray_color(
+            away,
+            &world,
+            &lights,
+            &Background::Black,
+            Integrator::Rgb,
+            10,
+            &mut rng
+        ),
+        Color::new_dfl()
+    );
+    let grey = Background::Constant(Color::new_singleton(0.25));
+    assert_eq!(
+        ray_color(away, &world, &lights, &grey, Integrator::Rgb, 10, &mut rng),
+        Color::new_singleton(0.25)
+    );
+}
+
+#[test]
+fn test_spectral_ray_color() {
+    use crate::material::MaterialType;
+    use crate::vec3::Point;
+
+    // A spectral path sees the lamp's color through a few wavelengths at a time, off
+    // by only as much as the spectrum it is given misses the color
+    let mut rng = StdRng::seed_from_u64(7);
+    let color = Color::new(4.0, 2.0, 1.0);
+    let lamp = HittableObject::Sphere(
+        Point::new(0.0, 0.0, -2.0),
+        0.5,
+        MaterialType::DiffuseLight(color.into()),
+    );
+    let world = HittableObject::HittableList(vec![lamp]);
+    let lights = Lights::new(&world);
+    let at_lamp = Ray::new(Point::new_dfl(), Vec3::new(0.0, 0.0, -1.0));
+    let n = 4000;
+    let mean = (0..n)
+        .map(|_| {
+            ray_color(
+                at_lamp,
+                &world,
+                &lights,
+                &Background::Black,
+                Integrator::Spectral,
+                10,
+                &mut rng,
+            )
+        })
+        .fold(Color::new_dfl(), |acc, c| acc + c / n as f32);
+    assert!((mean - color).length() < 0.03 * color.length());
+}
+
+#[test]
+fn test_environment_lighting() {
+    use crate::framebuffer::Framebuffer;
+    use crate::material::MaterialType;
+    use crate::vec3::Point;
+    use std::path::PathBuf;
+
+    // A grey ball under an even white sky reflects its albedo whichever way light is
+    // found, and mostly the same way through a map with one bright spot
+    let mut rng = StdRng::seed_from_u64(4);
+    let ball = HittableObject::Sphere(
+        Point::new_dfl(),
+        1.0,
+        MaterialType::Lambertian(Color::new_singleton(0.5).into()),
+    );
+    let even = Framebuffer::from_pixels(8, 4, vec![Color::new_singleton(1.0); 32]);
+    let even = Background::Environment(Arc::new(EnvironmentMap::new(
+        PathBuf::new(),
+        &even,
+        0.0,
+        1.0,
+    )));
+    let no_lights = Lights::new(&ball);
+    let r = Ray::new(Point::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+    let n = 4000;
+    let mean = (0..n)
+        .map(|_| ray_color(r, &ball, &no_lights, &even, Integrator::Rgb, 10, &mut rng).y())
+        .sum::<f32>()
+        / n as f32;
+    assert!((mean - 0.5).abs() < 0.01);
+
+    let mut pixels = vec![Color::new_singleton(0.1); 32];
+    pixels[8 + 5] = Color::new_singleton(200.0);
+    let spot = Framebuffer::from_pixels(8, 4, pixels);
+    let spot = Background::Environment(Arc::new(EnvironmentMap::new(
+        PathBuf::new(),
+        &spot,
+        0.0,
+        1.0,
+    )));
+    let sample = |rng: &mut StdRng| {
+        let n = 2000;
+        let colors = (0..n)
+            .map(|_| ray_color(r, &ball, &no_lights, &spot, Integrator::Rgb, 10, rng).y())
+            .collect::<Vec<_>>();
+        let mean = colors.iter().sum::<f32>() / n as f32;
+        let variance = colors.iter().map(|c| (c - mean).powi(2)).sum::<f32>() / n as f32;
+        (mean, variance)
+    };
+    let (a, variance) = sample(&mut rng);
+    let (b, _) = sample(&mut rng);
+    assert!((a - b).abs() < 0.1 * a);
+    // Bouncing at random would find the spot about once in a hundred paths
+    assert!(variance.sqrt() < 2.0 * a);
+}
+
+#[test]
+fn test_light_sampling() {
+    use crate::material::MaterialType;
+    use crate::rect::{Plane, Rect};
+    use crate::vec3::Point;
+    use std::f32::consts::PI;
+
+    // Inside a glowing shell a grey ball reflects its albedo, lit half by shadow rays
+    // and half by bounces
+    let mut rng = StdRng::seed_from_u64(5);
+    let grey = MaterialType::Lambertian(Color::new_singleton(0.5).into());
+    let shell = HittableObject::HittableList(vec![
+        HittableObject::Sphere(Point::new_dfl(), 1.0, grey.clone()),
+        HittableObject::Sphere(
+            Point::new_dfl(),
+            10.0,
+            MaterialType::DiffuseLight(Color::new_singleton(1.0).into()),
+        ),
+    ]);
+    let lights = Lights::new(&shell);
+    let r = Ray::new(Point::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+    let n = 4000;
+    let mean = (0..n)
+        .map(|_| {
+            ray_color(
+                r,
+                &shell,
+                &lights,
+                &Background::Black,
+                Integrator::Rgb,
+                10,
+                &mut rng,
+            )
+            .y()
+        })
+        .sum::<f32>()
+        / n as f32;
+    assert!((mean - 0.5).abs() < 0.01);
+
+    // A small lamp over a floor, which bounces would rarely find. The floor directly
+    // below gets E = I cos / d^2 from a lamp of intensity I = L A / pi, looking down.
+    let lamp = Rect::new(Plane::Xz, (-0.05, -0.05), (0.05, 0.05), 2.0).flipped();
+    let room = HittableObject::HittableList(vec![
+        HittableObject::Rect(
+            Rect::new(Plane::Xz, (-10.0, -10.0), (10.0, 10.0), 0.0),
+            grey,
+        ),
+        HittableObject::Rect(
+            lamp,
+            MaterialType::DiffuseLight(Color::new_singleton(1000.0).into()),
+        ),
+    ]);
+    let lights = Lights::new(&room);
+    let r = Ray::new(Point::new(0.0, 1.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+    let colors = (0..n)
+        .map(|_| {
+            ray_color(
+                r,
+                &room,
+                &lights,
+                &Background::Black,
+                Integrator::Rgb,
+                2,
                 &mut rng,
             )
+            .y()
         })
-        .collect::<Vec<_>>()
+        .collect::<Vec<_>>();
+    let mean = colors.iter().sum::<f32>() / n as f32;
+    let expected = 0.5 / PI * 1000.0 * 0.01 / 4.0;
+    assert!((mean - expected).abs() < 0.02 * expected);
+    let variance = colors.iter().map(|c| (c - mean).powi(2)).sum::<f32>() / n as f32;
+    assert!(variance.sqrt() < 0.1 * mean);
+}
+
+#[test]
+fn test_coloured_glass() {
+    use crate::material::{Ior, MaterialType};
+    use crate::vec3::Point;
+
+    // Glass that bends nothing passes a ray straight through its middle, which loses
+    // what two units of the inside absorb
+    let mut rng = StdRng::seed_from_u64(6);
+    let absorption = Color::new(1.0, 0.5, 0.0);
+    let ball = HittableObject::Sphere(
+        Point::new_dfl(),
+        1.0,
+        MaterialType::Dielectric(Ior::Constant(1.0), absorption),
+    );
+    let white = Background::Constant(Color::new_singleton(1.0));
+    let no_lights = Lights::new(&ball);
+    let r = Ray::new(Point::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+    let color = ray_color(r, &ball, &no_lights, &white, Integrator::Rgb, 10, &mut rng);
+    let expected = Color::new((-2.0_f32).exp(), (-1.0_f32).exp(), 1.0);
+    assert!((color - expected).length() < 1e-5);
+
+    // Dispersive glass sends each path through as one wavelength, which tints it, but
+    // the paths together are still white
+    let prism = HittableObject::Sphere(
+        Point::new_dfl(),
+        1.0,
+        MaterialType::Dielectric(Ior::Cauchy(1.0, 0.001), Color::new_dfl()),
+    );
+    let n = 20000;
+    for integrator in [Integrator::Rgb, Integrator::Spectral].iter() {
+        let colors = (0..n)
+            .map(|_| ray_color(r, &prism, &no_lights, &white, *integrator, 10, &mut rng))
+            .collect::<Vec<_>>();
+        assert!(colors.iter().any(|c| c.x() > 2.0 * c.z().abs()));
+        assert!(colors.iter().any(|c| c.z() > 2.0 * c.x().abs()));
+        let mean = colors
+            .iter()
+            .fold(Color::new_dfl(), |acc, c| acc + *c / n as f32);
+        assert!((mean - Color::new_singleton(1.0)).length() < 0.05);
+    }
+
+    // Spectral paths absorb each wavelength at its own rate from the absorption's
+    // spectrum, which is not quite what the RGB rates give, but still red most and
+    // blue least
+    let expected = (0..n)
+        .map(|i| Wavelengths::sample((i as f32 + 0.5) / n as f32))
+        .map(|w| w.to_rgb(transmittance(w.upsample(absorption), 2.0)))
+        .fold(Color::new_dfl(), |acc, c| acc + c / n as f32);
+    let mean = (0..n)
+        .map(|_| {
+            ray_color(
+                r,
+                &ball,
+                &no_lights,
+                &white,
+                Integrator::Spectral,
+                10,
+                &mut rng,
+            )
+        })
+        .fold(Color::new_dfl(), |acc, c| acc + c / n as f32);
+    assert!((mean - expected).length() < 0.03);
+    assert!(mean.x() < mean.y() && mean.y() < mean.z());
+}
+
+#[test]
+fn test_render_scene() {
+    use crate::camera::CameraSettings;
+
+    // Every sample of an empty scene sees the background, so each pixel's mean is the
+    // background however bright, after summing every sample
+    let world = HittableObject::HittableList(Vec::new());
+    let lights = Lights::new(&world);
+    let background = Background::Constant(Color::new(0.25, 1.0, 3.0));
+    let settings = RenderSettings {
+        width: 4,
+        height: 3,
+        samples_per_pixel: 5,
+        max_depth: 4,
+        integrator: Integrator::Rgb,
+    };
+    let cam = CameraSettings::new_dfl().build(4.0 / 3.0);
+    let mut rng = StdRng::seed_from_u64(2);
+    let fb = render_scene(&world, &lights, &background, &settings, cam, &mut rng);
+    assert_eq!((fb.width(), fb.height()), (4, 3));
+    for pixel in fb.pixels() {
+        assert!((pixel - Color::new(0.25, 1.0, 3.0)).length() < 1e-5);
+    }
 }
@@ -0,0 +1,136 @@
+//! Drawing samples in proportion to a tabulated function, and weighting the estimates of
+//! several sampling strategies against each other.
+
+/// A piecewise constant function over [0, 1), sampled by inverting its running sum
+#[derive(Clone, Debug, PartialEq)]
+pub struct Distribution1D {
+    func: Vec<f32>,
+    cdf: Vec<f32>,
+    integral: f32,
+}
+
+impl Distribution1D {
+    /// Negative values are taken as 0. A function that is 0 everywhere is sampled
+    /// uniformly instead.
+    pub fn new(func: Vec<f32>) -> Self {
+        assert!(!func.is_empty());
+        let n = func.len() as f32;
+        let func = func.into_iter().map(|f| f.max(0.0)).collect::<Vec<_>>();
+        let mut cdf = vec![0.0];
+        for f in func.iter() {
+            cdf.push(cdf[cdf.len() - 1] + f / n);
+        }
+        let integral = cdf[cdf.len() - 1];
+        for (i, c) in cdf.iter_mut().enumerate() {
+            *c = match integral > 0.0 {
+                true => *c / integral,
+                false => i as f32 / n,
+            };
+        }
+        Self {
+            func,
+            cdf,
+            integral,
+        }
+    }
+
+    /// Get the distribution's integral over [0, 1).
+    pub fn integral(&self) -> f32 {
+        self.integral
+    }
+
+    /// The point in [0, 1) that `u` maps to, its density and the piece it lies in
+    pub fn sample(&self, u: f32) -> (f32, f32, usize) {
+        // The last piece whose running sum starts at or below u
+        let i = (self.cdf.partition_point(|&c| c <= u) - 1).min(self.func.len() - 1);
+        let width = self.cdf[i + 1] - self.cdf[i];
+        let offset = match width > 0.0 {
+            true => (u - self.cdf[i]) / width,
+            false => 0.0,
+        };
+        let x = ((i as f32 + offset) / self.func.len() as f32).min(1.0 - f32::EPSILON);
+        (x, self.pdf_of(i), i)
+    }
+
+    /// The density of sampling anywhere in piece `i`
+    pub fn pdf_of(&self, i: usize) -> f32 {
+        match self.integral > 0.0 {
+            true => self.func[i] / self.integral,
+            false => 1.0,
+        }
+    }
+
+    /// The piece that `x` in [0, 1) lies in
+    pub fn piece(&self, x: f32) -> usize {
+        ((x * self.func.len() as f32) as usize).min(self.func.len() - 1)
+    }
+}
+
+/// A piecewise constant function over the unit square, given as rows in order of v.
+/// A row is picked by its total, then a column within it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Distribution2D {
+    rows: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    pub fn new(width: usize, values: &[f32]) -> Self {
+        let rows = values
+            .chunks_exact(width)
+            .map(|row| Distribution1D::new(row.to_vec()))
+            .collect::<Vec<_>>();
+        let marginal = Distribution1D::new(rows.iter().map(|row| row.integral()).collect());
+        Self { rows, marginal }
+    }
+
+    /// The point (u, v) that two uniform numbers map to, and its density
+    pub fn sample(&self, u0: f32, u1: f32) -> ((f32, f32), f32) {
+        let (v, pdf_v, row) = self.marginal.sample(u1);
+        let (u, pdf_u, _) = self.rows[row].sample(u0);
+        ((u, v), pdf_v * pdf_u)
+    }
+
+    pub fn pdf(&self, u: f32, v: f32) -> f32 {
+        let row = self.marginal.piece(v);
+        self.marginal.pdf_of(row) * self.rows[row].pdf_of(self.rows[row].piece(u))
+    }
+}
+
+/// Veach's power heuristic weight for a sample drawn with density `pdf` when another
+/// strategy could have drawn it with density `other`
+pub fn power_heuristic(pdf: f32, other: f32) -> f32 {
+    let (a, b) = (pdf * pdf, other * other);
+    match a + b > 0.0 {
+        true => a / (a + b),
+        false => 0.0,
+    }
+}
+
+#[test]
+fn test_distribution() {
+    let d = Distribution1D::new(vec![1.0, 0.0, 3.0]);
+    assert!((d.integral() - 4.0 / 3.0).abs() < 1e-6);
+    // The empty middle piece is never chosen
+    let (x, pdf, i) = d.sample(0.2);
+    assert_eq!(i, 0);
+    assert!((x - 0.8 / 3.0).abs() < 1e-6);
+    assert!((pdf - 0.75).abs() < 1e-6);
+    let (x, pdf, i) = d.sample(0.25);
+    assert_eq!(i, 2);
+    assert!((x - 2.0 / 3.0).abs() < 1e-6);
+    assert!((pdf - 2.25).abs() < 1e-6);
+    assert_eq!(d.sample(0.999_999).2, 2);
+
+    let flat = Distribution1D::new(vec![0.0, 0.0]);
+    assert_eq!(flat.sample(0.75), (0.75, 1.0, 1));
+
+    // Samples follow the function, and the density agrees with where they land
+    let d2 = Distribution2D::new(2, &[1.0, 1.0, 0.0, 2.0]);
+    let ((u, v), pdf) = d2.sample(0.1, 0.9);
+    assert!(u > 0.5 && v > 0.5);
+    assert!((pdf - d2.pdf(u, v)).abs() < 1e-6);
+    assert!((d2.pdf(0.75, 0.75) - 2.0).abs() < 1e-6);
+    assert_eq!(d2.pdf(0.25, 0.75), 0.0);
+    assert!((power_heuristic(1.0, 1.0) - 0.5).abs() < 1e-6);
+}
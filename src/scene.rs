@@ -1,25 +1,59 @@
 // use rand::Rng;
 
+use std::sync::Arc;
+
 use rand::Rng;
 
+use crate::camera::CameraSettings;
 use crate::hittable::HittableObject;
-use crate::material::MaterialType;
+use crate::instance::Instance;
+use crate::material::{Ior, MaterialType};
+use crate::rect::{cuboid, Plane, Rect};
+use crate::render::Background;
 use crate::utils::{random, random_range};
-use crate::vec3::{Color, Point};
+use crate::vec3::{Color, Point, Transform, Vec3};
+
+/// A world together with how to look at it and, optionally, how to render it
+pub struct Scene {
+    pub world: HittableObject,
+    pub camera: CameraSettings,
+    pub background: Background,
+    pub width: Option<usize>,
+    pub height: Option<usize>,
+    pub samples_per_pixel: Option<usize>,
+    pub max_depth: Option<usize>,
+    /// What was skipped while loading the scene's models
+    pub warnings: Vec<String>,
+}
+
+impl Scene {
+    pub fn new(world: HittableObject, camera: CameraSettings) -> Self {
+        Self {
+            world,
+            camera,
+            background: Background::default(),
+            width: None,
+            height: None,
+            samples_per_pixel: None,
+            max_depth: None,
+            warnings: Vec::new(),
+        }
+    }
+}
 
 pub fn img_11() -> HittableObject {
     let mut world = Vec::<HittableObject>::new();
     // let ground_mat = Box::new(MaterialType::Lambertian(Color::new(0.8, 0.8, 0.)));
-    let mat_ground = MaterialType::Lambertian(Color::new(0.8, 0.8, 0.));
+    let mat_ground = MaterialType::Lambertian(Color::new(0.8, 0.8, 0.).into());
     let ground = HittableObject::Sphere(Point::new(0., -100.5, -1.), 100., mat_ground);
     world.push(ground);
-    let mat_center = MaterialType::Lambertian(Color::new(0.7, 0.3, 0.3));
+    let mat_center = MaterialType::Lambertian(Color::new(0.7, 0.3, 0.3).into());
     let center = HittableObject::Sphere(Point::new(0., 0., -1.), 0.5, mat_center);
     world.push(center);
-    let mat_left = MaterialType::Metal(Color::new_singleton(0.8), 0.);
+    let mat_left = MaterialType::Metal(Color::new_singleton(0.8).into(), 0.);
     let left = HittableObject::Sphere(Point::new(-1., 0., -1.), 0.5, mat_left);
     world.push(left);
-    let mat_right = MaterialType::Metal(Color::new(0.8, 0.6, 0.2), 0.);
+    let mat_right = MaterialType::Metal(Color::new(0.8, 0.6, 0.2).into(), 0.);
     let right = HittableObject::Sphere(Point::new(1., 0., -1.), 0.5, mat_right);
     world.push(right);
     HittableObject::HittableList(world)
@@ -27,7 +61,7 @@ pub fn img_11() -> HittableObject {
 
 pub fn random_scene<R: Rng + ?Sized>(rng: &mut R) -> HittableObject {
     let mut world = Vec::<HittableObject>::new();
-    let ground_material = MaterialType::Lambertian(Color::new(0.5, 0.5, 0.5));
+    let ground_material = MaterialType::Lambertian(Color::new(0.5, 0.5, 0.5).into());
     world.push(HittableObject::Sphere(
         Point::new(0.0, -1000.0, 0.0),
         1000.0,
@@ -43,32 +77,32 @@ pub fn random_scene<R: Rng + ?Sized>(rng: &mut R) -> HittableObject {
             );
             if (center - Point::new(4.0, 0.2, 0.0)).length() > 0.9 {
                 let sphere_material = match choose_mat {
-                    x if x < 0.8 => {
-                        MaterialType::Lambertian(Color::random_vec3(rng) * Color::random_vec3(rng))
-                    }
+                    x if x < 0.8 => MaterialType::Lambertian(
+                        (Color::random_vec3(rng) * Color::random_vec3(rng)).into(),
+                    ),
                     x if x < 0.95 => MaterialType::Metal(
-                        Color::random_vec3_range(0.5, 1.0, rng),
+                        Color::random_vec3_range(0.5, 1.0, rng).into(),
                         random_range(0.0, 0.5, rng),
                     ),
-                    _ => (MaterialType::Dielectric(1.5)),
+                    _ => MaterialType::Dielectric(Ior::Constant(1.5), Color::new_dfl()),
                 };
                 world.push(HittableObject::Sphere(center, 0.2, sphere_material));
             }
         }
     }
-    let material1 = MaterialType::Dielectric(1.5);
+    let material1 = MaterialType::Dielectric(Ior::Constant(1.5), Color::new_dfl());
     world.push(HittableObject::Sphere(
         Point::new(0.0, 1.0, 0.0),
         1.0,
         material1,
     ));
-    let material2 = MaterialType::Lambertian(Color::new(0.4, 0.2, 0.1));
+    let material2 = MaterialType::Lambertian(Color::new(0.4, 0.2, 0.1).into());
     world.push(HittableObject::Sphere(
         Point::new(-4.0, 1.0, 0.0),
         1.0,
         material2,
     ));
-    let material3 = MaterialType::Metal(Color::new(0.7, 0.6, 0.5), 0.0);
+    let material3 = MaterialType::Metal(Color::new(0.7, 0.6, 0.5).into(), 0.0);
     world.push(HittableObject::Sphere(
         Point::new(4.0, 1.0, 0.0),
         1.0,
@@ -76,9 +110,49 @@ pub fn random_scene<R: Rng + ?Sized>(rng: &mut R) -> HittableObject {
     ));
     HittableObject::HittableList(world)
 }
+
 pub fn debug_scene() -> HittableObject {
     let mut world = Vec::<HittableObject>::new();
-    let material = MaterialType::Dielectric(1.5);
+    let material = MaterialType::Dielectric(Ior::Constant(1.5), Color::new_dfl());
     world.push(HittableObject::Sphere(Point::new_dfl(), 1., material));
     HittableObject::HittableList(world)
 }
+
+/// The Cornell box: a room lit through a hole in its ceiling, with two boxes inside
+pub fn cornell_box() -> HittableObject {
+    let red = MaterialType::Lambertian(Color::new(0.65, 0.05, 0.05).into());
+    let white = MaterialType::Lambertian(Color::new_singleton(0.73).into());
+    let green = MaterialType::Lambertian(Color::new(0.12, 0.45, 0.15).into());
+    let light = MaterialType::DiffuseLight(Color::new_singleton(15.0).into());
+    let wall = |plane: Plane, offset: f32| Rect::new(plane, (0.0, 0.0), (555.0, 555.0), offset);
+    let mut world = vec![
+        HittableObject::Rect(wall(Plane::Yz, 555.0).flipped(), green),
+        HittableObject::Rect(wall(Plane::Yz, 0.0), red),
+        HittableObject::Rect(
+            Rect::new(Plane::Xz, (213.0, 227.0), (343.0, 332.0), 554.0).flipped(),
+            light,
+        ),
+        HittableObject::Rect(wall(Plane::Xz, 0.0), white.clone()),
+        HittableObject::Rect(wall(Plane::Xz, 555.0).flipped(), white.clone()),
+        HittableObject::Rect(wall(Plane::Xy, 555.0).flipped(), white.clone()),
+    ];
+    let mut block = |size: Vec3, degrees: f32, at: Vec3| {
+        let object = cuboid(Point::new_dfl(), size, white.clone());
+        let transform = Transform::rotate(1, degrees).then(&Transform::translate(at));
+        world.push(HittableObject::Instance(Box::new(Instance::new(
+            Arc::new(object),
+            transform,
+        ))));
+    };
+    block(
+        Vec3::new(165.0, 330.0, 165.0),
+        15.0,
+        Vec3::new(265.0, 0.0, 295.0),
+    );
+    block(
+        Vec3::new_singleton(165.0),
+        -18.0,
+        Vec3::new(130.0, 0.0, 65.0),
+    );
+    HittableObject::HittableList(world)
+}
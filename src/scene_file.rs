@@ -0,0 +1,1937 @@
+//! Plain text scene descriptions.
+//!
+//! A scene file is a list of statements, one per line. Each statement is a keyword,
+//! then any positional arguments, then `key=value` parameters. `#` starts a comment.
+//! A value in double quotes, such as `path="my textures/earth.png"`, may hold spaces
+//! and `#`, but not a double quote.
+//!
+//! ```text
+//! render width=400 height=225 spp=100 max_depth=50
+//! camera lookfrom=13,2,3 lookat=0,0,0 vup=0,1,0 vfov=20 aperture=0.1 focus_dist=10
+//! background gradient horizon=1,1,1 zenith=0.5,0.7,1
+//!
+//! texture tiles checker even=0.2,0.3,0.1 odd=0.9 scale=0.5
+//! texture stone marble scale=4 color=0.9,0.85,0.8 seed=2
+//! texture map image path=textures/earth.png
+//!
+//! material ground lambertian albedo=tiles
+//! material gold metal albedo=0.8,0.6,0.2 fuzz=0.1
+//! material glass dielectric ior=1.5
+//! material frost dielectric ior=1.5 roughness=0.3
+//! material wine dielectric ior=1.34 absorption=0.1,2,1.5
+//! material prism dielectric ior=sf11
+//! material brushed conductor preset=aluminium roughness=0.2
+//! material paint principled base_color=0.8,0.1,0.1 roughness=0.4 clearcoat=1
+//! material lamp light emit=4
+//!
+//! sphere center=0,-1000,0 radius=1000 material=ground
+//! group
+//!     sphere center=0,1,0 radius=1 material=glass
+//!     sphere center=4,1,0 radius=1 material=gold
+//! end
+//! triangle a=-1,0,2 b=1,0,2 c=0,2,2 material=gold
+//! rect -xz min=-1,-1 max=1,1 at=5 material=lamp
+//! box min=-1,0,-1 max=1,0.5,1 rotate=0,30,0 translate=0,0,3 material=ground
+//! mesh material=ground
+//!     vertex position=0,0,0 normal=0,1,0 uv=0,0 color=1,0.5,0.5
+//!     vertex position=1,0,0 normal=0,1,0 uv=1,0 color=1
+//!     vertex position=0,0,1 normal=0,1,0 uv=0,1 color=1
+//!     face 0 1 2
+//! end
+//! model path=models/teapot.obj scale=0.5 rotate=0,45,0 translate=2,0,0
+//!
+//! object pillar
+//!     sphere center=0,1,0 radius=0.5 material=gold
+//!     sphere center=0,2,0 radius=0.25 material=gold
+//! end
+//! instance pillar translate=-3,0,0
+//! instance pillar scale=1,2,1 translate=-4,0,0
+//! ```
+//!
+//! Vectors and colors are written as `x,y,z`, or as a single number for all three.
+//! Materials must be declared before they are used, and `group ... end` blocks nest.
+//! A `light` material gives off its `emit` color and scatters nothing.
+//!
+//! A `conductor` is a metal with GGX microfacet `roughness=` from 0, a mirror, to 1.
+//! Its color comes from its complex index of refraction, either a `preset=` of `gold`,
+//! `copper` or `aluminium`, or `eta=` and `k=` for each channel. A `dielectric` given a
+//! `roughness=` is frosted the same way.
+//!
+//! A dielectric's `ior=` is a number or one of the glasses `bk7`, `fused_silica` or
+//! `sf11`, which bend each wavelength differently and so split white light into
+//! rainbows. `cauchy=a,b` or `sellmeier=b1,b2,b3,c1,c2,c3` give other such glasses,
+//! with wavelengths in micrometres. Light inside loses each channel at the rate
+//! `absorption=` per unit of distance, so thicker glass is more deeply colored.
+//!
+//! A `principled` material blends a diffuse base, a metal, glass and a clear coat by
+//! `base_color=`, `metallic=`, `roughness=`, `specular=`, `specular_tint=`, `sheen=`,
+//! `clearcoat=`, `transmission=` and `ior=`, all optional. Each is a number or a texture,
+//! whose brightness is used for all but the base color.
+//!
+//! A material's `albedo` or `emit` is a color or the name of a texture declared before
+//! it. Textures are a `solid color=`, a 3D `checker` of cubes of side `scale=` between
+//! the textures or colors `even=` and `odd=`, Perlin `noise`, `turbulence` or `marble`
+//! at frequency `scale=` tinting `color=`, with the same pattern for the same `seed=`,
+//! or an `image path=` relative to the scene file. Images repeat outside texture
+//! coordinates of 0 to 1, and `.png` and `.ppm` ones are taken to be sRGB encoded.
+//! A saved scene refers to images by their absolute path.
+//!
+//! The `background` is what rays escaping the scene see: a `gradient` from `horizon=`
+//! up to `zenith=`, a `constant color=`, or `black` so that only lights light the
+//! scene, or an `environment path=` panorama in a `.hdr` or `.pfm` file. The middle of
+//! the panorama faces -z before it is turned by `rotate=` degrees about +y, and its
+//! light is scaled by `intensity=`. `sky horizon= zenith=` is the older spelling of a
+//! gradient.
+//!
+//! `rect PLANE` is the rectangle from `min=` to `max=` in the two axes of `xy`, `xz` or
+//! `yz`, lying at `at=` along the third. Its outside faces up that axis, or down it
+//! when the plane is written with a leading `-`. `box` is the six rects between the
+//! corners `min=` and `max=`, facing out, and can be transformed like an instance.
+//!
+//! A `mesh ... end` block holds only `vertex` and `face` statements. Vertices are
+//! numbered from 0 in order and each needs a normal, uv or color if any other does.
+//! Colors tint the albedo of the mesh's material. Faces with more than three vertices
+//! are split into a fan of triangles.
+//!
+//! `model path=PATH` loads the meshes of a Wavefront `.obj`, `.ply` or `.stl` file,
+//! relative to the scene file, with its own materials unless `material=NAME` replaces
+//! them all. A file is only loaded once, however many times it is placed.
+//!
+//! An `object NAME ... end` block at the top level defines objects without adding them
+//! to the scene, and each `instance NAME` adds a copy sharing the same memory. Models
+//! and instances are scaled by `scale=`, rotated by `rotate=` degrees about x, y then
+//! z, and moved by `translate=`, or else transformed by `matrix=` with the 12 numbers
+//! of the top three rows of an affine 4x4 matrix, acting on column vectors.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::camera::CameraSettings;
+use crate::environment::EnvironmentMap;
+use crate::hittable::HittableObject;
+use crate::instance::Instance;
+use crate::material::{ConductorPreset, Ior, MaterialType};
+use crate::mesh::Mesh;
+use crate::obj::load_obj;
+use crate::perlin::Perlin;
+use crate::ply::load_ply;
+use crate::principled::Principled;
+use crate::rect::{cuboid, Plane, Rect};
+use crate::render::Background;
+use crate::scene::Scene;
+use crate::spectrum::{LAMBDA_MAX, LAMBDA_MIN};
+use crate::stl::load_stl;
+use crate::texture::{ImageTexture, TextureType};
+use crate::vec3::{Transform, Vec3};
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io(io::Error),
+    Parse {
+        line: usize,
+        column: usize,
+        message: String,
+    },
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Io(e) => write!(f, "{}", e),
+            SceneError::Parse {
+                line,
+                column,
+                message,
+            } => write!(f, "{}:{}: {}", line, column, message),
+        }
+    }
+}
+
+impl From<io::Error> for SceneError {
+    fn from(e: io::Error) -> Self {
+        SceneError::Io(e)
+    }
+}
+
+pub fn load_scene<P: AsRef<Path>>(path: P) -> Result<Scene, SceneError> {
+    let path = path.as_ref();
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    parse_scene(&fs::read_to_string(path)?, dir)
+}
+
+#[derive(Clone, Copy)]
+struct Token<'a> {
+    text: &'a str,
+    line: usize,
+    column: usize,
+}
+
+impl<'a> Token<'a> {
+    fn error<S: Into<String>>(&self, message: S) -> SceneError {
+        SceneError::Parse {
+            line: self.line,
+            column: self.column,
+            message: message.into(),
+        }
+    }
+
+    /// The part of this token starting `offset` bytes in
+    fn slice(&self, offset: usize, len: usize) -> Token<'a> {
+        Token {
+            text: &self.text[offset..offset + len],
+            line: self.line,
+            column: self.column + self.text[..offset].chars().count(),
+        }
+    }
+
+    fn parse_f32(&self) -> Result<f32, SceneError> {
+        match self.text.parse::<f32>() {
+            Ok(x) if x.is_finite() => Ok(x),
+            _ => Err(self.error(format!("expected a number, found '{}'", self.text))),
+        }
+    }
+
+    fn parse_usize(&self) -> Result<usize, SceneError> {
+        self.text.parse::<usize>().map_err(|_| {
+            self.error(format!(
+                "expected a non-negative integer, found '{}'",
+                self.text
+            ))
+        })
+    }
+
+    fn parse_list(&self) -> Result<Vec<f32>, SceneError> {
+        let mut parts = Vec::new();
+        let mut offset = 0;
+        for part in self.text.split(',') {
+            parts.push(self.slice(offset, part.len()).parse_f32()?);
+            offset += part.len() + 1;
+        }
+        Ok(parts)
+    }
+
+    fn parse_uv(&self) -> Result<[f32; 2], SceneError> {
+        match self.parse_list()?[..] {
+            [u, v] => Ok([u, v]),
+            ref parts => Err(self.error(format!(
+                "expected 2 comma separated numbers, found {}",
+                parts.len()
+            ))),
+        }
+    }
+
+    fn parse_vec3(&self) -> Result<Vec3, SceneError> {
+        let parts = self.parse_list()?;
+        match parts[..] {
+            [e] => Ok(Vec3::new_singleton(e)),
+            [x, y, z] => Ok(Vec3::new(x, y, z)),
+            _ => Err(self.error(format!(
+                "expected 1 or 3 comma separated numbers, found {}",
+                parts.len()
+            ))),
+        }
+    }
+}
+
+fn tokenize(line: &str, line_number: usize) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+    let mut quoted = false;
+    let mut end = line.len();
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '#' if !quoted => {
+                end = i;
+                break;
+            }
+            _ => {}
+        }
+    }
+    quoted = false;
+    for (i, c) in line[..end]
+        .char_indices()
+        .chain(std::iter::once((end, ' ')))
+    {
+        // An unclosed quote still ends at the end of the line
+        quoted ^= c == '"';
+        match ((c.is_whitespace() && !quoted) || i == end, start) {
+            (true, Some(s)) => {
+                tokens.push(Token {
+                    text: &line[s..i],
+                    line: line_number,
+                    column: line[..s].chars().count() + 1,
+                });
+                start = None;
+            }
+            (false, None) => start = Some(i),
+            _ => {}
+        }
+    }
+    tokens
+}
+
+/// One line of a scene file, split into its keyword, positional arguments and parameters
+struct Statement<'a> {
+    keyword: Token<'a>,
+    positional: Vec<Token<'a>>,
+    params: Vec<(Token<'a>, Token<'a>)>,
+}
+
+impl<'a> Statement<'a> {
+    fn new(tokens: Vec<Token<'a>>) -> Result<Self, SceneError> {
+        let keyword = tokens[0];
+        let mut positional = Vec::new();
+        let mut params: Vec<(Token, Token)> = Vec::new();
+        for token in tokens.into_iter().skip(1) {
+            match token.text.find('=') {
+                Some(eq) => {
+                    let key = token.slice(0, eq);
+                    let mut value = token.slice(eq + 1, token.text.len() - eq - 1);
+                    if let Some(inner) = value.text.strip_prefix('"') {
+                        match inner.find('"') {
+                            Some(end) if end == inner.len() - 1 => value = value.slice(1, end),
+                            Some(_) => {
+                                return Err(value.error("expected nothing after a closing quote"))
+                            }
+                            None => return Err(value.error("missing closing quote")),
+                        }
+                    }
+                    if key.text.is_empty() {
+                        return Err(token.error("missing parameter name before '='"));
+                    }
+                    if value.text.is_empty() {
+                        return Err(value.error(format!("missing value for '{}'", key.text)));
+                    }
+                    if params.iter().any(|(k, _)| k.text == key.text) {
+                        return Err(key.error(format!("'{}' is given more than once", key.text)));
+                    }
+                    params.push((key, value));
+                }
+                None if params.is_empty() => positional.push(token),
+                None => {
+                    return Err(token.error(format!(
+                        "expected key=value, found '{}' after parameters",
+                        token.text
+                    )))
+                }
+            }
+        }
+        Ok(Self {
+            keyword,
+            positional,
+            params,
+        })
+    }
+
+    /// Check the statement has exactly the positional arguments named in `names`
+    fn expect_positional(&self, names: &[&str]) -> Result<(), SceneError> {
+        match (self.positional.len(), names.len()) {
+            (found, wanted) if found < wanted => Err(self
+                .keyword
+                .error(format!("'{}' needs a {}", self.keyword.text, names[found]))),
+            (found, wanted) if found > wanted => Err(self.positional[wanted].error(format!(
+                "unexpected argument '{}'",
+                self.positional[wanted].text
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    fn param(&self, key: &str) -> Option<Token<'a>> {
+        self.params
+            .iter()
+            .find(|(k, _)| k.text == key)
+            .map(|(_, v)| *v)
+    }
+
+    fn required(&self, key: &str) -> Result<Token<'a>, SceneError> {
+        self.param(key).ok_or_else(|| {
+            self.keyword
+                .error(format!("'{}' needs a '{}'", self.keyword.text, key))
+        })
+    }
+
+    fn f32_or(&self, key: &str, default: f32) -> Result<f32, SceneError> {
+        self.param(key).map_or(Ok(default), |t| t.parse_f32())
+    }
+
+    fn vec3_or(&self, key: &str, default: Vec3) -> Result<Vec3, SceneError> {
+        self.param(key).map_or(Ok(default), |t| t.parse_vec3())
+    }
+
+    /// Reject any parameter not in `allowed`
+    fn allow_only(&self, allowed: &[&str]) -> Result<(), SceneError> {
+        match self.params.iter().find(|(k, _)| !allowed.contains(&k.text)) {
+            Some((key, _)) => Err(key.error(format!(
+                "unknown parameter '{}' for '{}', expected one of: {}",
+                key.text,
+                self.keyword.text,
+                allowed.join(", ")
+            ))),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The index of refraction given by exactly one of `ior=`, `cauchy=` or `sellmeier=`
+fn parse_ior(stmt: &Statement) -> Result<Ior, SceneError> {
+    let given = ["ior", "cauchy", "sellmeier"]
+        .iter()
+        .filter_map(|key| stmt.param(key).map(|t| (*key, t)))
+        .collect::<Vec<_>>();
+    let (key, token) = match given[..] {
+        [] => ("ior", stmt.required("ior")?),
+        [given] => given,
+        [_, (_, extra), ..] => {
+            return Err(extra.error("give only one of 'ior', 'cauchy' or 'sellmeier'"))
+        }
+    };
+    let ior = match key {
+        "ior" => token.text.parse::<Ior>().map_err(|e| token.error(e))?,
+        _ => match (key, &token.parse_list()?[..]) {
+            ("cauchy", &[a, b]) => Ior::Cauchy(a, b),
+            ("sellmeier", &[b0, b1, b2, c0, c1, c2]) => Ior::Sellmeier([b0, b1, b2], [c0, c1, c2]),
+            ("cauchy", _) => return Err(token.error("expected the 2 numbers a,b")),
+            _ => return Err(token.error("expected the 6 numbers b1,b2,b3,c1,c2,c3")),
+        },
+    };
+    // A Sellmeier term has a pole where λ² = c, which gives no sensible index if it
+    // falls within visible light. Without one the index is smooth, so checking it
+    // every nanometre catches it going negative.
+    let visible = LAMBDA_MIN * 1e-3..=LAMBDA_MAX * 1e-3;
+    let pole = match ior {
+        Ior::Sellmeier(b, c) => b
+            .iter()
+            .zip(c.iter())
+            .any(|(&b, &c)| b != 0.0 && c > 0.0 && visible.contains(&c.sqrt())),
+        _ => false,
+    };
+    let positive = (LAMBDA_MIN as usize..=LAMBDA_MAX as usize).all(|l| {
+        let n = ior.at(Some(l as f32));
+        n.is_finite() && n > 0.0
+    });
+    match !pole && positive {
+        true => Ok(ior),
+        false => Err(token.error("must give a positive index across visible light")),
+    }
+}
+
+fn positive(token: Token, x: f32) -> Result<f32, SceneError> {
+    match x > 0.0 {
+        true => Ok(x),
+        false => Err(token.error("must be greater than 0")),
+    }
+}
+
+fn at_least(token: Token, n: usize, min: usize) -> Result<usize, SceneError> {
+    match n >= min {
+        true => Ok(n),
+        false => Err(token.error(format!("must be at least {}", min))),
+    }
+}
+
+type Textures<'a> = HashMap<&'a str, TextureType>;
+
+/// A color, or the name of a texture declared earlier
+fn parse_texture(token: Token, textures: &Textures) -> Result<TextureType, SceneError> {
+    match textures.get(token.text) {
+        Some(texture) => Ok(texture.clone()),
+        None if token.text.starts_with(char::is_alphabetic) => {
+            Err(token.error(format!("unknown texture '{}'", token.text)))
+        }
+        None => Ok(TextureType::Solid(token.parse_vec3()?)),
+    }
+}
+
+fn parse_texture_type(
+    stmt: &Statement,
+    textures: &Textures,
+    dir: &Path,
+) -> Result<TextureType, SceneError> {
+    let kind = stmt.positional[1];
+    let scale = |default: f32| match stmt.param("scale") {
+        Some(t) => positive(t, t.parse_f32()?),
+        None => Ok(default),
+    };
+    match kind.text {
+        "solid" => {
+            stmt.allow_only(&["color"])?;
+            Ok(TextureType::Solid(stmt.required("color")?.parse_vec3()?))
+        }
+        "checker" => {
+            stmt.allow_only(&["even", "odd", "scale"])?;
+            Ok(TextureType::Checker(
+                Box::new(parse_texture(stmt.required("even")?, textures)?),
+                Box::new(parse_texture(stmt.required("odd")?, textures)?),
+                scale(1.0)?,
+            ))
+        }
+        "noise" | "turbulence" | "marble" => {
+            stmt.allow_only(&["scale", "color", "seed"])?;
+            let seed = match stmt.param("seed") {
+                Some(t) => t.parse_usize()? as u64,
+                None => 0,
+            };
+            Ok(TextureType::Noise(
+                kind.text.parse().map_err(|e: String| kind.error(e))?,
+                scale(1.0)?,
+                stmt.vec3_or("color", Vec3::new_singleton(1.0))?,
+                Arc::new(Perlin::new(seed)),
+            ))
+        }
+        "image" => {
+            stmt.allow_only(&["path"])?;
+            let path = stmt.required("path")?;
+            let file = dir.join(path.text);
+            let image = ImageTexture::load(&file).map_err(|e| {
+                path.error(format!("cannot load '{}': {}", file.display(), e))
+            })?;
+            Ok(TextureType::Image(Arc::new(image)))
+        }
+        other => Err(kind.error(format!(
+            "unknown texture type '{}', expected solid, checker, noise, turbulence, marble or image",
+            other
+        ))),
+    }
+}
+
+fn parse_material(stmt: &Statement, textures: &Textures) -> Result<MaterialType, SceneError> {
+    let kind = stmt.positional[1];
+    match kind.text {
+        "lambertian" => {
+            stmt.allow_only(&["albedo"])?;
+            Ok(MaterialType::Lambertian(parse_texture(
+                stmt.required("albedo")?,
+                textures,
+            )?))
+        }
+        "metal" => {
+            stmt.allow_only(&["albedo", "fuzz"])?;
+            let fuzz = stmt.f32_or("fuzz", 0.0)?;
+            if !(0.0..=1.0).contains(&fuzz) {
+                return Err(stmt.required("fuzz")?.error("must be between 0 and 1"));
+            }
+            Ok(MaterialType::Metal(
+                parse_texture(stmt.required("albedo")?, textures)?,
+                fuzz,
+            ))
+        }
+        "dielectric" => {
+            stmt.allow_only(&["ior", "cauchy", "sellmeier", "absorption", "roughness"])?;
+            let ior = parse_ior(stmt)?;
+            let absorption = stmt.vec3_or("absorption", Vec3::new_dfl())?;
+            if absorption.min(Vec3::new_dfl()) != Vec3::new_dfl() {
+                return Err(stmt.required("absorption")?.error("must not be negative"));
+            }
+            match stmt.param("roughness") {
+                Some(_) => Ok(MaterialType::RoughDielectric(
+                    ior,
+                    absorption,
+                    parse_roughness(stmt)?,
+                )),
+                None => Ok(MaterialType::Dielectric(ior, absorption)),
+            }
+        }
+        "conductor" => {
+            stmt.allow_only(&["preset", "eta", "k", "roughness"])?;
+            let (eta, k) = match stmt.param("preset") {
+                Some(preset) => {
+                    if let Some(extra) = stmt.param("eta").or_else(|| stmt.param("k")) {
+                        return Err(extra.error("a preset already gives eta and k"));
+                    }
+                    preset
+                        .text
+                        .parse::<ConductorPreset>()
+                        .map_err(|e| preset.error(e))?
+                        .ior()
+                }
+                None => (
+                    stmt.required("eta")?.parse_vec3()?,
+                    stmt.required("k")?.parse_vec3()?,
+                ),
+            };
+            Ok(MaterialType::Conductor(eta, k, parse_roughness(stmt)?))
+        }
+        "light" => {
+            stmt.allow_only(&["emit"])?;
+            Ok(MaterialType::DiffuseLight(parse_texture(
+                stmt.required("emit")?,
+                textures,
+            )?))
+        }
+        "principled" => {
+            stmt.allow_only(&Principled::INPUTS)?;
+            let mut principled = Principled::default();
+            for (name, input) in Principled::INPUTS
+                .iter()
+                .zip(principled.inputs_mut().iter_mut())
+            {
+                if let Some(token) = stmt.param(name) {
+                    **input = parse_texture(token, textures)?;
+                }
+            }
+            Ok(MaterialType::Principled(Box::new(principled)))
+        }
+        other => Err(kind.error(format!(
+            "unknown material type '{}', expected lambertian, metal, dielectric, conductor, principled or light",
+            other
+        ))),
+    }
+}
+
+fn parse_roughness(stmt: &Statement) -> Result<f32, SceneError> {
+    let roughness = stmt.f32_or("roughness", 0.0)?;
+    match (0.0..=1.0).contains(&roughness) {
+        true => Ok(roughness),
+        false => Err(stmt.required("roughness")?.error("must be between 0 and 1")),
+    }
+}
+
+fn parse_camera(stmt: &Statement) -> Result<CameraSettings, SceneError> {
+    stmt.allow_only(&[
+        "lookfrom",
+        "lookat",
+        "vup",
+        "vfov",
+        "aperture",
+        "focus_dist",
+    ])?;
+    let dfl = CameraSettings::new_dfl();
+    let lookfrom = stmt.vec3_or("lookfrom", dfl.lookfrom)?;
+    let lookat = stmt.vec3_or("lookat", dfl.lookat)?;
+    let vup = stmt.vec3_or("vup", dfl.vup)?;
+    if (lookfrom - lookat).near_zero() {
+        return Err(stmt
+            .keyword
+            .error("lookfrom and lookat must be different points"));
+    }
+    if vup.cross(lookfrom - lookat).near_zero() {
+        return Err(stmt
+            .param("vup")
+            .unwrap_or(stmt.keyword)
+            .error("vup must not be parallel to the view direction"));
+    }
+    let vfov = stmt.f32_or("vfov", dfl.vfov)?;
+    if !(vfov > 0.0 && vfov < 180.0) {
+        return Err(stmt
+            .required("vfov")?
+            .error("must be between 0 and 180 degrees"));
+    }
+    let aperture = stmt.f32_or("aperture", dfl.aperture)?;
+    if aperture < 0.0 {
+        return Err(stmt.required("aperture")?.error("must not be negative"));
+    }
+    let focus_dist = match stmt.param("focus_dist") {
+        Some(t) => positive(t, t.parse_f32()?)?,
+        None => (lookfrom - lookat).length(),
+    };
+    Ok(CameraSettings {
+        lookfrom,
+        lookat,
+        vup,
+        vfov,
+        aperture,
+        focus_dist,
+    })
+}
+
+fn parse_background(stmt: &Statement, kind: Token, dir: &Path) -> Result<Background, SceneError> {
+    match kind.text {
+        "gradient" => {
+            stmt.allow_only(&["horizon", "zenith"])?;
+            // Either end that is left out keeps the default sky's color
+            match Background::default() {
+                Background::Gradient(horizon, zenith) => Ok(Background::Gradient(
+                    stmt.vec3_or("horizon", horizon)?,
+                    stmt.vec3_or("zenith", zenith)?,
+                )),
+                other => Ok(other),
+            }
+        }
+        "constant" => {
+            stmt.allow_only(&["color"])?;
+            Ok(Background::Constant(stmt.required("color")?.parse_vec3()?))
+        }
+        "black" => {
+            stmt.allow_only(&[])?;
+            Ok(Background::Black)
+        }
+        "environment" => {
+            stmt.allow_only(&["path", "rotate", "intensity"])?;
+            let path = stmt.required("path")?;
+            let intensity = match stmt.param("intensity") {
+                Some(t) => positive(t, t.parse_f32()?)?,
+                None => 1.0,
+            };
+            let file = dir.join(path.text);
+            let map = EnvironmentMap::load(&file, stmt.f32_or("rotate", 0.0)?, intensity)
+                .map_err(|e| path.error(format!("cannot load '{}': {}", file.display(), e)))?;
+            Ok(Background::Environment(Arc::new(map)))
+        }
+        other => Err(kind.error(format!(
+            "unknown background '{}', expected gradient, constant, black or environment",
+            other
+        ))),
+    }
+}
+
+/// The contents of a `mesh ... end` block read so far
+struct MeshBlock<'a> {
+    start: Token<'a>,
+    material: MaterialType,
+    positions: Vec<Vec3>,
+    normals: Vec<Vec3>,
+    uvs: Vec<[f32; 2]>,
+    colors: Vec<Vec3>,
+    triangles: Vec<[usize; 3]>,
+}
+
+impl<'a> MeshBlock<'a> {
+    fn vertex(&mut self, stmt: &Statement<'a>) -> Result<(), SceneError> {
+        stmt.expect_positional(&[])?;
+        stmt.allow_only(&["position", "normal", "uv", "color"])?;
+        let position = stmt.required("position")?.parse_vec3()?;
+        // Either every vertex has an attribute or none do, as the first one decides
+        let first = self.positions.is_empty();
+        let check = |key: &str, had: bool| match (stmt.param(key), first || had) {
+            (Some(t), false) => Err(t.error(format!(
+                "earlier vertices have no {}, so this one cannot either",
+                key
+            ))),
+            (None, true) if !first => Err(stmt.keyword.error(format!(
+                "'vertex' needs a '{}' like the ones before it",
+                key
+            ))),
+            (t, _) => Ok(t),
+        };
+        let normal = check("normal", !self.normals.is_empty())?;
+        let uv = check("uv", !self.uvs.is_empty())?;
+        let color = check("color", !self.colors.is_empty())?;
+        self.positions.push(position);
+        if let Some(t) = normal {
+            self.normals.push(t.parse_vec3()?);
+        }
+        if let Some(t) = uv {
+            self.uvs.push(t.parse_uv()?);
+        }
+        if let Some(t) = color {
+            self.colors.push(t.parse_vec3()?);
+        }
+        Ok(())
+    }
+
+    fn face(&mut self, stmt: &Statement<'a>) -> Result<(), SceneError> {
+        stmt.allow_only(&[])?;
+        if stmt.positional.len() < 3 {
+            return Err(stmt.keyword.error("'face' needs at least 3 vertex indices"));
+        }
+        let indices = stmt
+            .positional
+            .iter()
+            .map(|t| match t.parse_usize()? {
+                i if i < self.positions.len() => Ok(i),
+                i => Err(t.error(format!(
+                    "vertex {} has not been defined, there are {} so far",
+                    i,
+                    self.positions.len()
+                ))),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        for k in 1..indices.len() - 1 {
+            self.triangles
+                .push([indices[0], indices[k], indices[k + 1]]);
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> HittableObject {
+        let mesh = Mesh::new(
+            self.positions,
+            self.normals,
+            self.uvs,
+            self.colors,
+            self.triangles,
+            self.material,
+        )
+        .expect("vertex attributes and indices are checked as they are read");
+        HittableObject::Mesh(Arc::new(mesh))
+    }
+}
+
+/// An open `group` or `object` block, with the objects read inside it so far
+struct Block<'a> {
+    start: Token<'a>,
+    /// The name an `object` block is defined under
+    name: Option<Token<'a>>,
+    objects: Vec<HittableObject>,
+}
+
+/// The transform given by `matrix=`, or else by `scale=`, `rotate=` then `translate=`
+fn parse_transform(stmt: &Statement) -> Result<Transform, SceneError> {
+    if let Some(t) = stmt.param("matrix") {
+        if let Some(other) = ["translate", "rotate", "scale"]
+            .iter()
+            .find_map(|key| stmt.param(key))
+        {
+            return Err(other.error("'matrix' cannot be combined with translate, rotate or scale"));
+        }
+        let values = t.parse_list()?;
+        if values.len() != 12 && values.len() != 16 {
+            return Err(t.error("expected the 12 or 16 numbers of the matrix's rows"));
+        }
+        let mut m = Transform::identity().matrix();
+        for (row, values) in m.iter_mut().zip(values.chunks(4)) {
+            row.copy_from_slice(values);
+        }
+        return Transform::from_matrix(m).map_err(|e| t.error(e));
+    }
+    let mut transform = match stmt.param("scale") {
+        Some(t) => Transform::scale(t.parse_vec3()?).map_err(|e| t.error(e))?,
+        None => Transform::identity(),
+    };
+    if let Some(t) = stmt.param("rotate") {
+        let degrees = t.parse_vec3()?;
+        for axis in (0..3).filter(|&axis| degrees[axis] != 0.0) {
+            transform = transform.then(&Transform::rotate(axis, degrees[axis]));
+        }
+    }
+    if let Some(t) = stmt.param("translate") {
+        transform = transform.then(&Transform::translate(t.parse_vec3()?));
+    }
+    Ok(transform)
+}
+
+/// Record that a statement which may only appear once has been seen
+fn once<'a>(seen: &mut Option<Token<'a>>, keyword: Token<'a>) -> Result<(), SceneError> {
+    match seen.replace(keyword) {
+        Some(prev) => Err(keyword.error(format!(
+            "'{}' was already given on line {}",
+            keyword.text, prev.line
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Parse a scene, finding the models it loads relative to `dir`
+pub fn parse_scene(source: &str, dir: &Path) -> Result<Scene, SceneError> {
+    let mut textures = Textures::new();
+    let mut materials = HashMap::<&str, MaterialType>::new();
+    let mut camera = None;
+    let mut background = None;
+    let mut render = None;
+    // Objects at the top level, then open blocks with the innermost last
+    let mut world = Vec::new();
+    let mut groups: Vec<Block> = Vec::new();
+    let mut mesh: Option<MeshBlock> = None;
+    let mut definitions = HashMap::<&str, Arc<HittableObject>>::new();
+    // Models already loaded, by path and replacement material
+    let mut models = HashMap::<(&str, Option<&str>), Arc<HittableObject>>::new();
+    let mut scene = Scene::new(
+        HittableObject::HittableList(Vec::new()),
+        CameraSettings::new_dfl(),
+    );
+
+    for (i, line) in source.lines().enumerate() {
+        let tokens = tokenize(line, i + 1);
+        if tokens.is_empty() {
+            continue;
+        }
+        let stmt = Statement::new(tokens)?;
+        let keyword = stmt.keyword;
+        if let Some(block) = mesh.as_mut() {
+            match keyword.text {
+                "vertex" => block.vertex(&stmt)?,
+                "face" => block.face(&stmt)?,
+                "end" => {
+                    stmt.expect_positional(&[])?;
+                    stmt.allow_only(&[])?;
+                    let object = mesh.take().unwrap().finish();
+                    match groups.last_mut() {
+                        Some(block) => block.objects.push(object),
+                        None => world.push(object),
+                    }
+                }
+                other => {
+                    return Err(keyword.error(format!(
+                        "'{}' is not allowed inside a mesh, expected vertex, face or end",
+                        other
+                    )))
+                }
+            }
+            continue;
+        }
+        let lookup_material = |name: Token| {
+            materials
+                .get(name.text)
+                .cloned()
+                .ok_or_else(|| name.error(format!("unknown material '{}'", name.text)))
+        };
+        let objects = match groups.last_mut() {
+            Some(block) => &mut block.objects,
+            None => &mut world,
+        };
+        match keyword.text {
+            "render" => {
+                once(&mut render, keyword)?;
+                stmt.expect_positional(&[])?;
+                stmt.allow_only(&["width", "height", "spp", "max_depth"])?;
+                let setting = |key: &str, min: usize| -> Result<Option<usize>, SceneError> {
+                    match stmt.param(key) {
+                        Some(t) => Ok(Some(at_least(t, t.parse_usize()?, min)?)),
+                        None => Ok(None),
+                    }
+                };
+                scene.width = setting("width", 2)?;
+                scene.height = setting("height", 2)?;
+                scene.samples_per_pixel = setting("spp", 1)?;
+                scene.max_depth = setting("max_depth", 1)?;
+            }
+            "camera" => {
+                once(&mut camera, keyword)?;
+                stmt.expect_positional(&[])?;
+                scene.camera = parse_camera(&stmt)?;
+            }
+            "background" => {
+                once(&mut background, keyword)?;
+                stmt.expect_positional(&["kind"])?;
+                scene.background = parse_background(&stmt, stmt.positional[0], dir)?;
+            }
+            "sky" => {
+                once(&mut background, keyword)?;
+                stmt.expect_positional(&[])?;
+                scene.background = parse_background(&stmt, Token { text: "gradient", ..keyword }, dir)?;
+            }
+            "material" => {
+                stmt.expect_positional(&["name", "material type"])?;
+                let name = stmt.positional[0];
+                if materials.contains_key(name.text) {
+                    return Err(name.error(format!("material '{}' is already defined", name.text)));
+                }
+                materials.insert(name.text, parse_material(&stmt, &textures)?);
+            }
+            "texture" => {
+                stmt.expect_positional(&["name", "texture type"])?;
+                let name = stmt.positional[0];
+                if textures.contains_key(name.text) {
+                    return Err(name.error(format!("texture '{}' is already defined", name.text)));
+                }
+                let texture = parse_texture_type(&stmt, &textures, dir)?;
+                textures.insert(name.text, texture);
+            }
+            "sphere" => {
+                stmt.expect_positional(&[])?;
+                stmt.allow_only(&["center", "radius", "material"])?;
+                let center = stmt.required("center")?.parse_vec3()?;
+                let radius = stmt.required("radius")?;
+                let radius = match radius.parse_f32()? {
+                    0.0 => return Err(radius.error("radius must not be 0")),
+                    r => r,
+                };
+                let material = lookup_material(stmt.required("material")?)?;
+                objects.push(HittableObject::Sphere(center, radius, material));
+            }
+            "triangle" => {
+                stmt.expect_positional(&[])?;
+                stmt.allow_only(&["a", "b", "c", "material"])?;
+                let corners = [
+                    stmt.required("a")?.parse_vec3()?,
+                    stmt.required("b")?.parse_vec3()?,
+                    stmt.required("c")?.parse_vec3()?,
+                ];
+                if (corners[1] - corners[0])
+                    .cross(corners[2] - corners[0])
+                    .near_zero()
+                {
+                    return Err(keyword.error("triangle corners must not lie on one line"));
+                }
+                let material = lookup_material(stmt.required("material")?)?;
+                objects.push(HittableObject::Triangle(corners, material));
+            }
+            "rect" => {
+                stmt.expect_positional(&["plane"])?;
+                stmt.allow_only(&["min", "max", "at", "material"])?;
+                let plane = stmt.positional[0];
+                let (flipped, name) = match plane.text.strip_prefix('-') {
+                    Some(name) => (true, name),
+                    None => (false, plane.text),
+                };
+                let min = stmt.required("min")?;
+                let max = stmt.required("max")?;
+                let ([a0, b0], [a1, b1]) = (min.parse_uv()?, max.parse_uv()?);
+                if a0 == a1 || b0 == b1 {
+                    return Err(max.error("the rect must not have zero area"));
+                }
+                let rect = Rect::new(
+                    name.parse::<Plane>().map_err(|e| plane.error(e))?,
+                    (a0, b0),
+                    (a1, b1),
+                    stmt.required("at")?.parse_f32()?,
+                );
+                let rect = match flipped {
+                    true => rect.flipped(),
+                    false => rect,
+                };
+                let material = lookup_material(stmt.required("material")?)?;
+                objects.push(HittableObject::Rect(rect, material));
+            }
+            "box" => {
+                stmt.expect_positional(&[])?;
+                stmt.allow_only(&["min", "max", "translate", "rotate", "scale", "matrix", "material"])?;
+                let min = stmt.required("min")?.parse_vec3()?;
+                let max_token = stmt.required("max")?;
+                let max = max_token.parse_vec3()?;
+                if (0..3).any(|axis| min[axis] == max[axis]) {
+                    return Err(max_token.error("the box must not be flat"));
+                }
+                let material = lookup_material(stmt.required("material")?)?;
+                let object = cuboid(min, max, material);
+                let transformed = ["translate", "rotate", "scale", "matrix"]
+                    .iter()
+                    .any(|key| stmt.param(key).is_some());
+                objects.push(match transformed {
+                    true => HittableObject::Instance(Box::new(Instance::new(
+                        Arc::new(object),
+                        parse_transform(&stmt)?,
+                    ))),
+                    false => object,
+                });
+            }
+            "mesh" => {
+                stmt.expect_positional(&[])?;
+                stmt.allow_only(&["material"])?;
+                mesh = Some(MeshBlock {
+                    start: keyword,
+                    material: lookup_material(stmt.required("material")?)?,
+                    positions: Vec::new(),
+                    normals: Vec::new(),
+                    uvs: Vec::new(),
+                    colors: Vec::new(),
+                    triangles: Vec::new(),
+                });
+            }
+            "model" => {
+                stmt.expect_positional(&[])?;
+                stmt.allow_only(&["path", "translate", "rotate", "scale", "matrix", "material"])?;
+                let path = stmt.required("path")?;
+                let material = stmt.param("material");
+                let transform = parse_transform(&stmt)?;
+                let key = (path.text, material.map(|name| name.text));
+                let model = match models.get(&key) {
+                    Some(model) => model.clone(),
+                    None => {
+                        let material = match material {
+                            Some(name) => Some(lookup_material(name)?),
+                            None => None,
+                        };
+                        let file = dir.join(path.text);
+                        let default = MaterialType::Lambertian(Vec3::new_singleton(0.5).into());
+                        let extension = file.extension().and_then(|e| e.to_str());
+                        let (meshes, warnings) = match extension
+                            .map(str::to_ascii_lowercase)
+                            .as_deref()
+                        {
+                            Some("obj") => load_obj(&file, &default),
+                            Some("ply") => load_ply(&file, &default).map(|m| (vec![m], Vec::new())),
+                            Some("stl") => load_stl(&file, &default).map(|m| (vec![m], Vec::new())),
+                            _ => {
+                                return Err(
+                                    path.error("expected a model ending in .obj, .ply or .stl")
+                                )
+                            }
+                        }
+                        .map_err(|e| {
+                            path.error(format!("cannot load '{}': {}", file.display(), e))
+                        })?;
+                        scene.warnings.extend(warnings);
+                        let meshes = meshes
+                            .into_iter()
+                            .map(|mesh| match &material {
+                                Some(material) => mesh.with_material(material.clone()),
+                                None => mesh,
+                            })
+                            .map(|mesh| HittableObject::Mesh(Arc::new(mesh)))
+                            .collect();
+                        let model = Arc::new(HittableObject::HittableList(meshes));
+                        models.insert(key, model.clone());
+                        model
+                    }
+                };
+                objects.push(HittableObject::Instance(Box::new(Instance::new(
+                    model, transform,
+                ))));
+            }
+            "instance" => {
+                stmt.expect_positional(&["object name"])?;
+                stmt.allow_only(&["translate", "rotate", "scale", "matrix"])?;
+                let name = stmt.positional[0];
+                let object = definitions
+                    .get(name.text)
+                    .ok_or_else(|| name.error(format!("unknown object '{}'", name.text)))?
+                    .clone();
+                let transform = parse_transform(&stmt)?;
+                objects.push(HittableObject::Instance(Box::new(Instance::new(
+                    object, transform,
+                ))));
+            }
+            "object" => {
+                stmt.expect_positional(&["name"])?;
+                stmt.allow_only(&[])?;
+                let name = stmt.positional[0];
+                if !groups.is_empty() {
+                    return Err(keyword.error("'object' blocks cannot be inside other blocks"));
+                }
+                if definitions.contains_key(name.text) {
+                    return Err(name.error(format!("object '{}' is already defined", name.text)));
+                }
+                groups.push(Block {
+                    start: keyword,
+                    name: Some(name),
+                    objects: Vec::new(),
+                });
+            }
+            "vertex" | "face" => {
+                return Err(keyword.error(format!(
+                    "'{}' must be inside a 'mesh' block",
+                    keyword.text
+                )))
+            }
+            "group" => {
+                stmt.expect_positional(&[])?;
+                stmt.allow_only(&[])?;
+                groups.push(Block {
+                    start: keyword,
+                    name: None,
+                    objects: Vec::new(),
+                });
+            }
+            "end" => {
+                stmt.expect_positional(&[])?;
+                stmt.allow_only(&[])?;
+                let block = groups
+                    .pop()
+                    .ok_or_else(|| keyword.error("'end' without a matching 'group' or 'object'"))?;
+                let list = HittableObject::HittableList(block.objects);
+                match (block.name, groups.last_mut()) {
+                    (Some(name), _) => {
+                        definitions.insert(name.text, Arc::new(list));
+                    }
+                    (None, Some(outer)) => outer.objects.push(list),
+                    (None, None) => world.push(list),
+                }
+            }
+            other => {
+                return Err(keyword.error(format!(
+                    "unknown statement '{}', expected one of: render, camera, background, texture, material, sphere, triangle, rect, box, mesh, model, object, instance, group, end",
+                    other
+                )))
+            }
+        }
+    }
+
+    if let Some(block) = mesh {
+        return Err(block.start.error("'mesh' is never closed with 'end'"));
+    }
+    match groups.pop() {
+        Some(block) => Err(block
+            .start
+            .error(format!("'{}' is never closed with 'end'", block.start.text))),
+        None => {
+            scene.world = HittableObject::HittableList(world);
+            Ok(scene)
+        }
+    }
+}
+
+/// Writes an index of refraction as the parameter `parse_ior` reads it from
+struct IorFmt(Ior);
+
+impl fmt::Display for IorFmt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Ior::Constant(ior) => write!(f, "ior={}", ior),
+            Ior::Cauchy(a, b) => write!(f, "cauchy={},{}", a, b),
+            Ior::Sellmeier(b, c) => write!(
+                f,
+                "sellmeier={},{},{},{},{},{}",
+                b[0], b[1], b[2], c[0], c[1], c[2]
+            ),
+        }
+    }
+}
+
+/// A path in quotes, so that `tokenize` keeps it whole
+fn quoted(path: &Path) -> io::Result<String> {
+    let text = path.display().to_string();
+    match text.contains('"') {
+        true => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("cannot write the path '{}', which has a '\"' in it", text),
+        )),
+        false => Ok(format!("\"{}\"", text)),
+    }
+}
+
+/// Writes a vector so that `parse_vec3` reads back exactly the same value
+struct VecFmt(Vec3);
+
+impl fmt::Display for VecFmt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // `{}` on floats prints the shortest string that parses back to the same bits
+        match self.0 {
+            v if v.x() == v.y() && v.y() == v.z() => write!(f, "{}", v.x()),
+            v => write!(f, "{},{},{}", v.x(), v.y(), v.z()),
+        }
+    }
+}
+
+fn material_kind(material: &MaterialType) -> &'static str {
+    match material {
+        MaterialType::Lambertian(..) => "lambertian",
+        MaterialType::Metal(..) => "metal",
+        MaterialType::Dielectric(..) | MaterialType::RoughDielectric(..) => "dielectric",
+        MaterialType::DiffuseLight(..) => "light",
+        MaterialType::Conductor(..) => "conductor",
+        MaterialType::Principled(..) => "principled",
+    }
+}
+
+/// Gather each distinct material in the world, in the order they are first used
+fn collect_materials<'a>(object: &'a HittableObject, materials: &mut Vec<&'a MaterialType>) {
+    match object {
+        HittableObject::Sphere(_, _, material)
+        | HittableObject::Triangle(_, material)
+        | HittableObject::Rect(_, material) => {
+            if !materials.contains(&material) {
+                materials.push(material);
+            }
+        }
+        HittableObject::Mesh(mesh) | HittableObject::MeshTriangle(mesh, _) => {
+            if !materials.contains(&mesh.material()) {
+                materials.push(mesh.material());
+            }
+        }
+        HittableObject::HittableList(objects) => objects
+            .iter()
+            .for_each(|object| collect_materials(object, materials)),
+        HittableObject::Bvh(bvh) => bvh
+            .objects()
+            .for_each(|object| collect_materials(object, materials)),
+        HittableObject::Instance(instance) => collect_materials(instance.object(), materials),
+    }
+}
+
+/// Gather each distinct object that is instanced, after any it instances itself
+fn collect_objects<'a>(object: &'a HittableObject, objects: &mut Vec<&'a Arc<HittableObject>>) {
+    match object {
+        HittableObject::HittableList(list) => list
+            .iter()
+            .for_each(|object| collect_objects(object, objects)),
+        HittableObject::Bvh(bvh) => bvh
+            .objects()
+            .for_each(|object| collect_objects(object, objects)),
+        HittableObject::Instance(instance) => {
+            let shared = instance.object();
+            if !objects.iter().any(|o| Arc::ptr_eq(o, shared)) {
+                collect_objects(shared, objects);
+                objects.push(shared);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn object_name(objects: &[&Arc<HittableObject>], object: &Arc<HittableObject>) -> String {
+    let index = objects.iter().position(|o| Arc::ptr_eq(o, object)).unwrap();
+    format!("object{}", index)
+}
+
+/// Gather each distinct texture that is not a plain color, after any it is built from
+fn collect_textures<'a>(texture: &'a TextureType, textures: &mut Vec<&'a TextureType>) {
+    if let TextureType::Checker(even, odd, _) = texture {
+        collect_textures(even, textures);
+        collect_textures(odd, textures);
+    }
+    if !matches!(texture, TextureType::Solid(_)) && !textures.contains(&texture) {
+        textures.push(texture);
+    }
+}
+
+/// How a material refers to a texture: a plain color is written in place
+fn texture_ref(textures: &[&TextureType], texture: &TextureType) -> String {
+    let kind = match texture {
+        TextureType::Solid(color) => return VecFmt(*color).to_string(),
+        TextureType::Checker(..) => "checker",
+        TextureType::Noise(kind, ..) => kind.name(),
+        TextureType::Image(_) => "image",
+    };
+    let index = textures.iter().position(|t| *t == texture).unwrap();
+    format!("{}{}", kind, index)
+}
+
+fn material_name(materials: &[&MaterialType], material: &MaterialType) -> String {
+    let index = materials.iter().position(|m| *m == material).unwrap();
+    format!("{}{}", material_kind(material), index)
+}
+
+fn write_mesh<W: Write>(
+    out: &mut W,
+    mesh: &Mesh,
+    materials: &[&MaterialType],
+    indent: &str,
+) -> io::Result<()> {
+    writeln!(
+        out,
+        "{}mesh material={}",
+        indent,
+        material_name(materials, mesh.material())
+    )?;
+    for (i, position) in mesh.positions().iter().enumerate() {
+        write!(out, "{}    vertex position={}", indent, VecFmt(*position))?;
+        if let Some(normal) = mesh.normals().get(i) {
+            write!(out, " normal={}", VecFmt(*normal))?;
+        }
+        if let Some([u, v]) = mesh.uvs().get(i) {
+            write!(out, " uv={},{}", u, v)?;
+        }
+        if let Some(color) = mesh.colors().get(i) {
+            write!(out, " color={}", VecFmt(*color))?;
+        }
+        writeln!(out)?;
+    }
+    for [a, b, c] in mesh.triangles() {
+        writeln!(out, "{}    face {} {} {}", indent, a, b, c)?;
+    }
+    writeln!(out, "{}end", indent)
+}
+
+fn write_object<W: Write>(
+    out: &mut W,
+    object: &HittableObject,
+    materials: &[&MaterialType],
+    objects: &[&Arc<HittableObject>],
+    depth: usize,
+) -> io::Result<()> {
+    let indent = "    ".repeat(depth);
+    match object {
+        HittableObject::Sphere(center, radius, material) => writeln!(
+            out,
+            "{}sphere center={} radius={} material={}",
+            indent,
+            VecFmt(*center),
+            radius,
+            material_name(materials, material)
+        ),
+        HittableObject::Triangle([a, b, c], material) => writeln!(
+            out,
+            "{}triangle a={} b={} c={} material={}",
+            indent,
+            VecFmt(*a),
+            VecFmt(*b),
+            VecFmt(*c),
+            material_name(materials, material)
+        ),
+        HittableObject::Rect(rect, material) => {
+            let ((a0, b0), (a1, b1)) = (rect.min(), rect.max());
+            writeln!(
+                out,
+                "{}rect {}{} min={},{} max={},{} at={} material={}",
+                indent,
+                match rect.is_flipped() {
+                    true => "-",
+                    false => "",
+                },
+                rect.plane().name(),
+                a0,
+                b0,
+                a1,
+                b1,
+                rect.offset(),
+                material_name(materials, material)
+            )
+        }
+        HittableObject::Mesh(mesh) => write_mesh(out, mesh, materials, &indent),
+        // A triangle split out of a mesh by the hierarchy is saved as a mesh of its own
+        HittableObject::MeshTriangle(mesh, i) => {
+            let index = mesh.triangles()[*i];
+            let pick = |attribute: &[Vec3]| match attribute.is_empty() {
+                true => Vec::new(),
+                false => index.iter().map(|&k| attribute[k]).collect(),
+            };
+            let single = Mesh::new(
+                pick(mesh.positions()),
+                pick(mesh.normals()),
+                index
+                    .iter()
+                    .filter_map(|&k| mesh.uvs().get(k).copied())
+                    .collect(),
+                pick(mesh.colors()),
+                vec![[0, 1, 2]],
+                mesh.material().clone(),
+            )
+            .unwrap();
+            write_mesh(out, &single, materials, &indent)
+        }
+        HittableObject::Instance(instance) => {
+            let m = instance.transform().matrix();
+            let rows = m[..3].iter().flatten().map(|x| x.to_string());
+            writeln!(
+                out,
+                "{}instance {} matrix={}",
+                indent,
+                object_name(objects, instance.object()),
+                rows.collect::<Vec<_>>().join(",")
+            )
+        }
+        HittableObject::HittableList(list) => {
+            writeln!(out, "{}group", indent)?;
+            for object in list {
+                write_object(out, object, materials, objects, depth + 1)?;
+            }
+            writeln!(out, "{}end", indent)
+        }
+        // The hierarchy is rebuilt when rendering, so only its primitives are saved
+        HittableObject::Bvh(bvh) => {
+            writeln!(out, "{}group", indent)?;
+            for object in bvh.objects() {
+                write_object(out, object, materials, objects, depth + 1)?;
+            }
+            writeln!(out, "{}end", indent)
+        }
+    }
+}
+
+/// The objects inside a block. The loader wraps every block in a list, so the
+/// outermost one is implied.
+fn contents(object: &HittableObject) -> Vec<&HittableObject> {
+    match object {
+        HittableObject::HittableList(list) => list.iter().collect(),
+        object => vec![object],
+    }
+}
+
+/// Write a scene in the format read by `parse_scene`, such that reading it back
+/// gives an identical scene
+pub fn write_scene<W: Write>(out: &mut W, scene: &Scene) -> io::Result<()> {
+    let settings = [
+        ("width", scene.width),
+        ("height", scene.height),
+        ("spp", scene.samples_per_pixel),
+        ("max_depth", scene.max_depth),
+    ];
+    if settings.iter().any(|(_, value)| value.is_some()) {
+        write!(out, "render")?;
+        for (key, value) in settings.iter() {
+            if let Some(value) = value {
+                write!(out, " {}={}", key, value)?;
+            }
+        }
+        writeln!(out)?;
+    }
+    let cam = &scene.camera;
+    writeln!(
+        out,
+        "camera lookfrom={} lookat={} vup={} vfov={} aperture={} focus_dist={}",
+        VecFmt(cam.lookfrom),
+        VecFmt(cam.lookat),
+        VecFmt(cam.vup),
+        cam.vfov,
+        cam.aperture,
+        cam.focus_dist
+    )?;
+    match &scene.background {
+        Background::Gradient(horizon, zenith) => writeln!(
+            out,
+            "background gradient horizon={} zenith={}",
+            VecFmt(*horizon),
+            VecFmt(*zenith)
+        ),
+        Background::Constant(color) => {
+            writeln!(out, "background constant color={}", VecFmt(*color))
+        }
+        Background::Black => writeln!(out, "background black"),
+        Background::Environment(map) => writeln!(
+            out,
+            "background environment path={} rotate={} intensity={}",
+            quoted(map.path())?,
+            map.rotation(),
+            map.intensity()
+        ),
+    }?;
+
+    let mut materials = Vec::new();
+    collect_materials(&scene.world, &mut materials);
+    let mut textures = Vec::new();
+    for material in materials.iter() {
+        match material {
+            MaterialType::Lambertian(texture)
+            | MaterialType::Metal(texture, _)
+            | MaterialType::DiffuseLight(texture) => collect_textures(texture, &mut textures),
+            MaterialType::Principled(principled) => principled
+                .inputs()
+                .iter()
+                .for_each(|texture| collect_textures(texture, &mut textures)),
+            MaterialType::Dielectric(..)
+            | MaterialType::Conductor(..)
+            | MaterialType::RoughDielectric(..) => {}
+        }
+    }
+    writeln!(out)?;
+    for texture in textures.iter() {
+        let name = texture_ref(&textures, texture);
+        match texture {
+            TextureType::Solid(_) => unreachable!("plain colors are written in place"),
+            TextureType::Checker(even, odd, scale) => writeln!(
+                out,
+                "texture {} checker even={} odd={} scale={}",
+                name,
+                texture_ref(&textures, even),
+                texture_ref(&textures, odd),
+                scale
+            ),
+            TextureType::Noise(kind, scale, color, perlin) => writeln!(
+                out,
+                "texture {} {} scale={} color={} seed={}",
+                name,
+                kind.name(),
+                scale,
+                VecFmt(*color),
+                perlin.seed()
+            ),
+            TextureType::Image(image) => {
+                writeln!(out, "texture {} image path={}", name, quoted(image.path())?)
+            }
+        }?;
+    }
+    for material in materials.iter() {
+        let name = material_name(&materials, material);
+        match material {
+            MaterialType::Lambertian(albedo) => {
+                writeln!(
+                    out,
+                    "material {} lambertian albedo={}",
+                    name,
+                    texture_ref(&textures, albedo)
+                )
+            }
+            MaterialType::Metal(albedo, fuzz) => writeln!(
+                out,
+                "material {} metal albedo={} fuzz={}",
+                name,
+                texture_ref(&textures, albedo),
+                fuzz
+            ),
+            MaterialType::Dielectric(ior, absorption) => writeln!(
+                out,
+                "material {} dielectric {} absorption={}",
+                name,
+                IorFmt(*ior),
+                VecFmt(*absorption)
+            ),
+            MaterialType::RoughDielectric(ior, absorption, roughness) => writeln!(
+                out,
+                "material {} dielectric {} absorption={} roughness={}",
+                name,
+                IorFmt(*ior),
+                VecFmt(*absorption),
+                roughness
+            ),
+            MaterialType::Principled(principled) => {
+                write!(out, "material {} principled", name)?;
+                for (input, texture) in Principled::INPUTS.iter().zip(principled.inputs().iter()) {
+                    write!(out, " {}={}", input, texture_ref(&textures, texture))?;
+                }
+                writeln!(out)
+            }
+            MaterialType::Conductor(eta, k, roughness) => writeln!(
+                out,
+                "material {} conductor eta={} k={} roughness={}",
+                name,
+                VecFmt(*eta),
+                VecFmt(*k),
+                roughness
+            ),
+            MaterialType::DiffuseLight(emit) => {
+                writeln!(
+                    out,
+                    "material {} light emit={}",
+                    name,
+                    texture_ref(&textures, emit)
+                )
+            }
+        }?;
+    }
+
+    let mut objects = Vec::new();
+    collect_objects(&scene.world, &mut objects);
+    for object in objects.iter() {
+        writeln!(out, "\nobject {}", object_name(&objects, object))?;
+        for item in contents(object) {
+            write_object(out, item, &materials, &objects, 1)?;
+        }
+        writeln!(out, "end")?;
+    }
+
+    writeln!(out)?;
+    contents(&scene.world)
+        .into_iter()
+        .try_for_each(|object| write_object(out, object, &materials, &objects, 0))
+}
+
+pub fn save_scene<P: AsRef<Path>>(path: P, scene: &Scene) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    write_scene(&mut out, scene)?;
+    out.flush()
+}
+
+#[test]
+fn test_parse_scene() {
+    let scene = parse_scene(include_str!("../scenes/img11.scene"), Path::new("scenes")).unwrap();
+    assert_eq!(scene.camera, CameraSettings::new_dfl());
+    assert_eq!(scene.width, Some(400));
+    assert_eq!(scene.world, crate::scene::img_11());
+
+    assert_eq!(
+        parse_error("material m lambertian albedo=1\nsphere center=0 radius=x material=m").0,
+        2
+    );
+    assert_eq!(parse_error("sphere center=0,1 radius=1 material=m").1, 15);
+    assert_eq!(parse_error("  sphere center=0 radius=1 material=m").1, 37);
+    assert_eq!(parse_error("group\nsphere").0, 2);
+    assert!(parse_error("group").2.contains("never closed"));
+}
+
+#[test]
+fn test_parse_quoted_values() {
+    // Quotes keep spaces and '#' in a value, and point errors inside them
+    assert!(
+        parse_error("texture t image path=\"no such # file.png\" # comment")
+            .2
+            .contains("'no such # file.png'")
+    );
+    assert_eq!(parse_error("texture t image path=\"missing.png").1, 22);
+    assert_eq!(parse_error("texture t image path=\"a\"b").1, 22);
+}
+
+#[test]
+fn test_parse_mesh() {
+    let quad = "material m lambertian albedo=1\nmesh material=m\n";
+    let vertices = "vertex position=0\nvertex position=1,0,0\nvertex position=1\n";
+    let scene = parse_scene(
+        &format!(
+            "{}{}vertex position=0,1,0\nface 0 1 2 3\nend",
+            quad, vertices
+        ),
+        Path::new(""),
+    )
+    .unwrap();
+    match scene.world {
+        HittableObject::HittableList(objects) => match &objects[0] {
+            HittableObject::Mesh(mesh) => assert_eq!(mesh.triangles(), &[[0, 1, 2], [0, 2, 3]]),
+            _ => panic!("expected a mesh"),
+        },
+        _ => panic!("expected a list"),
+    }
+    assert_eq!(
+        parse_error(&format!("{}{}face 0 1 3\nend", quad, vertices)).1,
+        10
+    );
+    assert!(parse_error(&format!(
+        "{}vertex position=0 uv=0,0\nvertex position=1",
+        quad
+    ))
+    .2
+    .contains("uv"));
+    assert!(parse_error(&format!("{}{}", quad, vertices))
+        .2
+        .contains("never closed"));
+    assert!(parse_error("face 0 1 2").2.contains("inside a 'mesh'"));
+}
+
+#[test]
+fn test_parse_instances() {
+    assert!(parse_error("instance missing").2.contains("unknown object"));
+    assert_eq!(
+        parse_error("object a\nend\ninstance a translate=1 matrix=1,0,0,0,0,1,0,0,0,0,1,0").1,
+        22
+    );
+    assert!(parse_error("object a\nend\ninstance a scale=1,0,1")
+        .2
+        .contains("0"));
+    assert!(parse_error("group\nobject a")
+        .2
+        .contains("inside other blocks"));
+    assert!(parse_error("object a")
+        .2
+        .contains("'object' is never closed"));
+}
+
+#[test]
+fn test_parse_rects() {
+    let cornell = parse_scene(include_str!("../scenes/cornell.scene"), Path::new("")).unwrap();
+    assert_eq!(cornell.camera, CameraSettings::new_cornell());
+    assert_eq!(cornell.world, crate::scene::cornell_box());
+
+    let rect = "material m lambertian albedo=1\nrect";
+    assert_eq!(
+        parse_error(&format!("{} zx min=0,0 max=1,1 at=0 material=m", rect)).1,
+        6
+    );
+    assert!(
+        parse_error(&format!("{} xy min=0,0 max=0,1 at=0 material=m", rect))
+            .2
+            .contains("zero area")
+    );
+    assert!(
+        parse_error("material m lambertian albedo=1\nbox min=0 max=1,0,1 material=m")
+            .2
+            .contains("flat")
+    );
+}
+
+#[test]
+fn test_parse_backgrounds() {
+    let night = parse_scene(include_str!("../scenes/night.scene"), Path::new("")).unwrap();
+    assert!(matches!(night.background, Background::Constant(_)));
+
+    let sky = parse_scene("sky zenith=0", Path::new("")).unwrap();
+    assert_eq!(
+        sky.background,
+        Background::Gradient(Vec3::new_singleton(1.0), Vec3::new_singleton(0.0))
+    );
+    assert!(parse_error("background black\nsky")
+        .2
+        .contains("already given"));
+
+    assert_eq!(parse_error("background dusk").1, 12);
+}
+
+#[test]
+fn test_parse_textures() {
+    assert!(parse_error("material m lambertian albedo=wood")
+        .2
+        .contains("unknown texture"));
+    assert_eq!(parse_error("texture t noise scale=-1").1, 23);
+    assert!(parse_error("texture t image path=missing.png")
+        .2
+        .contains("cannot load"));
+}
+
+#[test]
+fn test_parse_conductors() {
+    assert!(parse_scene(include_str!("../scenes/metals.scene"), Path::new("")).is_ok());
+
+    let copper = parse_scene(
+        "material m conductor preset=copper\nsphere center=0 radius=1 material=m",
+        Path::new(""),
+    )
+    .unwrap();
+    let (eta, k) = ConductorPreset::Copper.ior();
+    assert_eq!(
+        copper.world,
+        HittableObject::HittableList(vec![HittableObject::Sphere(
+            Vec3::new_dfl(),
+            1.0,
+            MaterialType::Conductor(eta, k, 0.0)
+        )])
+    );
+    assert!(parse_error("material m conductor preset=silver")
+        .2
+        .contains("expected gold"));
+    assert_eq!(parse_error("material m conductor preset=gold k=1").1, 36);
+    assert_eq!(
+        parse_error("material m dielectric ior=1.5 roughness=2").1,
+        41
+    );
+}
+
+#[test]
+fn test_parse_principled() {
+    let paint = parse_scene(
+        "material m principled base_color=0.5,0,0 clearcoat=1\nsphere center=0 radius=1 material=m",
+        Path::new(""),
+    )
+    .unwrap();
+    let expected = Principled {
+        base_color: Vec3::new(0.5, 0.0, 0.0).into(),
+        clearcoat: Vec3::new_singleton(1.0).into(),
+        ..Principled::default()
+    };
+    assert_eq!(
+        paint.world,
+        HittableObject::HittableList(vec![HittableObject::Sphere(
+            Vec3::new_dfl(),
+            1.0,
+            MaterialType::Principled(Box::new(expected))
+        )])
+    );
+    assert!(parse_error("material m principled gloss=1")
+        .2
+        .contains("gloss"));
+}
+
+#[test]
+fn test_parse_glass() {
+    assert!(parse_error("material m dielectric ior=bk8")
+        .2
+        .contains("sf11"));
+    assert_eq!(
+        parse_error("material m dielectric ior=1.5 cauchy=1.5,0").1,
+        38
+    );
+    assert_eq!(parse_error("material m dielectric cauchy=1.5").1, 30);
+    assert_eq!(
+        parse_error("material m dielectric sellmeier=1,0,0,0.2,0,0").1,
+        33
+    );
+    // A pole near 548nm, between the ends of visible light where the index is fine
+    assert_eq!(
+        parse_error("material m dielectric sellmeier=0.01,0,0,0.3,0,0").1,
+        33
+    );
+    assert_eq!(
+        parse_error("material m dielectric ior=1.5 absorption=-1").1,
+        42
+    );
+}
+
+#[test]
+fn test_write_scene_round_trip() {
+    use rand::{prelude::StdRng, SeedableRng};
+
+    let dir = std::env::temp_dir().join(format!("scene_file_test_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("my tile.ppm"), "P3\n2 1\n255\n255 0 0 0 0 255\n").unwrap();
+    let textured = parse_scene(
+        "texture tile image path=\"my tile.ppm\"\n\
+         texture stone marble scale=4 color=0.9,0.8,0.7 seed=3\n\
+         texture floor checker even=stone odd=0.1 scale=0.5\n\
+         material a lambertian albedo=floor\n\
+         material b metal albedo=tile fuzz=0.2\n\
+         material c light emit=stone\n\
+         material d conductor preset=gold roughness=0.3\n\
+         material e dielectric ior=1.4 roughness=0.5\n\
+         material g dielectric ior=sf11 absorption=0.5,0.25,0\n\
+         material h dielectric cauchy=1.5,0.004 roughness=0.2\n\
+         material f principled base_color=tile metallic=stone clearcoat=0.5 ior=1.3\n\
+         sphere center=0 radius=1 material=a\n\
+         sphere center=2,0,0 radius=1 material=b\n\
+         sphere center=4,0,0 radius=1 material=c\n\
+         sphere center=6,0,0 radius=1 material=d\n\
+         sphere center=8,0,0 radius=1 material=e\n\
+         sphere center=10,0,0 radius=1 material=f\n\
+         sphere center=12,0,0 radius=1 material=g\n\
+         sphere center=14,0,0 radius=1 material=h\n",
+        &dir,
+    )
+    .unwrap();
+    let mut rng: StdRng = SeedableRng::seed_from_u64(7);
+    let mut scene = Scene::new(
+        crate::scene::random_scene(&mut rng),
+        CameraSettings::new_random(),
+    );
+    scene.world = HittableObject::HittableList(vec![
+        scene.world,
+        HittableObject::HittableList(vec![crate::scene::debug_scene()]),
+        HittableObject::Triangle(
+            [
+                Vec3::new(0.0, 0.0, 0.0),
+                Vec3::new(1.5, 0.25, 0.0),
+                Vec3::new(0.0, 1.0, -0.1),
+            ],
+            MaterialType::Metal(Vec3::new(0.9, 0.8, 0.7).into(), 0.1),
+        ),
+        HittableObject::Mesh(Arc::new(
+            Mesh::new(
+                vec![
+                    Vec3::new(0.0, 0.0, 1.0),
+                    Vec3::new(1.0, 0.0, 1.0),
+                    Vec3::new(0.0, 1.0, 1.0),
+                ],
+                vec![
+                    Vec3::new(0.0, 0.0, 1.0),
+                    Vec3::new(0.1, 0.0, 1.0),
+                    Vec3::new(0.0, 0.1, 1.0),
+                ],
+                vec![[0.0, 0.0], [1.0, 0.0], [0.0, 0.5]],
+                vec![
+                    Vec3::new(1.0, 0.5, 0.25),
+                    Vec3::new_singleton(1.0),
+                    Vec3::new_dfl(),
+                ],
+                vec![[0, 1, 2]],
+                MaterialType::Dielectric(Ior::Constant(1.5), Vec3::new_dfl()),
+            )
+            .unwrap(),
+        )),
+    ]);
+    let shared = Arc::new(HittableObject::HittableList(vec![
+        crate::scene::debug_scene(),
+    ]));
+    let instance = |transform: Transform| {
+        HittableObject::Instance(Box::new(Instance::new(shared.clone(), transform)))
+    };
+    let nested = Arc::new(HittableObject::HittableList(vec![instance(
+        Transform::translate(Vec3::new(0.0, 3.0, 0.0)),
+    )]));
+    scene.world = HittableObject::HittableList(vec![
+        scene.world,
+        instance(
+            Transform::rotate(1, 30.0).then(&Transform::scale(Vec3::new(1.0, 2.0, -1.0)).unwrap()),
+        ),
+        instance(Transform::identity()),
+        HittableObject::Instance(Box::new(Instance::new(nested, Transform::rotate(0, 10.0)))),
+        crate::scene::cornell_box(),
+        textured.world,
+    ]);
+    scene.background = Background::Gradient(Vec3::new(0.1, 0.2, 0.3), Vec3::new_singleton(0.0));
+    scene.samples_per_pixel = Some(10);
+
+    let mut text = Vec::new();
+    write_scene(&mut text, &scene).unwrap();
+    let loaded = parse_scene(std::str::from_utf8(&text).unwrap(), Path::new("")).unwrap();
+    assert_eq!(loaded.world, scene.world);
+    assert_eq!(loaded.camera, scene.camera);
+    assert_eq!(loaded.background, scene.background);
+    let mut sky = fs::File::create(dir.join("night #1 sky.pfm")).unwrap();
+    let panorama = crate::framebuffer::Framebuffer::from_pixels(
+        2,
+        1,
+        vec![Vec3::new(4.0, 3.0, 2.0), Vec3::new_singleton(0.5)],
+    );
+    crate::pfm::write_pfm(&mut sky, &panorama).unwrap();
+    let environment = EnvironmentMap::load(dir.join("night #1 sky.pfm"), 30.0, 2.5).unwrap();
+    for background in [
+        Background::Constant(Vec3::new(0.1, 0.2, 0.3)),
+        Background::Black,
+        Background::Environment(Arc::new(environment)),
+    ]
+    .iter()
+    {
+        scene.background = background.clone();
+        let mut text = Vec::new();
+        write_scene(&mut text, &scene).unwrap();
+        let loaded = parse_scene(std::str::from_utf8(&text).unwrap(), Path::new("")).unwrap();
+        assert_eq!(loaded.background, scene.background);
+    }
+    assert_eq!(loaded.samples_per_pixel, Some(10));
+    assert_eq!(loaded.width, None);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+/// The line, column and message of the error in parsing `source`
+#[cfg(test)]
+fn parse_error(source: &str) -> (usize, usize, String) {
+    match parse_scene(source, Path::new("")) {
+        Err(SceneError::Parse {
+            line,
+            column,
+            message,
+        }) => (line, column, message),
+        _ => panic!("expected a parse error"),
+    }
+}
@@ -0,0 +1,235 @@
+//! Single wavelengths of light, for paths through materials that treat each one
+//! differently, and how much of red, green and blue each stands for.
+//!
+//! The spectral integrator follows a few wavelengths per path. Colors given in RGB are
+//! turned into smooth spectra by Smits' method (1999), a mix of the white, cyan,
+//! magenta, yellow, red, green and blue spectra below, and the wavelengths' values go
+//! back to RGB through the CIE colour matching functions.
+//!
+//! Smits' tables stop at 720 nanometres, but paths sample up to 780, so each spectrum
+//! is extended by holding its last value. The tables are already flat there, and the
+//! eye barely sees past 700 nanometres, so the colours come back much the same.
+
+use crate::vec3::{Color, Vec3};
+
+/// The visible wavelengths in nanometres, which paths pick from uniformly
+pub const LAMBDA_MIN: f32 = 380.0;
+pub const LAMBDA_MAX: f32 = 780.0;
+
+/// The linear RGB of an equal energy spectrum over the visible wavelengths, which is
+/// taken to be white
+const EQUAL_ENERGY: Color = Color::new(128.361, 101.538, 97.065);
+
+/// The spectra Smits builds colors from, in 10 bins from 380 to 720 nanometres
+const WHITE: [f32; 10] = [1.0, 1.0, 0.9999, 0.9993, 0.9992, 0.9998, 1.0, 1.0, 1.0, 1.0];
+const CYAN: [f32; 10] = [
+    0.971, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0, 0.0, 0.0,
+];
+const MAGENTA: [f32; 10] = [
+    1.0, 1.0, 0.9685, 0.2229, 0.0, 0.0458, 0.8369, 1.0, 1.0, 0.9959,
+];
+const YELLOW: [f32; 10] = [
+    0.0001, 0.0, 0.1088, 0.6651, 1.0, 1.0, 0.9996, 0.9586, 0.9685, 0.984,
+];
+const RED: [f32; 10] = [
+    0.1012, 0.0515, 0.0, 0.0, 0.0, 0.0, 0.8325, 1.0149, 1.0149, 1.0149,
+];
+const GREEN: [f32; 10] = [
+    0.0, 0.0, 0.0273, 0.7937, 1.0, 0.9418, 0.1719, 0.0, 0.0, 0.0025,
+];
+const BLUE: [f32; 10] = [
+    1.0, 1.0, 0.8916, 0.3323, 0.0, 0.0, 0.0003, 0.0369, 0.0483, 0.0496,
+];
+
+/// How many wavelengths a spectral path carries, one in each channel of a `Vec3`
+const WAVELENGTHS: usize = 3;
+
+/// The wavelengths a spectral path carries, the first, the hero, drawn uniformly and
+/// the others spaced evenly after it around the visible range (Wilkie et al. 2014)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Wavelengths {
+    lambdas: [f32; WAVELENGTHS],
+}
+
+impl Wavelengths {
+    pub fn sample(u: f32) -> Self {
+        let hero = sample_wavelength(u);
+        let step = (LAMBDA_MAX - LAMBDA_MIN) / WAVELENGTHS as f32;
+        let mut lambdas = [hero; WAVELENGTHS];
+        for (i, lambda) in lambdas.iter_mut().enumerate() {
+            *lambda = hero + i as f32 * step;
+            if *lambda >= LAMBDA_MAX {
+                *lambda -= LAMBDA_MAX - LAMBDA_MIN;
+            }
+        }
+        Self { lambdas }
+    }
+
+    /// Get the wavelengths' hero, which dispersion leaves as the only one followed.
+    pub fn hero(&self) -> f32 {
+        self.lambdas[0]
+    }
+
+    /// A path's `values` once only the hero can be followed, which then stands in for
+    /// all the wavelengths
+    pub fn hero_only(self, values: Vec3) -> Vec3 {
+        Vec3::new(WAVELENGTHS as f32 * values.x(), 0.0, 0.0)
+    }
+
+    /// The smooth spectrum of `rgb` at each wavelength
+    pub fn upsample(self, rgb: Color) -> Vec3 {
+        let [a, b, c] = self.lambdas;
+        Vec3::new(upsample(rgb, a), upsample(rgb, b), upsample(rgb, c))
+    }
+
+    /// The RGB of light with `values` at each wavelength
+    pub fn to_rgb(self, values: Vec3) -> Color {
+        let [a, b, c] = self.lambdas;
+        (values.x() * wavelength_weight(a)
+            + values.y() * wavelength_weight(b)
+            + values.z() * wavelength_weight(c))
+            / WAVELENGTHS as f32
+    }
+}
+
+/// The value at `lambda` of one of Smits' spectra, between the middles of its bins,
+/// and held at the first and last bins' values beyond them
+fn smits(spectrum: &[f32; 10], lambda: f32) -> f32 {
+    let x = ((lambda - 380.0) / 34.0 - 0.5).clamp(0.0, 9.0);
+    let i = (x as usize).min(8);
+    let f = x - i as f32;
+    spectrum[i] * (1.0 - f) + spectrum[i + 1] * f
+}
+
+/// The value at `lambda` of a smooth spectrum that looks like `rgb`: the smallest
+/// channel as white, the gap to the middle one as the secondary color sharing the two
+/// largest, and the rest as the largest channel's primary
+pub fn upsample(rgb: Color, lambda: f32) -> f32 {
+    let (r, g, b) = (rgb.x(), rgb.y(), rgb.z());
+    let s = |spectrum: &[f32; 10]| smits(spectrum, lambda);
+    match (r <= g && r <= b, g <= r && g <= b) {
+        (true, _) if g <= b => r * s(&WHITE) + (g - r) * s(&CYAN) + (b - g) * s(&BLUE),
+        (true, _) => r * s(&WHITE) + (b - r) * s(&CYAN) + (g - b) * s(&GREEN),
+        (_, true) if r <= b => g * s(&WHITE) + (r - g) * s(&MAGENTA) + (b - r) * s(&BLUE),
+        (_, true) => g * s(&WHITE) + (b - g) * s(&MAGENTA) + (r - b) * s(&RED),
+        _ if r <= g => b * s(&WHITE) + (r - b) * s(&YELLOW) + (g - r) * s(&GREEN),
+        _ => b * s(&WHITE) + (g - b) * s(&YELLOW) + (r - g) * s(&RED),
+    }
+}
+
+/// A wavelength drawn uniformly from `u` in 0..1
+pub fn sample_wavelength(u: f32) -> f32 {
+    LAMBDA_MIN + u * (LAMBDA_MAX - LAMBDA_MIN)
+}
+
+/// The CIE 1931 colour matching functions at `lambda`, by the multi-lobe fit of Wyman,
+/// Sloan and Shirley (2013)
+pub fn xyz(lambda: f32) -> Vec3 {
+    let g = |mu: f32, below: f32, above: f32| {
+        let sigma = if lambda < mu { below } else { above };
+        (-0.5 * ((lambda - mu) / sigma).powi(2)).exp()
+    };
+    Vec3::new(
+        1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2),
+        0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1),
+        1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8),
+    )
+}
+
+/// Linear sRGB from CIE XYZ
+pub fn xyz_to_rgb(xyz: Vec3) -> Color {
+    Color::new(
+        3.240_454 * xyz.x() - 1.537_139 * xyz.y() - 0.498_531 * xyz.z(),
+        -0.969_266 * xyz.x() + 1.876_011 * xyz.y() + 0.041_556 * xyz.z(),
+        0.055_643 * xyz.x() - 0.204_026 * xyz.y() + 1.057_225 * xyz.z(),
+    )
+}
+
+/// What a path that carries only `lambda`, drawn by `sample_wavelength`, adds to each
+/// channel. It averages to white over all wavelengths, and is negative where a pure
+/// wavelength is more saturated than sRGB can show.
+pub fn wavelength_weight(lambda: f32) -> Color {
+    let rgb = xyz_to_rgb(xyz(lambda)) * (LAMBDA_MAX - LAMBDA_MIN);
+    Color::new(
+        rgb.x() / EQUAL_ENERGY.x(),
+        rgb.y() / EQUAL_ENERGY.y(),
+        rgb.z() / EQUAL_ENERGY.z(),
+    )
+}
+
+#[test]
+fn test_wavelength_weight() {
+    let n = 4000;
+    let mean = (0..n)
+        .map(|i| wavelength_weight(sample_wavelength((i as f32 + 0.5) / n as f32)))
+        .fold(Color::new_dfl(), |acc, w| acc + w / n as f32);
+    assert!((mean - Color::new_singleton(1.0)).length() < 1e-3);
+    // Long wavelengths are red and short ones blue
+    let red = wavelength_weight(650.0);
+    assert!(red.x() > red.y() && red.x() > red.z());
+    let blue = wavelength_weight(450.0);
+    assert!(blue.z() > blue.x() && blue.z() > blue.y());
+}
+
+#[test]
+fn test_wavelengths_sample() {
+    // The others follow the hero a third of the range apart, wrapping back to the start
+    let step = (LAMBDA_MAX - LAMBDA_MIN) / 3.0;
+    let w = Wavelengths::sample(0.1);
+    assert!((w.hero() - 420.0).abs() < 1e-3);
+    let expected = [420.0, 420.0 + step, 420.0 + 2.0 * step];
+    assert!(w
+        .lambdas
+        .iter()
+        .zip(expected.iter())
+        .all(|(a, b)| (a - b).abs() < 1e-3));
+    let w = Wavelengths::sample(0.9);
+    assert!((w.hero() - 740.0).abs() < 1e-3);
+    let expected = [740.0, 740.0 + step - 400.0, 740.0 + 2.0 * step - 400.0];
+    assert!(w
+        .lambdas
+        .iter()
+        .zip(expected.iter())
+        .all(|(a, b)| (a - b).abs() < 1e-3));
+    for u in [0.0, 0.3, 0.5, 0.999_999].iter() {
+        let w = Wavelengths::sample(*u);
+        assert!(w
+            .lambdas
+            .iter()
+            .all(|l| (LAMBDA_MIN..LAMBDA_MAX).contains(l)));
+    }
+}
+
+#[test]
+fn test_upsample_flat() {
+    // White and greys have the same value at every wavelength, past 720 nm too
+    for grey in [1.0, 0.5, 0.18, 0.0].iter() {
+        let rgb = Color::new_singleton(*grey);
+        for i in 0..=40 {
+            let lambda = LAMBDA_MIN + i as f32 * 10.0;
+            assert!((upsample(rgb, lambda) - grey).abs() < 1e-3 * grey.max(1.0));
+        }
+    }
+}
+
+#[test]
+fn test_upsample_round_trip() {
+    // Colors come back from their spectra, to within what Smits' method misses
+    let n = 4000;
+    for rgb in [
+        Color::new_singleton(1.0),
+        Color::new(0.65, 0.05, 0.05),
+        Color::new(0.12, 0.45, 0.15),
+        Color::new(0.2, 0.3, 0.8),
+        Color::new(0.9, 0.8, 0.1),
+        Color::new(4.0, 2.0, 1.0),
+    ]
+    .iter()
+    {
+        let back = (0..n)
+            .map(|i| Wavelengths::sample((i as f32 + 0.5) / n as f32))
+            .map(|w| w.to_rgb(w.upsample(*rgb)))
+            .fold(Color::new_dfl(), |acc, c| acc + c / n as f32);
+        assert!((back - *rgb).length() < 0.03 * rgb.length());
+    }
+}
@@ -0,0 +1,151 @@
+//! STL triangle soups from CAD tools, in ASCII or binary.
+//!
+//! STL stores every triangle with its own three corners, so corners at exactly the same
+//! position are merged into one vertex. Facet normals are ignored in favour of the
+//! winding, which CAD tools keep consistent.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::material::MaterialType;
+use crate::mesh::Mesh;
+use crate::vec3::Point;
+
+fn invalid<S: Into<String>>(message: S) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// Corners of each triangle in a binary file: an 80 byte header, a triangle count, then
+/// per triangle a normal, three corners and two attribute bytes
+fn binary_triangles(bytes: &[u8]) -> Vec<[Point; 3]> {
+    bytes[84..]
+        .chunks_exact(50)
+        .map(|record| {
+            let f = |i: usize| {
+                let at = 12 + 4 * i;
+                f32::from_le_bytes([record[at], record[at + 1], record[at + 2], record[at + 3]])
+            };
+            let corner = |k: usize| Point::new(f(3 * k), f(3 * k + 1), f(3 * k + 2));
+            [corner(0), corner(1), corner(2)]
+        })
+        .collect()
+}
+
+/// Corners of each facet in an ASCII file, splitting polygonal facets into fans
+fn ascii_triangles(text: &str) -> io::Result<Vec<[Point; 3]>> {
+    let mut triangles = Vec::new();
+    let mut facet: Option<Vec<Point>> = None;
+    let mut words = text.split_ascii_whitespace();
+    while let Some(word) = words.next() {
+        match (word, facet.as_mut()) {
+            ("facet", None) => facet = Some(Vec::new()),
+            ("vertex", Some(corners)) => {
+                let mut coordinate = || -> io::Result<f32> {
+                    let word = words.next().ok_or_else(|| invalid("the file ends early"))?;
+                    match word.parse::<f32>() {
+                        Ok(x) if x.is_finite() => Ok(x),
+                        _ => Err(invalid(format!("expected a number, found '{}'", word))),
+                    }
+                };
+                corners.push(Point::new(coordinate()?, coordinate()?, coordinate()?));
+            }
+            ("endfacet", Some(corners)) => {
+                if corners.len() < 3 {
+                    return Err(invalid("a facet has fewer than 3 vertices"));
+                }
+                for k in 1..corners.len() - 1 {
+                    triangles.push([corners[0], corners[k], corners[k + 1]]);
+                }
+                facet = None;
+            }
+            ("facet", Some(_)) | ("vertex", None) | ("endfacet", None) => {
+                return Err(invalid(format!("unexpected '{}'", word)))
+            }
+            // The solid's name, facet normals, loops and the like carry nothing we need
+            _ => {}
+        }
+    }
+    match facet {
+        Some(_) => Err(invalid("the last facet is never closed with 'endfacet'")),
+        None => Ok(triangles),
+    }
+}
+
+/// Read a mesh from the whole of a file
+pub fn read_stl(bytes: &[u8], material: &MaterialType) -> io::Result<Mesh> {
+    // Binary files may also start with "solid", so trust the size the count implies
+    let binary = bytes.len() >= 84 && {
+        let count = u32::from_le_bytes([bytes[80], bytes[81], bytes[82], bytes[83]]);
+        bytes.len() as u64 == 84 + 50 * count as u64
+    };
+    let soup = match (binary, std::str::from_utf8(bytes)) {
+        (true, _) => binary_triangles(bytes),
+        (false, Ok(text)) if text.trim_start().starts_with("solid") => ascii_triangles(text)?,
+        _ => return Err(invalid("not an STL file")),
+    };
+
+    let mut positions = Vec::new();
+    let mut index = HashMap::new();
+    let triangles = soup
+        .iter()
+        .map(|corners| {
+            let mut vertex = |p: Point| {
+                let key = [p.x().to_bits(), p.y().to_bits(), p.z().to_bits()];
+                *index.entry(key).or_insert_with(|| {
+                    positions.push(p);
+                    positions.len() - 1
+                })
+            };
+            [vertex(corners[0]), vertex(corners[1]), vertex(corners[2])]
+        })
+        .collect();
+    Mesh::new(
+        positions,
+        Vec::new(),
+        Vec::new(),
+        Vec::new(),
+        triangles,
+        material.clone(),
+    )
+    .map_err(invalid)
+}
+
+pub fn load_stl<P: AsRef<Path>>(path: P, material: &MaterialType) -> io::Result<Mesh> {
+    read_stl(&fs::read(path)?, material)
+}
+
+#[test]
+fn test_read_stl() {
+    use crate::vec3::Vec3;
+
+    let material = MaterialType::Lambertian(Vec3::new_singleton(0.5).into());
+    let ascii = "solid square\n\
+                 facet normal 0 0 1\n outer loop\n  vertex 0 0 0\n  vertex 1 0 0\n  vertex 1 1 0\n endloop\nendfacet\n\
+                 facet normal 0 0 1\n outer loop\n  vertex 0 0 0\n  vertex 1 1 0\n  vertex 0 1 0\n endloop\nendfacet\n\
+                 endsolid square\n";
+    let mesh = read_stl(ascii.as_bytes(), &material).unwrap();
+    assert_eq!(mesh.positions().len(), 4);
+    assert_eq!(mesh.triangles(), &[[0, 1, 2], [0, 2, 3]]);
+
+    // The same square in binary, with a header that looks like ASCII
+    let mut binary = b"solid but really binary".to_vec();
+    binary.resize(80, 0);
+    binary.extend(&2u32.to_le_bytes());
+    for corners in [
+        [0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 1.0, 0.0],
+        [0.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0.0, 1.0, 0.0],
+    ]
+    .iter()
+    {
+        [0.0f32, 0.0, 1.0]
+            .iter()
+            .chain(corners.iter())
+            .for_each(|x| binary.extend(&x.to_le_bytes()));
+        binary.extend(&[0, 0]);
+    }
+    assert_eq!(read_stl(&binary, &material).unwrap(), mesh);
+
+    assert!(read_stl(b"solid x\nfacet\nvertex 0 0 0\n", &material).is_err());
+}
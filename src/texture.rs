@@ -0,0 +1,197 @@
+//! Colors that vary over a surface, looked up by the hit's texture coordinates and point.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::image::{image_source, load_hdr_image, load_ldr_image, ImageFormat};
+use crate::perlin::Perlin;
+use crate::tonemap::Transfer;
+use crate::vec3::{Color, Point};
+
+/// Octaves summed for turbulence and marble
+const TURBULENCE_DEPTH: usize = 7;
+
+pub trait Texture {
+    fn value(&self, u: f32, v: f32, p: Point) -> Color;
+}
+
+/// How a noise texture turns Perlin noise into a brightness
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NoiseKind {
+    /// Plain noise, soft blotches
+    Noise,
+    /// Summed octaves of noise, like smoke or camouflage
+    Turbulence,
+    /// Stripes along z, bent by turbulence
+    Marble,
+}
+
+impl NoiseKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            NoiseKind::Noise => "noise",
+            NoiseKind::Turbulence => "turbulence",
+            NoiseKind::Marble => "marble",
+        }
+    }
+}
+
+impl FromStr for NoiseKind {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "noise" => Ok(NoiseKind::Noise),
+            "turbulence" => Ok(NoiseKind::Turbulence),
+            "marble" => Ok(NoiseKind::Marble),
+            _ => Err("expected noise, turbulence or marble".to_string()),
+        }
+    }
+}
+
+/// A picture wrapped over the surface by its texture coordinates, in linear color
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImageTexture {
+    path: PathBuf,
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+}
+
+impl ImageTexture {
+    /// Rows of `pixels` run from the top of the picture down
+    pub fn new(path: PathBuf, width: usize, height: usize, pixels: Vec<Color>) -> Self {
+        assert_eq!(pixels.len(), width * height);
+        Self {
+            path,
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// Load a `.png` or `.ppm`, taken to be sRGB encoded, or a linear `.hdr` or `.pfm`
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let format = ImageFormat::from_path(path)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let absolute = image_source(path)?;
+        match format {
+            ImageFormat::Hdr | ImageFormat::Pfm => {
+                let fb = load_hdr_image(path)?;
+                let pixels = fb.pixels().collect();
+                Ok(Self::new(absolute, fb.width(), fb.height(), pixels))
+            }
+            _ => {
+                let image = load_ldr_image(path)?;
+                let scale = image.maxval as f32;
+                let decode = |x: u16| Transfer::Srgb.decode(x as f32 / scale);
+                let pixels = image
+                    .data
+                    .iter()
+                    .map(|[r, g, b]| Color::new(decode(*r), decode(*g), decode(*b)))
+                    .collect();
+                Ok(Self::new(absolute, image.width, image.height, pixels))
+            }
+        }
+    }
+
+    /// Get the image texture's path.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TextureType {
+    Solid(Color),
+    /// Cubes of side `scale` alternating between two textures through all of space
+    Checker(Box<TextureType>, Box<TextureType>, f32),
+    /// Perlin noise of the given kind at frequency `scale`, tinting a color
+    Noise(NoiseKind, f32, Color, Arc<Perlin>),
+    /// Repeats outside texture coordinates of 0 to 1
+    Image(Arc<ImageTexture>),
+}
+
+impl From<Color> for TextureType {
+    fn from(color: Color) -> Self {
+        TextureType::Solid(color)
+    }
+}
+
+impl Texture for TextureType {
+    fn value(&self, u: f32, v: f32, p: Point) -> Color {
+        match self {
+            TextureType::Solid(color) => *color,
+            TextureType::Checker(even, odd, scale) => {
+                let cell = |x: f32| (x / *scale).floor() as i64;
+                match (cell(p.x()) + cell(p.y()) + cell(p.z())).rem_euclid(2) {
+                    0 => even.value(u, v, p),
+                    _ => odd.value(u, v, p),
+                }
+            }
+            TextureType::Noise(kind, scale, color, perlin) => {
+                let brightness = match kind {
+                    NoiseKind::Noise => 0.5 * (1.0 + perlin.noise(*scale * p)),
+                    NoiseKind::Turbulence => perlin.turbulence(*scale * p, TURBULENCE_DEPTH),
+                    NoiseKind::Marble => {
+                        let turbulence = perlin.turbulence(p, TURBULENCE_DEPTH);
+                        0.5 * (1.0 + (*scale * p.z() + 10.0 * turbulence).sin())
+                    }
+                };
+                brightness * *color
+            }
+            TextureType::Image(image) => {
+                // v runs up the picture, and rows down it
+                let (u, v) = (u - u.floor(), v - v.floor());
+                let i = ((u * image.width as f32) as usize).min(image.width - 1);
+                let j = (((1.0 - v) * image.height as f32) as usize).min(image.height - 1);
+                image.pixels[j * image.width + i]
+            }
+        }
+    }
+}
+
+#[test]
+fn test_texture() {
+    let p = Point::new(0.5, 0.5, 0.5);
+    let white = Color::new_singleton(1.0);
+    assert_eq!(TextureType::from(white).value(0.3, 0.7, p), white);
+
+    let checker = TextureType::Checker(
+        Box::new(white.into()),
+        Box::new(Color::new_dfl().into()),
+        2.0,
+    );
+    assert_eq!(checker.value(0.0, 0.0, p), white);
+    assert_eq!(
+        checker.value(0.0, 0.0, Point::new(2.5, 0.5, 0.5)),
+        Color::new_dfl()
+    );
+    assert_eq!(
+        checker.value(0.0, 0.0, Point::new(-0.5, 0.5, 0.5)),
+        Color::new_dfl()
+    );
+    assert_eq!(checker.value(0.0, 0.0, Point::new(-0.5, -0.5, 0.5)), white);
+
+    let marble = TextureType::Noise(NoiseKind::Marble, 4.0, white, Arc::new(Perlin::new(1)));
+    let c = marble.value(0.0, 0.0, p);
+    assert!(c.x() >= 0.0 && c.x() <= 1.0);
+
+    // A 2x2 picture, red and green along the top row
+    let red = Color::new(1.0, 0.0, 0.0);
+    let green = Color::new(0.0, 1.0, 0.0);
+    let image = TextureType::Image(Arc::new(ImageTexture::new(
+        PathBuf::new(),
+        2,
+        2,
+        vec![red, green, white, Color::new_dfl()],
+    )));
+    assert_eq!(image.value(0.25, 0.75, p), red);
+    assert_eq!(image.value(0.75, 0.75, p), green);
+    assert_eq!(image.value(0.25, 0.25, p), white);
+    assert_eq!(image.value(1.25, 1.75, p), red);
+    // The far edges wrap around to the near ones
+    assert_eq!(image.value(1.0, 1.0, p), white);
+}
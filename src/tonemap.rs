@@ -0,0 +1,184 @@
+//! Turning linear radiance into display values in [0, 1].
+
+use std::str::FromStr;
+
+use crate::vec3::{luminance, Color};
+
+/// How radiance above 1 is squeezed into the displayable range
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ToneOperator {
+    /// Cut off everything brighter than white, the original behaviour
+    Clamp,
+    /// `L / (1 + L)` on luminance, never quite reaching white
+    Reinhard,
+    /// Reinhard with luminance `white_point` and above mapping to white
+    ReinhardExtended,
+    /// Narkowicz's curve fit of the ACES filmic reference transform
+    Aces,
+    /// John Hable's filmic curve from Uncharted 2
+    Hable,
+}
+
+impl FromStr for ToneOperator {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "clamp" => Ok(ToneOperator::Clamp),
+            "reinhard" => Ok(ToneOperator::Reinhard),
+            "reinhard-extended" => Ok(ToneOperator::ReinhardExtended),
+            "aces" => Ok(ToneOperator::Aces),
+            "hable" | "uncharted" => Ok(ToneOperator::Hable),
+            _ => Err("expected one of clamp, reinhard, reinhard-extended, aces, hable".to_string()),
+        }
+    }
+}
+
+/// The curve from display values to the numbers stored in the file
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Transfer {
+    Srgb,
+    /// A plain power curve; 2 reproduces the book's `sqrt`
+    Gamma(f32),
+}
+
+impl FromStr for Transfer {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "srgb" => Ok(Transfer::Srgb),
+            _ => match s.parse::<f32>() {
+                Ok(gamma) if gamma > 0.0 && gamma.is_finite() => Ok(Transfer::Gamma(gamma)),
+                _ => Err("expected srgb or a positive number".to_string()),
+            },
+        }
+    }
+}
+
+impl Transfer {
+    pub fn encode(self, x: f32) -> f32 {
+        match self {
+            Transfer::Srgb if x <= 0.003_130_8 => 12.92 * x,
+            Transfer::Srgb => 1.055 * x.powf(1.0 / 2.4) - 0.055,
+            Transfer::Gamma(gamma) => x.max(0.0).powf(1.0 / gamma),
+        }
+    }
+
+    /// The inverse of `encode`, from stored numbers back to linear display values
+    pub fn decode(self, x: f32) -> f32 {
+        match self {
+            Transfer::Srgb if x <= 0.040_45 => x / 12.92,
+            Transfer::Srgb => ((x + 0.055) / 1.055).powf(2.4),
+            Transfer::Gamma(gamma) => x.max(0.0).powf(gamma),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ToneMapping {
+    pub operator: ToneOperator,
+    /// Brightness adjustment in stops, applied before the operator
+    pub exposure: f32,
+    /// Luminance that maps to white for `ReinhardExtended`
+    pub white_point: f32,
+    pub transfer: Transfer,
+}
+
+impl Default for ToneMapping {
+    fn default() -> Self {
+        Self {
+            operator: ToneOperator::Clamp,
+            exposure: 0.0,
+            white_point: 4.0,
+            transfer: Transfer::Srgb,
+        }
+    }
+}
+
+/// Scale a color so its luminance becomes `f(luminance)`, keeping its hue
+fn map_luminance<F: Fn(f32) -> f32>(c: Color, f: F) -> Color {
+    match luminance(c) {
+        l if l <= 0.0 => Color::new_dfl(),
+        l => c * (f(l) / l),
+    }
+}
+
+fn per_channel<F: Fn(f32) -> f32>(c: Color, f: F) -> Color {
+    Color::new(f(c.x()), f(c.y()), f(c.z()))
+}
+
+fn aces(x: f32) -> f32 {
+    let (a, b, c, d, e) = (2.51, 0.03, 2.43, 0.59, 0.14);
+    (x * (a * x + b)) / (x * (c * x + d) + e)
+}
+
+fn hable_partial(x: f32) -> f32 {
+    let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+    ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f
+}
+
+fn hable(x: f32) -> f32 {
+    const EXPOSURE_BIAS: f32 = 2.0;
+    const WHITE: f32 = 11.2;
+    hable_partial(x * EXPOSURE_BIAS) / hable_partial(WHITE)
+}
+
+impl ToneMapping {
+    /// Map linear radiance to encoded display values, each in [0, 1]
+    pub fn apply(&self, c: Color) -> Color {
+        let c = per_channel(c, |x| x.max(0.0)) * 2_f32.powf(self.exposure);
+        let white_sq = self.white_point * self.white_point;
+        let mapped = match self.operator {
+            ToneOperator::Clamp => c,
+            ToneOperator::Reinhard => map_luminance(c, |l| l / (1.0 + l)),
+            ToneOperator::ReinhardExtended => {
+                map_luminance(c, |l| l * (1.0 + l / white_sq) / (1.0 + l))
+            }
+            ToneOperator::Aces => per_channel(c, aces),
+            ToneOperator::Hable => per_channel(c, hable),
+        };
+        per_channel(mapped, |x| self.transfer.encode(x.clamp(0.0, 1.0)))
+    }
+}
+
+#[test]
+fn test_tone_mapping() {
+    let srgb = Transfer::Srgb;
+    assert_eq!(srgb.encode(0.0), 0.0);
+    assert!((srgb.encode(1.0) - 1.0).abs() < 1e-6);
+    assert!((srgb.encode(0.18) - 0.461).abs() < 1e-3);
+    assert!((srgb.decode(srgb.encode(0.18)) - 0.18).abs() < 1e-6);
+
+    let gamma2 = ToneMapping {
+        transfer: Transfer::Gamma(2.0),
+        ..ToneMapping::default()
+    };
+    assert_eq!(
+        gamma2.apply(Color::new(0.25, 4.0, -1.0)),
+        Color::new(0.5, 1.0, 0.0)
+    );
+
+    for operator in [
+        ToneOperator::Reinhard,
+        ToneOperator::ReinhardExtended,
+        ToneOperator::Aces,
+        ToneOperator::Hable,
+    ]
+    .iter()
+    {
+        let tm = ToneMapping {
+            operator: *operator,
+            ..ToneMapping::default()
+        };
+        let levels = [0.01, 0.1, 1.0, 10.0, 100.0]
+            .iter()
+            .map(|&x| tm.apply(Color::new_singleton(x)).y())
+            .collect::<Vec<_>>();
+        assert!(levels.windows(2).all(|w| w[0] <= w[1]), "{:?}", operator);
+        assert!(levels[4] <= 1.0);
+    }
+    let extended = ToneMapping {
+        operator: ToneOperator::ReinhardExtended,
+        ..ToneMapping::default()
+    };
+    assert!((extended.apply(Color::new_singleton(4.0)).y() - 1.0).abs() < 1e-5);
+}
@@ -1,4 +1,4 @@
-use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub, SubAssign};
+use std::ops::{Add, AddAssign, Div, DivAssign, Index, Mul, MulAssign, Neg, Sub, SubAssign};
 
 use rand::Rng;
 
@@ -14,6 +14,11 @@ pub struct Vec3 {
 pub type Color = Vec3;
 pub type Point = Vec3;
 
+/// Brightness as the eye sees it, from linear Rec. 709 primaries
+pub fn luminance(c: Color) -> f32 {
+    c.dot(Color::new(0.2126, 0.7152, 0.0722))
+}
+
 impl Vec3 {
     pub const fn new(x: f32, y: f32, z: f32) -> Self {
         Self { x, y, z }
@@ -75,9 +80,50 @@ impl Vec3 {
     pub fn refract(self, n: Vec3, etai_over_etat: f32) -> Vec3 {
         let cos_theta = n.dot(-self).min(1.0);
         let r_out_perp = etai_over_etat * (self + cos_theta * n);
-        let r_out_parallel = (1.0 - r_out_perp.length_squared()).abs().sqrt() * -1.0 * n;
+        let r_out_parallel = -(1.0 - r_out_perp.length_squared()).abs().sqrt() * n;
         r_out_parallel + r_out_perp
     }
+    pub fn min(self, other: Vec3) -> Vec3 {
+        Vec3::new(
+            self.x().min(other.x()),
+            self.y().min(other.y()),
+            self.z().min(other.z()),
+        )
+    }
+    pub fn max(self, other: Vec3) -> Vec3 {
+        Vec3::new(
+            self.x().max(other.x()),
+            self.y().max(other.y()),
+            self.z().max(other.z()),
+        )
+    }
+    /// Two unit vectors at right angles to this unit vector and each other (Duff et al.
+    /// 2017)
+    pub fn basis(self) -> (Vec3, Vec3) {
+        let sign = 1.0_f32.copysign(self.z());
+        let a = -1.0 / (sign + self.z());
+        let b = self.x() * self.y() * a;
+        (
+            Vec3::new(
+                1.0 + sign * self.x() * self.x() * a,
+                sign * b,
+                -sign * self.x(),
+            ),
+            Vec3::new(b, sign + self.y() * self.y() * a, -self.y()),
+        )
+    }
+}
+
+impl Index<usize> for Vec3 {
+    type Output = f32;
+    fn index(&self, axis: usize) -> &f32 {
+        match axis {
+            0 => &self.x,
+            1 => &self.y,
+            2 => &self.z,
+            _ => panic!("Vec3 axis {} out of range", axis),
+        }
+    }
 }
 
 impl Neg for Vec3 {
@@ -201,6 +247,116 @@ impl Vec3 {
     }
 }
 
+/// An affine transform, as a 4x4 matrix acting on column vectors, kept with its inverse
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Transform {
+    m: [[f32; 4]; 4],
+    inv: [[f32; 4]; 4],
+}
+
+impl Transform {
+    pub fn identity() -> Self {
+        let mut m = [[0.0; 4]; 4];
+        (0..4).for_each(|i| m[i][i] = 1.0);
+        Self { m, inv: m }
+    }
+
+    /// The transform with matrix `m`, whose bottom row must be 0, 0, 0, 1 and whose
+    /// upper 3x3 part must be invertible.
+    pub fn from_matrix(m: [[f32; 4]; 4]) -> Result<Self, String> {
+        if m[3] != [0.0, 0.0, 0.0, 1.0] {
+            return Err("the bottom row must be 0,0,0,1 for an affine transform".to_string());
+        }
+        // Invert the linear part by cofactors in double precision, then the translation
+        let a = |r: usize, c: usize| m[r][c] as f64;
+        let cofactor = |r: usize, c: usize| {
+            let (r1, r2) = ((r + 1) % 3, (r + 2) % 3);
+            let (c1, c2) = ((c + 1) % 3, (c + 2) % 3);
+            a(r1, c1) * a(r2, c2) - a(r1, c2) * a(r2, c1)
+        };
+        let det = (0..3).map(|c| a(0, c) * cofactor(0, c)).sum::<f64>();
+        if det == 0.0 || !det.is_finite() {
+            return Err("the matrix cannot be inverted".to_string());
+        }
+        let mut inv = [[0.0; 4]; 4];
+        for (r, row) in inv.iter_mut().take(3).enumerate() {
+            for (c, x) in row.iter_mut().take(3).enumerate() {
+                *x = (cofactor(c, r) / det) as f32;
+            }
+            let t = (0..3).map(|c| cofactor(c, r) / det * a(c, 3)).sum::<f64>();
+            row[3] = -t as f32;
+        }
+        inv[3][3] = 1.0;
+        Ok(Self { m, inv })
+    }
+
+    pub fn translate(offset: Vec3) -> Self {
+        let mut m = Self::identity().m;
+        (0..3).for_each(|i| m[i][3] = offset[i]);
+        Self::from_matrix(m).unwrap()
+    }
+
+    /// Scale about the origin. Every factor must be nonzero.
+    pub fn scale(factors: Vec3) -> Result<Self, String> {
+        let mut m = Self::identity().m;
+        (0..3).for_each(|i| m[i][i] = factors[i]);
+        Self::from_matrix(m).map_err(|_| "scale factors must not be 0".to_string())
+    }
+
+    /// Rotate counterclockwise about `axis` (0 for x, 1 for y, 2 for z), looking down it
+    pub fn rotate(axis: usize, degrees: f32) -> Self {
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        let (i, j) = ((axis + 1) % 3, (axis + 2) % 3);
+        let mut m = Self::identity().m;
+        m[i][i] = cos;
+        m[i][j] = -sin;
+        m[j][i] = sin;
+        m[j][j] = cos;
+        Self::from_matrix(m).unwrap()
+    }
+
+    /// This transform followed by `next`
+    pub fn then(&self, next: &Transform) -> Self {
+        let mut m = [[0.0; 4]; 4];
+        for (r, row) in m.iter_mut().enumerate() {
+            for (c, x) in row.iter_mut().enumerate() {
+                *x = (0..4).map(|k| next.m[r][k] * self.m[k][c]).sum();
+            }
+        }
+        // The bottom row stays exact, so this only fails if one side was singular
+        Self::from_matrix(m).unwrap()
+    }
+
+    pub fn inverse(&self) -> Self {
+        Self {
+            m: self.inv,
+            inv: self.m,
+        }
+    }
+
+    /// Get the transform's matrix.
+    pub fn matrix(&self) -> [[f32; 4]; 4] {
+        self.m
+    }
+
+    pub fn point(&self, p: Point) -> Point {
+        self.vector(p) + Vec3::new(self.m[0][3], self.m[1][3], self.m[2][3])
+    }
+
+    pub fn vector(&self, v: Vec3) -> Vec3 {
+        let row = |r: usize| self.m[r][0] * v.x() + self.m[r][1] * v.y() + self.m[r][2] * v.z();
+        Vec3::new(row(0), row(1), row(2))
+    }
+
+    /// Normals take the inverse transpose, so they stay perpendicular to the surface. The
+    /// result is not normalized.
+    pub fn normal(&self, n: Vec3) -> Vec3 {
+        let col =
+            |c: usize| self.inv[0][c] * n.x() + self.inv[1][c] * n.y() + self.inv[2][c] * n.z();
+        Vec3::new(col(0), col(1), col(2))
+    }
+}
+
 #[test]
 fn test_linalg() {
     let vecx = Vec3::new(1.0, 0.0, 0.0);
@@ -215,3 +371,28 @@ fn test_linalg() {
     assert_eq!(0.0, vecx.dot(vecy));
     assert_eq!(1.0, vecx.dot(vecx));
 }
+
+#[test]
+fn test_transform() {
+    let close = |a: Vec3, b: Vec3| (a - b).length() < 1e-5;
+    let t = Transform::scale(Vec3::new(2.0, 1.0, 1.0))
+        .unwrap()
+        .then(&Transform::rotate(2, 90.0))
+        .then(&Transform::translate(Vec3::new(0.0, 0.0, 5.0)));
+    assert!(close(
+        t.point(Vec3::new(1.0, 0.0, 0.0)),
+        Vec3::new(0.0, 2.0, 5.0)
+    ));
+    assert!(close(
+        t.inverse().point(Vec3::new(0.0, 2.0, 5.0)),
+        Vec3::new(1.0, 0.0, 0.0)
+    ));
+    assert!(close(
+        t.vector(Vec3::new(0.0, 1.0, 0.0)),
+        Vec3::new(-1.0, 0.0, 0.0)
+    ));
+    // A normal to the plane x + y = 0 stays normal to it once stretched
+    let tangent = t.vector(Vec3::new(1.0, -1.0, 0.0));
+    assert!(t.normal(Vec3::new(1.0, 1.0, 0.0)).dot(tangent).abs() < 1e-5);
+    assert!(Transform::scale(Vec3::new(1.0, 0.0, 1.0)).is_err());
+}
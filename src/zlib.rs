@@ -0,0 +1,408 @@
+//! Just enough of zlib (RFC 1950) and deflate (RFC 1951) to read and write PNG files.
+//!
+//! Compression finds repeats with a hash chained LZ77 search and encodes them with
+//! the fixed Huffman codes, which needs no code tables in the stream. Decompression
+//! handles all three block types.
+
+use std::io;
+
+const WINDOW_SIZE: usize = 1 << 15;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const MAX_CHAIN: usize = 64;
+const HASH_BITS: u32 = 15;
+
+/// Base lengths for length codes 257..=285, and how many extra bits follow each
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+/// Base distances for distance codes 0..=29, and how many extra bits follow each
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+pub fn adler32(data: &[u8]) -> u32 {
+    const MOD: u32 = 65521;
+    // 5552 is the most bytes that can be summed before b can overflow a u32
+    let (a, b) = data.chunks(5552).fold((1_u32, 0_u32), |(a, b), chunk| {
+        let (a, b) = chunk.iter().fold((a, b), |(a, b), &byte| {
+            let a = a + byte as u32;
+            (a, b + a)
+        });
+        (a % MOD, b % MOD)
+    });
+    b << 16 | a
+}
+
+/// Packs bits least significant first, as deflate expects
+struct BitWriter {
+    out: Vec<u8>,
+    acc: u64,
+    len: u32,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        Self {
+            out: Vec::new(),
+            acc: 0,
+            len: 0,
+        }
+    }
+
+    fn write_bits(&mut self, bits: u32, count: u32) {
+        self.acc |= (bits as u64) << self.len;
+        self.len += count;
+        while self.len >= 8 {
+            self.out.push(self.acc as u8);
+            self.acc >>= 8;
+            self.len -= 8;
+        }
+    }
+
+    /// Huffman codes are defined most significant bit first, so go in backwards
+    fn write_code(&mut self, code: u32, count: u32) {
+        self.write_bits(code.reverse_bits() >> (32 - count), count)
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.len > 0 {
+            self.out.push(self.acc as u8);
+        }
+        self.out
+    }
+}
+
+fn write_literal(bits: &mut BitWriter, symbol: u16) {
+    let symbol = symbol as u32;
+    match symbol {
+        0..=143 => bits.write_code(0x30 + symbol, 8),
+        144..=255 => bits.write_code(0x190 + symbol - 144, 9),
+        256..=279 => bits.write_code(symbol - 256, 7),
+        _ => bits.write_code(0xC0 + symbol - 280, 8),
+    }
+}
+
+fn write_match(bits: &mut BitWriter, length: usize, distance: usize) {
+    let code = LENGTH_BASE
+        .iter()
+        .rposition(|&b| b as usize <= length)
+        .unwrap();
+    write_literal(bits, 257 + code as u16);
+    bits.write_bits(
+        (length - LENGTH_BASE[code] as usize) as u32,
+        LENGTH_EXTRA[code] as u32,
+    );
+    let code = DIST_BASE
+        .iter()
+        .rposition(|&b| b as usize <= distance)
+        .unwrap();
+    bits.write_code(code as u32, 5);
+    bits.write_bits(
+        (distance - DIST_BASE[code] as usize) as u32,
+        DIST_EXTRA[code] as u32,
+    );
+}
+
+fn hash(data: &[u8]) -> usize {
+    let x = (data[0] as u32) << 16 | (data[1] as u32) << 8 | data[2] as u32;
+    (x.wrapping_mul(0x9E37_79B1) >> (32 - HASH_BITS)) as usize
+}
+
+/// Compress `data` into a single fixed Huffman deflate block
+pub fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut bits = BitWriter::new();
+    // BFINAL = 1, BTYPE = 01 (fixed Huffman codes)
+    bits.write_bits(0b011, 3);
+
+    // head[h] is the latest position + 1 with hash h, prev[i % WINDOW_SIZE] the one before i
+    let mut head = vec![0_usize; 1 << HASH_BITS];
+    let mut prev = vec![0_usize; WINDOW_SIZE];
+    let insert = |i: usize, head: &mut [usize], prev: &mut [usize]| {
+        if i + MIN_MATCH <= data.len() {
+            let h = hash(&data[i..]);
+            prev[i % WINDOW_SIZE] = head[h];
+            head[h] = i + 1;
+        }
+    };
+
+    let mut i = 0;
+    while i < data.len() {
+        let mut best = (0, 0);
+        if i + MIN_MATCH <= data.len() {
+            let max_len = MAX_MATCH.min(data.len() - i);
+            let mut candidate = head[hash(&data[i..])];
+            let mut chain = 0;
+            while candidate > 0 && i - (candidate - 1) <= WINDOW_SIZE && chain < MAX_CHAIN {
+                let start = candidate - 1;
+                let len = data[start..]
+                    .iter()
+                    .zip(&data[i..i + max_len])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if len > best.0 {
+                    best = (len, i - start);
+                    if len == max_len {
+                        break;
+                    }
+                }
+                let next = prev[start % WINDOW_SIZE];
+                // Stop once the chain wraps around to positions newer than this one
+                if next >= candidate {
+                    break;
+                }
+                candidate = next;
+                chain += 1;
+            }
+        }
+        match best {
+            (len, dist) if len >= MIN_MATCH => {
+                write_match(&mut bits, len, dist);
+                (i..i + len).for_each(|j| insert(j, &mut head, &mut prev));
+                i += len;
+            }
+            _ => {
+                write_literal(&mut bits, data[i] as u16);
+                insert(i, &mut head, &mut prev);
+                i += 1;
+            }
+        }
+    }
+    write_literal(&mut bits, 256);
+    bits.finish()
+}
+
+/// Wrap a deflate stream in a zlib header and checksum
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    // CM = 8 (deflate), CINFO = 7 (32K window), FCHECK makes the header a multiple of 31
+    let mut out = vec![0x78, 0x01];
+    out.extend(deflate(data));
+    out.extend(adler32(data).to_be_bytes());
+    out
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Reads bits least significant first
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn bits(&mut self, count: u32) -> io::Result<u32> {
+        let mut value = 0;
+        for i in 0..count {
+            let byte = self
+                .data
+                .get(self.pos / 8)
+                .ok_or_else(|| invalid("deflate stream ends early"))?;
+            value |= ((byte >> (self.pos % 8)) as u32 & 1) << i;
+            self.pos += 1;
+        }
+        Ok(value)
+    }
+
+    fn align_to_byte(&mut self) {
+        self.pos = self.pos.div_ceil(8) * 8;
+    }
+}
+
+/// A canonical Huffman code, stored as how many codes there are of each length and the
+/// symbols in code order
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0; 16];
+        lengths.iter().for_each(|&l| counts[l as usize] += 1);
+        counts[0] = 0;
+        let mut symbols = Vec::with_capacity(lengths.len());
+        for len in 1..16 {
+            symbols
+                .extend((0..lengths.len() as u16).filter(|&s| lengths[s as usize] as usize == len));
+        }
+        Self { counts, symbols }
+    }
+
+    /// Read one code a bit at a time. Codes of each length are consecutive numbers
+    /// starting where the shorter ones left off, doubled.
+    fn decode(&self, bits: &mut BitReader) -> io::Result<u16> {
+        let (mut code, mut first, mut index) = (0_i32, 0_i32, 0_i32);
+        for len in 1..16 {
+            code |= bits.bits(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(invalid("bad Huffman code"))
+    }
+}
+
+fn fixed_codes() -> (Huffman, Huffman) {
+    let lengths = (0..288)
+        .map(|s| match s {
+            0..=143 => 8,
+            144..=255 => 9,
+            256..=279 => 7,
+            _ => 8,
+        })
+        .collect::<Vec<u8>>();
+    (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+}
+
+/// Read the code length code and then the literal/length and distance code lengths
+fn dynamic_codes(bits: &mut BitReader) -> io::Result<(Huffman, Huffman)> {
+    const ORDER: [usize; 19] = [
+        16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+    ];
+    let literals = bits.bits(5)? as usize + 257;
+    let distances = bits.bits(5)? as usize + 1;
+    let code_lengths = bits.bits(4)? as usize + 4;
+    let mut lengths = [0_u8; 19];
+    for &i in ORDER.iter().take(code_lengths) {
+        lengths[i] = bits.bits(3)? as u8;
+    }
+    let length_code = Huffman::new(&lengths);
+
+    let mut lengths = Vec::with_capacity(literals + distances);
+    while lengths.len() < literals + distances {
+        let (value, repeat) = match length_code.decode(bits)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => match lengths.last() {
+                Some(&last) => (last, 3 + bits.bits(2)?),
+                None => return Err(invalid("length repeat with no previous length")),
+            },
+            17 => (0, 3 + bits.bits(3)?),
+            _ => (0, 11 + bits.bits(7)?),
+        };
+        lengths.extend(std::iter::repeat(value).take(repeat as usize));
+    }
+    if lengths.len() > literals + distances {
+        return Err(invalid("code lengths overrun"));
+    }
+    Ok((
+        Huffman::new(&lengths[..literals]),
+        Huffman::new(&lengths[literals..]),
+    ))
+}
+
+/// Decompress a raw deflate stream
+pub fn inflate(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut bits = BitReader { data, pos: 0 };
+    let mut out = Vec::new();
+    loop {
+        let last = bits.bits(1)? == 1;
+        let (literal_code, distance_code) = match bits.bits(2)? {
+            0 => {
+                bits.align_to_byte();
+                let len = bits.bits(16)? as usize;
+                let nlen = bits.bits(16)? as usize;
+                if len != !nlen & 0xFFFF {
+                    return Err(invalid("stored block length check failed"));
+                }
+                let start = bits.pos / 8;
+                let block = data
+                    .get(start..start + len)
+                    .ok_or_else(|| invalid("deflate stream ends early"))?;
+                out.extend_from_slice(block);
+                bits.pos += len * 8;
+                match last {
+                    true => return Ok(out),
+                    false => continue,
+                }
+            }
+            1 => fixed_codes(),
+            2 => dynamic_codes(&mut bits)?,
+            _ => return Err(invalid("bad deflate block type")),
+        };
+        loop {
+            let symbol = literal_code.decode(&mut bits)? as usize;
+            match symbol {
+                0..=255 => out.push(symbol as u8),
+                256 => break,
+                _ => {
+                    let code = symbol - 257;
+                    if code >= LENGTH_BASE.len() {
+                        return Err(invalid("bad length code"));
+                    }
+                    let length =
+                        LENGTH_BASE[code] as usize + bits.bits(LENGTH_EXTRA[code] as u32)? as usize;
+                    let code = distance_code.decode(&mut bits)? as usize;
+                    if code >= DIST_BASE.len() {
+                        return Err(invalid("bad distance code"));
+                    }
+                    let distance =
+                        DIST_BASE[code] as usize + bits.bits(DIST_EXTRA[code] as u32)? as usize;
+                    if distance > out.len() {
+                        return Err(invalid("distance reaches before the start of the data"));
+                    }
+                    // Copy byte by byte, since the match may overlap what it produces
+                    let start = out.len() - distance;
+                    (0..length).for_each(|i| out.push(out[start + i]));
+                }
+            }
+        }
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+/// Unwrap and decompress a zlib stream, checking its checksum
+pub fn decompress(data: &[u8]) -> io::Result<Vec<u8>> {
+    match data {
+        [cmf, flg, ..] if cmf & 0x0F == 8 && (*cmf as u16 * 256 + *flg as u16) % 31 == 0 => {
+            if flg & 0x20 != 0 {
+                return Err(invalid("zlib preset dictionaries are not supported"));
+            }
+            if data.len() < 6 {
+                return Err(invalid("zlib stream ends early"));
+            }
+            let out = inflate(&data[2..data.len() - 4])?;
+            let checksum = &data[data.len() - 4..];
+            match adler32(&out).to_be_bytes() == checksum {
+                true => Ok(out),
+                false => Err(invalid("zlib checksum mismatch")),
+            }
+        }
+        _ => Err(invalid("not a zlib stream")),
+    }
+}
+
+#[test]
+fn test_adler32() {
+    assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    assert_eq!(adler32(&[]), 1);
+}
+
+#[test]
+fn test_inflate() {
+    let data = b"hello hello hello world, hello world".repeat(20);
+    assert_eq!(decompress(&compress(&data)).unwrap(), data);
+    // a single stored block, as written by compressors for incompressible data
+    let mut stored = vec![0x78, 0x01, 0x01, 0x05, 0x00, 0xFA, 0xFF];
+    stored.extend(b"hello");
+    stored.extend(adler32(b"hello").to_be_bytes());
+    assert_eq!(decompress(&stored).unwrap(), b"hello");
+    *stored.last_mut().unwrap() ^= 1;
+    assert!(decompress(&stored).is_err());
+}